@@ -1,4 +1,5 @@
-use std::process::Command;
+use eyre::eyre;
+use std::{env, fs, path::Path, process::Command};
 
 fn main() -> eyre::Result<()> {
     Command::new("ls").current_dir("./").spawn()?;
@@ -7,14 +8,51 @@ fn main() -> eyre::Result<()> {
         .args(["run", "build"])
         .spawn()?;
 
+    // the copy is read below, it has to be complete
     Command::new("cp")
         .current_dir("./glypho-web/")
         .args(["./dist/index.html", "../src/template.html"])
-        .spawn()?;
+        .status()?;
+
+    let template = fs::read_to_string("src/template.html")?;
+    let out_dir = env::var("OUT_DIR")?;
+    fs::write(
+        Path::new(&out_dir).join("template_parts.rs"),
+        template_parts(&template)?,
+    )?;
 
     println!("cargo::rerun-if-changed=build.rs");
     println!("cargo::rerun-if-changed=./glypho-web/src/*");
     println!("cargo::rerun-if-changed=./glypho-web/dist/index.html");
+    println!("cargo::rerun-if-changed=src/template.html");
 
     Ok(())
 }
+
+// Where `page.rs` cuts the compiled page into its slots. Found here, so that
+// a change to the markup fails the build instead of the first request.
+fn template_parts(template: &str) -> eyre::Result<String> {
+    let find = |from: usize, pattern: &str| {
+        template[from..]
+            .find(pattern)
+            .map(|i| from + i)
+            .ok_or_else(|| eyre!("src/template.html has no {pattern}"))
+    };
+    // the build output has no <head>, everything between the title and the
+    // body are the styles and scripts of the page
+    let head = template
+        .find("</title>")
+        .map_or(0, |i| i + "</title>".len());
+    let body = find(0, "<body")?;
+    let navbar = find(0, "<nav id=navbar")?;
+    let navbar_end = find(navbar, "</nav>")? + "</nav>".len();
+    let main = find(0, "<main")?;
+    let main_end = find(main, "</main>")? + "</main>".len();
+
+    Ok(format!(
+        "const HEAD: std::ops::Range<usize> = {head}..{body};\n\
+         const NAVBAR: std::ops::Range<usize> = {navbar}..{navbar_end};\n\
+         const ARTICLE: std::ops::Range<usize> = {main}..{main_end};\n\
+         const SCRIPTS: std::ops::RangeFrom<usize> = {main_end}..;\n"
+    ))
+}
@@ -0,0 +1,249 @@
+use clap::Parser;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::process::exit;
+use std::sync::Arc;
+use std::{env, path::PathBuf};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::{
+    auth::AccessToken,
+    cli::{Args, Command, ConfigAction},
+    config::{Config, Overrides, command_words},
+    control::{self, ControlRequest},
+    error::GlyphoError,
+    lint, page, print, render,
+    server::Server,
+    session::Session,
+    state::{AddFileRequest, InnerState},
+    stats,
+    tls::{self, TlsListener},
+};
+
+fn cleanup(socket: &Path) -> eyre::Result<()> {
+    if socket.exists() {
+        std::fs::remove_file(socket)?;
+    } else {
+        info!("Control socket cannot be removed");
+    }
+
+    Ok(())
+}
+
+async fn check_uniqueness(socket: &Path, file_to_add: PathBuf) -> eyre::Result<()> {
+    if socket.exists() {
+        //do client mode
+        let request = ControlRequest::Add(AddFileRequest { file: file_to_add });
+
+        // a socket nobody answers on is left over by a crashed instance
+        if control::send(socket, &request).await.is_ok() {
+            exit(0)
+        }
+    };
+
+    Ok(())
+}
+
+/// The `glypho` program: runs the command given on the command line, or
+/// previews the file in its session.
+#[doc(hidden)]
+pub async fn run() -> eyre::Result<()> {
+    let args = Args::parse();
+
+    let overrides = Overrides::from(&args);
+    let user_config = match &args.config {
+        Some(path) => Config::from_file(path)?,
+        None => Config::load()?,
+    };
+
+    if let Some(Command::Config {
+        action: ConfigAction::Show,
+    }) = args.command
+    {
+        let config = user_config
+            .for_dir(&env::current_dir()?)?
+            .with_overrides(&overrides);
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+    if let Some(Command::Lint { files }) = &args.command {
+        exit(lint::lint_files(files, &user_config, &overrides));
+    }
+    if let Some(Command::Stats { file }) = &args.command {
+        exit(stats::print_stats(file, &user_config, &overrides));
+    }
+    if let Some(Command::Render { file, standalone }) = &args.command {
+        exit(render::print_html(
+            file,
+            *standalone,
+            &user_config,
+            &overrides,
+        ));
+    }
+    if let Some(Command::Export {
+        files,
+        format,
+        output,
+    }) = &args.command
+    {
+        exit(print::export(
+            files,
+            *format,
+            output.as_deref(),
+            &user_config,
+            &overrides,
+        ));
+    }
+
+    let file = match &args.input {
+        Some(f) if f.is_file() => std::path::absolute(f.filename())?,
+        _ => return Err(GlyphoError::NotProvided.into()),
+    };
+    let config = user_config.for_file(&file, &overrides)?;
+
+    let port = config.port.unwrap_or(0);
+    if let Some(theme) = &config.theme
+        && page::theme_css(theme).is_none()
+    {
+        warn!(
+            "{theme} is not a bundled theme ({}), only your own stylesheets apply",
+            page::THEMES
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    let session = match &args.session {
+        Some(name) => Session::named(name)?,
+        None => Session::for_file(&file),
+    };
+    let socket = control::socket_path(&session)?;
+    check_uniqueness(&socket, file.clone()).await?;
+    info!("Starting Glypho session {}...", session.name());
+
+    let server = Server::new(
+        InnerState::new(file.clone())
+            .with_config(user_config)
+            .with_overrides(overrides)
+            .with_slides(args.slides),
+    );
+    let shared_state = server.state();
+    let shutdown = Arc::new(Notify::new());
+    let control_listener = control::bind(&socket)?;
+    tokio::spawn(control::serve(
+        control_listener,
+        shared_state.clone(),
+        shutdown.clone(),
+    ));
+
+    let address = if args.public {
+        IpAddr::from(Ipv4Addr::UNSPECIFIED)
+    } else {
+        config.bind
+    };
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::load_pem(cert, key)?),
+        _ if args.tls_self_signed => {
+            let mut hosts = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+            if !address.is_unspecified() && !address.is_loopback() {
+                hosts.push(address.to_string());
+            }
+            Some(tls::self_signed(hosts)?)
+        }
+        _ => None,
+    };
+    let token = match args.token {
+        Some(token) => Some(AccessToken::new(token)),
+        None if args.public => Some(AccessToken::generate()?),
+        None => None,
+    }
+    .map(|token| token.secure(tls.is_some()));
+
+    let router = match &token {
+        Some(token) => server.with_token(token.clone()).router(),
+        None => server.router(),
+    };
+
+    let listener = tokio::net::TcpListener::bind(SocketAddr::new(address, port)).await?;
+    let local_addr = listener.local_addr()?;
+
+    let file_name = file
+        .file_name()
+        .and_then(|fname| fname.to_str())
+        .unwrap_or("unknown");
+    let url = preview_url(local_addr, tls.is_some(), token.as_ref());
+    tracing::info!("Serving {} at {}", file_name, url);
+
+    if !address.is_loopback() {
+        if token.is_none() {
+            warn!("The preview is reachable from the network without an access token");
+        } else {
+            warn!(
+                "The preview is reachable from the network, others need the token in the URL above"
+            );
+        }
+    }
+
+    info!("Press Ctrl+C to stop the server");
+
+    if !args.no_browser {
+        open_browser(&url, config.browser.as_deref())?;
+    }
+
+    let server = async {
+        match tls {
+            Some(config) => axum::serve(TlsListener::new(listener, config)?, router).await,
+            None => axum::serve(listener, router).await,
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {
+            info!("Shutting down the server");
+        }
+        _ = shutdown.notified() => {
+            info!("Stop requested, shutting down the server");
+        }
+        _ = server => {}
+    }
+    cleanup(&socket)?;
+
+    Ok(())
+}
+
+fn preview_url(addr: SocketAddr, https: bool, token: Option<&AccessToken>) -> String {
+    let addr = if addr.ip().is_unspecified() {
+        SocketAddr::new(IpAddr::from(Ipv4Addr::LOCALHOST), addr.port())
+    } else {
+        addr
+    };
+    let scheme = if https { "https" } else { "http" };
+
+    match token {
+        Some(token) => format!("{scheme}://{addr}/?token={}", token.as_str()),
+        None => format!("{scheme}://{addr}"),
+    }
+}
+
+fn open_browser(url: &str, command: Option<&str>) -> std::io::Result<()> {
+    let words = command_words(command.unwrap_or_default()).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "The browser command has an unclosed quote",
+        )
+    })?;
+    let mut words = words.into_iter();
+    match words.next() {
+        Some(program) => {
+            std::process::Command::new(program)
+                .args(words)
+                .arg(url)
+                .spawn()?;
+            Ok(())
+        }
+        None => open::that_detached(url),
+    }
+}
@@ -0,0 +1,438 @@
+use axum::{
+    extract::{self, Request, State},
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use lazy_regex::*;
+use percent_encoding::percent_decode_str;
+use std::{
+    collections::BTreeSet,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+use tracing::*;
+
+use crate::{session::fnv1a, state::AppState};
+
+/// Extensions served from the documents' directories even when no document
+/// references them, so links typed in the browser keep working.
+pub const ALLOWED_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "svg", "webp", "avif", "bmp", "ico", "mp4", "webm", "mp3", "ogg",
+    "wav", "pdf",
+];
+
+/// Turns a URL path into a relative file path, rejecting anything that could
+/// leave the document directory or reveal hidden files.
+pub fn sanitize(url_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(url_path).decode_utf8().ok()?;
+    let mut path = PathBuf::new();
+
+    for component in Path::new(decoded.as_ref()).components() {
+        match component {
+            Component::RootDir | Component::CurDir => {}
+            Component::Normal(name) if !name.to_string_lossy().starts_with('.') => path.push(name),
+            _ => return None,
+        }
+    }
+
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+pub fn has_allowed_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ALLOWED_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Identifies a document in `/files/<doc-id>/` asset URLs.
+pub fn doc_id(file: &Path) -> String {
+    format!("{:016x}", fnv1a(file.as_os_str().as_encoded_bytes()))
+}
+
+fn is_local(url: &str) -> bool {
+    // skip urls with a scheme, protocol relative urls and in-page anchors
+    !url.contains("://")
+        && !url.starts_with("//")
+        && !url.starts_with('#')
+        && !url
+            .split(['/', '?', '#'])
+            .next()
+            .unwrap_or("")
+            .contains(':')
+}
+
+fn local_path(url: &str) -> Option<PathBuf> {
+    if !is_local(url) {
+        return None;
+    }
+    sanitize(url.split(['?', '#']).next().unwrap_or(url))
+}
+
+/// Local files linked from rendered `html`, relative to the document.
+pub fn referenced_assets(html: &str) -> BTreeSet<PathBuf> {
+    let re = regex!(r#"(?:src|href)\s*=\s*(?:"([^"]*)"|'([^']*)')"#);
+
+    re.captures_iter(html)
+        .filter_map(|caps| caps.get(1).or(caps.get(2)))
+        .filter_map(|url| local_path(url.as_str()))
+        .collect()
+}
+
+/// Points relative `src` and `href` attributes of `html` at the asset route of
+/// document `doc_id`, so they resolve against the document's own directory
+/// whichever document the page was opened with.
+pub fn rewrite_relative_urls(html: &str, doc_id: &str) -> String {
+    // no backreferences, each quote has its own alternative
+    let re = regex!(r#"(?P<attr>(?:src|href)\s*=\s*)(?:"(?P<double>[^"]*)"|'(?P<single>[^']*)')"#);
+
+    re.replace_all(html, |caps: &lazy_regex::Captures| {
+        let attr = &caps["attr"];
+        let (quote, url) = match caps.name("double") {
+            Some(url) => ('"', url.as_str()),
+            None => ('\'', &caps["single"]),
+        };
+        if local_path(url).is_none() {
+            return caps[0].to_string();
+        }
+
+        let relative = url.trim_start_matches("./").trim_start_matches('/');
+        format!("{attr}{quote}/files/{doc_id}/{relative}{quote}")
+    })
+    .into_owned()
+}
+
+/// Resolves `relative` inside `dir`, following symlinks only when they stay
+/// inside it.
+pub fn resolve(dir: &Path, relative: &Path) -> Option<PathBuf> {
+    let root = dir.canonicalize().ok()?;
+    let candidate = root.join(relative).canonicalize().ok()?;
+    (candidate.starts_with(&root) && candidate.is_file()).then_some(candidate)
+}
+
+/// Serves files next to the tracked documents, never hidden files and only
+/// when referenced by a document or of an allowed type.
+pub async fn serve_asset(State(state): State<Arc<AppState>>, request: Request) -> Response {
+    let Some(relative) = sanitize(request.uri().path()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let candidates = { state.lock().await.asset_dirs() };
+    serve_from(candidates, relative, request).await
+}
+
+/// Serves `/files/<doc-id>/<path>` from the directory of that document only.
+pub async fn serve_document_asset(
+    State(state): State<Arc<AppState>>,
+    extract::Path((doc, _)): extract::Path<(String, String)>,
+    request: Request,
+) -> Response {
+    // the raw path is decoded exactly once, by `sanitize`
+    let prefix = format!("/files/{doc}/");
+    let Some(relative) = request
+        .uri()
+        .path()
+        .strip_prefix(&prefix)
+        .and_then(sanitize)
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let candidates = { state.lock().await.asset_dir(&doc) };
+    serve_from(candidates.into_iter().collect(), relative, request).await
+}
+
+async fn serve_from(
+    candidates: Vec<(PathBuf, BTreeSet<PathBuf>)>,
+    relative: PathBuf,
+    request: Request,
+) -> Response {
+    for (dir, referenced) in candidates {
+        if !referenced.contains(&relative) && !has_allowed_extension(&relative) {
+            continue;
+        }
+        if let Some(path) = resolve(&dir, &relative) {
+            return match ServeFile::new(path).oneshot(request).await {
+                Ok(response) => sandboxed(response.into_response()),
+                Err(err) => {
+                    warn!("Cannot serve {}: {err}", relative.display());
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            };
+        }
+    }
+
+    debug!("Refusing to serve {}", relative.display());
+    StatusCode::NOT_FOUND.into_response()
+}
+
+// An SVG or HTML file opened on its own runs as a page of the preview's
+// origin, sandboxed its scripts cannot call the routes that write documents.
+fn sandboxed(mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("sandbox"),
+    );
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::InnerState;
+    use axum::{Router, body::Body};
+    use rstest::rstest;
+    use tempfile::TempDir;
+    use tokio::sync::Mutex;
+
+    async fn get(state: Arc<AppState>, uri: &str) -> StatusCode {
+        let app = Router::new()
+            .route(
+                "/files/{doc}/{*path}",
+                axum::routing::get(serve_document_asset),
+            )
+            .fallback(serve_asset)
+            .with_state(state);
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    fn state_with(dir: &TempDir, markdown: &str) -> Arc<AppState> {
+        let file = dir.path().join("doc.md");
+        std::fs::write(&file, markdown).unwrap();
+        let mut state = InnerState::new(file.clone());
+        state.render(&file).unwrap();
+        Arc::new(Mutex::new(state))
+    }
+
+    // ==================== Path Sanitizing Tests ====================
+
+    #[rstest]
+    #[case("/img.png", Some("img.png"))]
+    #[case("/images/a%20b.png", Some("images/a b.png"))]
+    #[case("./img.png", Some("img.png"))]
+    #[case("/../etc/passwd", None)]
+    #[case("/images/../../secret", None)]
+    #[case("/.env", None)]
+    #[case("/.git/config", None)]
+    #[case("/docs/.hidden/a.png", None)]
+    #[case("/%2e%2e/secret", None)]
+    #[case("/", None)]
+    fn test_sanitize(#[case] input: &str, #[case] expected: Option<&str>) {
+        assert_eq!(sanitize(input), expected.map(PathBuf::from));
+    }
+
+    #[rstest]
+    #[case("a.png", true)]
+    #[case("a.PNG", true)]
+    #[case("docs/manual.pdf", true)]
+    #[case("id_rsa", false)]
+    #[case("notes.md", false)]
+    #[case("config.toml", false)]
+    fn test_allowed_extension(#[case] path: &str, #[case] expected: bool) {
+        assert_eq!(has_allowed_extension(Path::new(path)), expected);
+    }
+
+    // ==================== Reference Extraction Tests ====================
+
+    #[test]
+    fn test_referenced_assets() {
+        let html = r##"<img src="images/diagram.png" alt="x" />
+<a href="notes.txt">notes</a>
+<a href='data/table.csv?raw=1#top'>csv</a>
+<a href="https://example.com/a.png">remote</a>
+<a href="//cdn.example.com/a.js">cdn</a>
+<a href="mailto:me@example.com">mail</a>
+<a href="#section">anchor</a>
+<a href="../outside.txt">outside</a>"##;
+
+        let assets = referenced_assets(html);
+        let expected: BTreeSet<PathBuf> = ["images/diagram.png", "notes.txt", "data/table.csv"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(assets, expected);
+    }
+
+    // ==================== URL Rewriting Tests ====================
+
+    #[test]
+    fn test_doc_id_is_stable_and_distinct() {
+        let a = doc_id(Path::new("/home/me/a/README.md"));
+        assert_eq!(a, doc_id(Path::new("/home/me/a/README.md")));
+        assert_ne!(a, doc_id(Path::new("/home/me/b/README.md")));
+        assert_eq!(a.len(), 16);
+    }
+
+    #[rstest]
+    #[case(r#"<img src="img.png" />"#, r#"<img src="/files/abc/img.png" />"#)]
+    #[case(
+        r#"<img src="./img/a%20b.png" />"#,
+        r#"<img src="/files/abc/img/a%20b.png" />"#
+    )]
+    #[case(
+        r#"<a href="/notes.txt#top">"#,
+        r#"<a href="/files/abc/notes.txt#top">"#
+    )]
+    #[case(r#"<img src='img.png'>"#, r#"<img src='/files/abc/img.png'>"#)]
+    #[case(r#"<img src="it's.png" />"#, r#"<img src="/files/abc/it's.png" />"#)]
+    #[case(r#"<a href='say-"hi".txt'>"#, r#"<a href='/files/abc/say-"hi".txt'>"#)]
+    #[case(
+        r#"<a href="https://example.com">"#,
+        r#"<a href="https://example.com">"#
+    )]
+    #[case(r##"<a href="#section">"##, r##"<a href="#section">"##)]
+    #[case(
+        r#"<a href="mailto:me@example.com">"#,
+        r#"<a href="mailto:me@example.com">"#
+    )]
+    #[case(r#"<a href="../up.png">"#, r#"<a href="../up.png">"#)]
+    fn test_rewrite_relative_urls(#[case] html: &str, #[case] expected: &str) {
+        assert_eq!(rewrite_relative_urls(html, "abc"), expected);
+    }
+
+    // ==================== Handler Tests ====================
+
+    #[tokio::test]
+    async fn test_serves_allowed_extension() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("logo.png"), "png").unwrap();
+        let state = state_with(&dir, "# Doc");
+
+        assert_eq!(get(state, "/logo.png").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_serves_assets_sandboxed() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("logo.svg"),
+            "<svg><script>fetch('/source')</script></svg>",
+        )
+        .unwrap();
+        let state = state_with(&dir, "# Doc");
+        let app = Router::new().fallback(serve_asset).with_state(state);
+
+        let request = Request::get("/logo.svg").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_SECURITY_POLICY],
+            "sandbox"
+        );
+        assert_eq!(
+            response.headers()[header::X_CONTENT_TYPE_OPTIONS],
+            "nosniff"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serves_referenced_file() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("data.csv"), "a,b").unwrap();
+        let state = state_with(&dir, "[data](data.csv)");
+
+        assert_eq!(get(state, "/data.csv").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_refuses_unreferenced_file() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("secrets.toml"), "key = 1").unwrap();
+        let state = state_with(&dir, "# Doc");
+
+        assert_eq!(get(state, "/secrets.toml").await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_refuses_hidden_files() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".git/logo.png"), "png").unwrap();
+        std::fs::write(dir.path().join(".env"), "KEY=1").unwrap();
+        let state = state_with(&dir, "[env](.env)");
+
+        assert_eq!(get(state.clone(), "/.env").await, StatusCode::NOT_FOUND);
+        assert_eq!(get(state, "/.git/logo.png").await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_refuses_symlink_escape() {
+        let dir = TempDir::new().unwrap();
+        let outside = TempDir::new().unwrap();
+        std::fs::write(outside.path().join("secret.png"), "png").unwrap();
+        std::os::unix::fs::symlink(
+            outside.path().join("secret.png"),
+            dir.path().join("link.png"),
+        )
+        .unwrap();
+        let state = state_with(&dir, "![x](link.png)");
+
+        assert_eq!(get(state, "/link.png").await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_serves_from_added_file_directory() {
+        let dir1 = TempDir::new().unwrap();
+        let dir2 = TempDir::new().unwrap();
+        std::fs::write(dir2.path().join("other.png"), "png").unwrap();
+        let state = state_with(&dir1, "# First");
+
+        let file2 = dir2.path().join("second.md");
+        std::fs::write(&file2, "![img](other.png)").unwrap();
+        crate::state::add_file(state.clone(), file2).await;
+
+        assert_eq!(get(state, "/other.png").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_document_route_uses_each_document_directory() {
+        let dir1 = TempDir::new().unwrap();
+        let dir2 = TempDir::new().unwrap();
+        std::fs::write(dir1.path().join("img.png"), "first").unwrap();
+        std::fs::write(dir2.path().join("notes.txt"), "second").unwrap();
+        let state = state_with(&dir1, "![img](img.png)");
+
+        let file2 = dir2.path().join("second.md");
+        std::fs::write(&file2, "[notes](notes.txt)").unwrap();
+        crate::state::add_file(state.clone(), file2.clone()).await;
+        state.lock().await.render(&file2).unwrap();
+
+        let id1 = doc_id(&dir1.path().join("doc.md"));
+        let id2 = doc_id(&file2);
+
+        let uri = format!("/files/{id2}/notes.txt");
+        assert_eq!(get(state.clone(), &uri).await, StatusCode::OK);
+        let uri = format!("/files/{id1}/img.png");
+        assert_eq!(get(state.clone(), &uri).await, StatusCode::OK);
+
+        // a document never serves from another document's directory
+        let uri = format!("/files/{id1}/notes.txt");
+        assert_eq!(get(state.clone(), &uri).await, StatusCode::NOT_FOUND);
+        let uri = format!("/files/{id2}/img.png");
+        assert_eq!(get(state, &uri).await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_document_route_rejects_unknown_document_and_hidden_files() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join(".env"), "KEY=1").unwrap();
+        let state = state_with(&dir, "# Doc");
+        let id = doc_id(&dir.path().join("doc.md"));
+
+        let uri = format!("/files/{id}/.env");
+        assert_eq!(get(state.clone(), &uri).await, StatusCode::NOT_FOUND);
+        let uri = format!("/files/{id}/%2e%2e/secret.png");
+        assert_eq!(get(state.clone(), &uri).await, StatusCode::NOT_FOUND);
+        assert_eq!(
+            get(state, "/files/0000000000000000/img.png").await,
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use axum::{
+    extract::{Query, Request, State},
+    http::{
+        HeaderValue, Method, StatusCode,
+        header::{COOKIE, HOST, ORIGIN, SET_COOKIE},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::{io::Read, net::IpAddr, sync::Arc};
+
+const COOKIE_NAME: &str = "glypho_token";
+
+/// Shared secret required on every request when the preview is reachable by
+/// other people. Browsers send it once as `?token=`, then as a cookie.
+#[derive(Clone)]
+pub struct AccessToken {
+    token: String,
+    secure: bool,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+impl AccessToken {
+    pub fn new(token: String) -> Self {
+        AccessToken {
+            token,
+            secure: false,
+        }
+    }
+
+    /// Restricts the cookie to HTTPS connections.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// A random 128 bit token, hex encoded.
+    pub fn generate() -> std::io::Result<Self> {
+        let mut bytes = [0u8; 16];
+        std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+        let token = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        Ok(AccessToken::new(token))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.token
+    }
+
+    fn matches(&self, candidate: &str) -> bool {
+        let (a, b) = (self.token.as_bytes(), candidate.as_bytes());
+        // compare in constant time so the token cannot be guessed byte by byte
+        a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+    }
+
+    fn in_cookie(&self, request: &Request) -> bool {
+        request
+            .headers()
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .any(|(name, value)| name == COOKIE_NAME && self.matches(value))
+    }
+}
+
+pub async fn require_token(
+    State(token): State<Arc<AccessToken>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if token.in_cookie(&request) {
+        return next.run(request).await;
+    }
+
+    let from_query = Query::<TokenQuery>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(q)| q.token)
+        .is_some_and(|t| token.matches(&t));
+
+    if !from_query {
+        return (StatusCode::UNAUTHORIZED, "Missing or invalid access token").into_response();
+    }
+
+    let mut response = next.run(request).await;
+    let mut cookie = format!(
+        "{COOKIE_NAME}={}; Path=/; HttpOnly; SameSite=Strict",
+        token.as_str()
+    );
+    if token.secure {
+        cookie.push_str("; Secure");
+    }
+    if let Ok(value) = HeaderValue::from_str(&cookie) {
+        response.headers_mut().append(SET_COOKIE, value);
+    }
+    response
+}
+
+/// Refuses requests that change documents or the session from pages of
+/// other sites.
+pub async fn require_same_origin(request: Request, next: Next) -> Response {
+    if foreign_write(&request) {
+        return (
+            StatusCode::FORBIDDEN,
+            "Requests from other sites are refused",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Refuses requests to a preview without access token that do not reach it
+/// by a local name or an address, as a rebound DNS name does.
+pub async fn require_local_host(request: Request, next: Next) -> Response {
+    let host = request.headers().get(HOST).and_then(|v| v.to_str().ok());
+    if host.is_some_and(|host| !is_local_host(host)) {
+        return (
+            StatusCode::FORBIDDEN,
+            "Requests from other sites are refused",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+fn foreign_write(request: &Request) -> bool {
+    let header = |name| request.headers().get(name).and_then(|v| v.to_str().ok());
+    let foreign_origin = match (header(ORIGIN), header(HOST)) {
+        (Some(origin), Some(host)) => origin
+            .split_once("://")
+            .is_none_or(|(_, authority)| authority != host),
+        // browsers send the Host with every request
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+    foreign_origin && request.method() != Method::GET
+}
+
+fn is_local_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    name == "localhost" || name.ends_with(".localhost") || name.parse::<IpAddr>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, middleware, routing::get};
+    use rstest::rstest;
+    use tower::ServiceExt;
+
+    fn app(token: &str) -> Router {
+        app_with(AccessToken::new(token.to_string()))
+    }
+
+    fn app_with(token: AccessToken) -> Router {
+        let token = Arc::new(token);
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(token, require_token))
+    }
+
+    // guarded as the preview is, the Host is only checked without token
+    fn same_origin_app(has_token: bool) -> Router {
+        let router = Router::new()
+            .route("/source", get(|| async { "ok" }).post(|| async { "saved" }))
+            .layer(middleware::from_fn(require_same_origin));
+        if has_token {
+            router
+        } else {
+            router.layer(middleware::from_fn(require_local_host))
+        }
+    }
+
+    async fn call(app: Router, request: axum::http::Request<Body>) -> Response {
+        app.oneshot(request).await.unwrap()
+    }
+
+    // ==================== Token Tests ====================
+
+    #[test]
+    fn test_generated_token_is_hex() {
+        let token = AccessToken::generate().unwrap();
+        assert_eq!(token.as_str().len(), 32);
+        assert!(token.as_str().chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn test_generated_tokens_differ() {
+        let t1 = AccessToken::generate().unwrap();
+        let t2 = AccessToken::generate().unwrap();
+        assert_ne!(t1.as_str(), t2.as_str());
+    }
+
+    #[rstest]
+    #[case("secret", true)]
+    #[case("secreT", false)]
+    #[case("secret2", false)]
+    #[case("", false)]
+    fn test_token_matches(#[case] candidate: &str, #[case] expected: bool) {
+        let token = AccessToken::new("secret".to_string());
+        assert_eq!(token.matches(candidate), expected);
+    }
+
+    // ==================== Middleware Tests ====================
+
+    #[tokio::test]
+    async fn test_request_without_token_rejected() {
+        let request = axum::http::Request::get("/").body(Body::empty()).unwrap();
+        let response = call(app("secret"), request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_query_token_sets_cookie() {
+        let request = axum::http::Request::get("/?token=secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = call(app("secret"), request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookie = response
+            .headers()
+            .get(SET_COOKIE)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(cookie.starts_with("glypho_token=secret;"));
+        assert!(cookie.contains("HttpOnly"));
+        assert!(!cookie.contains("Secure"));
+    }
+
+    #[tokio::test]
+    async fn test_secure_cookie_over_tls() {
+        let token = AccessToken::new("secret".to_string()).secure(true);
+        let request = axum::http::Request::get("/?token=secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = call(app_with(token), request).await;
+
+        let cookie = response.headers().get(SET_COOKIE).unwrap();
+        assert!(cookie.to_str().unwrap().ends_with("; Secure"));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_query_token_rejected() {
+        let request = axum::http::Request::get("/?token=guess")
+            .body(Body::empty())
+            .unwrap();
+        let response = call(app("secret"), request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_cookie_token_accepted() {
+        let request = axum::http::Request::get("/")
+            .header(COOKIE, "theme=dark; glypho_token=secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = call(app("secret"), request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(SET_COOKIE).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wrong_cookie_token_rejected() {
+        let request = axum::http::Request::get("/")
+            .header(COOKIE, "glypho_token=guess")
+            .body(Body::empty())
+            .unwrap();
+        let response = call(app("secret"), request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    // ==================== Same Origin Tests ====================
+
+    #[rstest]
+    #[case::same_origin("127.0.0.1:4000", Some("http://127.0.0.1:4000"), false, StatusCode::OK)]
+    #[case::without_origin("localhost:4000", None, false, StatusCode::OK)]
+    #[case::ipv6("[::1]:4000", Some("http://[::1]:4000"), false, StatusCode::OK)]
+    #[case::other_site(
+        "127.0.0.1:4000",
+        Some("https://evil.example"),
+        false,
+        StatusCode::FORBIDDEN
+    )]
+    #[case::opaque_origin("127.0.0.1:4000", Some("null"), true, StatusCode::FORBIDDEN)]
+    #[case::rebound_name(
+        "evil.example:4000",
+        Some("http://evil.example:4000"),
+        false,
+        StatusCode::FORBIDDEN
+    )]
+    #[case::name_with_token("notes.lan:4000", Some("http://notes.lan:4000"), true, StatusCode::OK)]
+    #[tokio::test]
+    async fn test_same_origin_writes(
+        #[case] host: &str,
+        #[case] origin: Option<&str>,
+        #[case] has_token: bool,
+        #[case] expected: StatusCode,
+    ) {
+        let mut request = axum::http::Request::post("/source").header(HOST, host);
+        if let Some(origin) = origin {
+            request = request.header(ORIGIN, origin);
+        }
+        let response = call(
+            same_origin_app(has_token),
+            request.body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_rebound_name_cannot_read() {
+        let request = axum::http::Request::get("/source")
+            .header(HOST, "evil.example:4000")
+            .body(Body::empty())
+            .unwrap();
+        let response = call(same_origin_app(false), request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
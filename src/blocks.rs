@@ -0,0 +1,271 @@
+use lazy_regex::*;
+use markdown::{CompileOptions, mdast::Node};
+
+/// Marks the first element each top level node of `tree` renders to with
+/// its index, in `html` as compiled from `tree`, before it is sanitized.
+/// The marks survive sanitizing, unlike the order of the elements when raw
+/// HTML is dropped.
+pub fn tag_blocks(html: &str, tree: &Node, compile: &CompileOptions) -> String {
+    // the node each top level element of `html` starts, raw HTML may open
+    // an element the next nodes are rendered in
+    let mut scanner = Scanner {
+        tagfilter: compile.gfm_tagfilter,
+        ..Scanner::default()
+    };
+    let mut owners = vec![];
+    for (index, node) in tree.children().into_iter().flatten().enumerate() {
+        let count = match node {
+            Node::Html(html) => scanner.scan(&html.value).len(),
+            // definitions, frontmatter and MDX leave nothing in the page
+            Node::Definition(_)
+            | Node::FootnoteDefinition(_)
+            | Node::Yaml(_)
+            | Node::Toml(_)
+            | Node::MdxjsEsm(_)
+            | Node::MdxFlowExpression(_)
+            | Node::MdxJsxFlowElement(_) => 0,
+            _ if scanner.depth > 0 => 0,
+            _ => 1,
+        };
+        owners.extend((0..count).map(|element| (element == 0).then_some(index)));
+    }
+
+    let mut html = without_block_tags(html);
+    let starts = Scanner::default().scan(&html);
+    for (start, owner) in starts.into_iter().zip(owners).rev() {
+        let Some(index) = owner else {
+            continue;
+        };
+        let name = html[start + 1..]
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+            .map_or(html.len(), |end| start + 1 + end);
+        html.insert_str(name, &format!(" data-block=\"{index}\""));
+    }
+    html
+}
+
+// Raw HTML cannot place blocks, its own `data-block` attributes are dropped
+// in any quoting the sanitizer would write back with double quotes.
+fn without_block_tags(html: &str) -> String {
+    regex_replace_all!(
+        r#"<[a-zA-Z](?:[^>"']|"[^"]*"|'[^']*')*>"#,
+        html,
+        |tag: &str| regex_replace_all!(
+            r#"\s+([^\s"'>/=]+)(?:\s*=\s*(?:"[^"]*"|'[^']*'|[^\s"'=<>`]+))?"#,
+            tag,
+            |attribute: &str, name: &str| match name.eq_ignore_ascii_case("data-block") {
+                true => String::new(),
+                false => attribute.to_string(),
+            }
+        )
+        .into_owned()
+    )
+    .into_owned()
+}
+
+/// The blocks of `html` tagged by `tag_blocks`, with the node each was
+/// rendered from. Returns `html` without the tags and the offset of each
+/// block in it.
+pub fn blocks<'a>(html: &str, tree: &'a Node) -> (String, Vec<(usize, &'a Node)>) {
+    let children = tree.children().map_or(&[][..], Vec::as_slice);
+    let mut untagged = String::with_capacity(html.len());
+    let mut blocks = vec![];
+    let mut from = 0;
+    for caps in regex!(r#" data-block="(\d+)""#).captures_iter(html) {
+        let tag = caps.get(0).map_or(0..0, |m| m.range());
+        untagged.push_str(&html[from..tag.start]);
+        from = tag.end;
+
+        let node = caps[1].parse::<usize>().ok().and_then(|i| children.get(i));
+        if let (Some(node), Some(start)) = (node, untagged.rfind('<')) {
+            blocks.push((start, node));
+        }
+    }
+    untagged.push_str(&html[from..]);
+    (untagged, blocks)
+}
+
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+// Raw HTML tags GFM escapes instead of passing them through.
+const FILTERED_TAGS: &[&str] = &[
+    "title",
+    "textarea",
+    "style",
+    "xmp",
+    "iframe",
+    "noembed",
+    "noframes",
+    "script",
+    "plaintext",
+];
+
+/// Finds the elements of HTML that are not inside another one, across
+/// several pieces of it.
+#[derive(Default)]
+struct Scanner {
+    // the raw HTML the scanner sees is compiled with the GFM tag filter
+    tagfilter: bool,
+    depth: usize,
+    // contents of script and style elements are not markup
+    raw_text: Option<String>,
+}
+
+impl Scanner {
+    /// Byte offsets in `html` of the elements starting at the top level.
+    fn scan(&mut self, html: &str) -> Vec<usize> {
+        let tag = regex!(r"<!--[\s\S]*?-->|<(/?)([a-zA-Z][a-zA-Z0-9-]*)[^<>]*?(/?)>");
+        let mut starts = vec![];
+
+        for caps in tag.captures_iter(html) {
+            let Some(name) = caps.get(2).map(|m| m.as_str().to_ascii_lowercase()) else {
+                continue;
+            };
+            if self.tagfilter && FILTERED_TAGS.contains(&name.as_str()) {
+                continue;
+            }
+            let closing = &caps[1] == "/";
+            if let Some(raw) = &self.raw_text {
+                if closing && *raw == name {
+                    self.raw_text = None;
+                    self.depth = self.depth.saturating_sub(1);
+                }
+                continue;
+            }
+
+            if closing {
+                self.depth = self.depth.saturating_sub(1);
+            } else {
+                if self.depth == 0 {
+                    starts.push(caps.get(0).map_or(0, |m| m.start()));
+                }
+                if !VOID_ELEMENTS.contains(&name.as_str()) && &caps[3] != "/" {
+                    self.depth += 1;
+                    if name == "script" || name == "style" {
+                        self.raw_text = Some(name);
+                    }
+                }
+            }
+        }
+        starts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dialect::Dialect, safe::sanitize_html, testing::tagged};
+    use rstest::rstest;
+
+    fn starts(html: &str, tree: &Node) -> Vec<(String, usize)> {
+        let (html, blocks) = blocks(html, tree);
+        blocks
+            .into_iter()
+            .map(|(start, node)| {
+                (
+                    html[start..start + 3].to_string(),
+                    node.position().unwrap().start.line,
+                )
+            })
+            .collect()
+    }
+
+    // ==================== Element Tests ====================
+
+    #[test]
+    fn test_top_level_elements() {
+        let html = "<h1>a</h1>\n<hr />\n<ul>\n<li><p>x</p></li>\n</ul>\n<!-- c --><script>if (a<b) {}</script><p>y<br>z</p>";
+        let starts: Vec<&str> = Scanner::default()
+            .scan(html)
+            .into_iter()
+            .map(|start| &html[start..start + 3])
+            .collect();
+        assert_eq!(starts, vec!["<h1", "<hr", "<ul", "<sc", "<p>"]);
+    }
+
+    // ==================== Block Tests ====================
+
+    #[test]
+    fn test_blocks_skip_definitions_and_comments() {
+        let (html, tree) = tagged(
+            "[a]: https://example.com\n\n# One\n\n<!-- note -->\n\n- item\n",
+            Dialect::Gfm,
+        );
+
+        assert_eq!(
+            starts(&html, &tree),
+            vec![("<h1".to_string(), 3), ("<ul".to_string(), 7)]
+        );
+    }
+
+    #[test]
+    fn test_blocks_after_dropped_html() {
+        let (html, tree) = tagged(
+            "# One\n\n<script>x()</script>\n\n## Two\n\n- item\n",
+            Dialect::Gfm,
+        );
+        let html = sanitize_html(&html);
+
+        assert_eq!(
+            starts(&html, &tree),
+            vec![
+                ("<h1".to_string(), 1),
+                ("<h2".to_string(), 5),
+                ("<ul".to_string(), 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_blocks_after_dropped_html_without_tagfilter() {
+        let content = "# One\n\n<script>x()</script>\n\n## Two\n";
+        let (html, tree) = tagged(content, Dialect::Commonmark);
+        let html = sanitize_html(&html);
+
+        assert_eq!(
+            starts(&html, &tree),
+            vec![("<h1".to_string(), 1), ("<h2".to_string(), 5)]
+        );
+    }
+
+    #[test]
+    fn test_blocks_inside_raw_html() {
+        let (html, tree) = tagged("<div>\n\n*inside*\n\n</div>\n\nAfter\n", Dialect::Gfm);
+
+        assert_eq!(
+            starts(&html, &tree),
+            vec![("<di".to_string(), 1), ("<p>".to_string(), 7)]
+        );
+    }
+
+    #[rstest]
+    #[case("<div data-block=\"0\">forged</div>")]
+    #[case("<div title=\">\" DATA-BLOCK=0>forged</div>")]
+    #[case("<div data-block='0' data-block>forged</div>")]
+    #[case("<div data-blocks=\"x\" data-block=\"0\">forged</div>")]
+    fn test_raw_html_cannot_tag_blocks(#[case] raw: &str) {
+        let (html, tree) = tagged(&format!("# One\n\n{raw}\n\nText\n"), Dialect::Gfm);
+        let html = sanitize_html(&html);
+
+        assert_eq!(
+            starts(&html, &tree),
+            vec![
+                ("<h1".to_string(), 1),
+                ("<di".to_string(), 3),
+                ("<p>".to_string(), 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_blocks_untagged() {
+        let (html, tree) = tagged("# One\n\nText\n", Dialect::Gfm);
+        assert!(html.starts_with("<h1 data-block=\"0\">"));
+
+        let (html, _) = blocks(&html, &tree);
+        assert_eq!(html, "<h1>One</h1>\n<p>Text</p>\n");
+    }
+}
@@ -0,0 +1,217 @@
+use lazy_regex::*;
+
+/// Callout types with their icon, GitHub alerts and the Obsidian types
+/// together with their aliases.
+const KINDS: &[(&str, &[&str], &str)] = &[
+    ("note", &[], "✎"),
+    ("abstract", &["summary", "tldr"], "☰"),
+    ("info", &[], "ℹ"),
+    ("todo", &[], "☐"),
+    ("tip", &["hint"], "💡"),
+    ("important", &[], "❢"),
+    ("success", &["check", "done"], "✔"),
+    ("question", &["help", "faq"], "?"),
+    ("warning", &["attention"], "⚠"),
+    ("caution", &[], "⛔"),
+    ("failure", &["fail", "missing"], "✘"),
+    ("danger", &["error"], "⚡"),
+    ("bug", &[], "🐞"),
+    ("example", &[], "▤"),
+    ("quote", &["cite"], "❝"),
+];
+
+/// The type a callout is styled as and its icon, unknown types look like notes.
+fn kind(name: &str) -> (&'static str, &'static str) {
+    let name = name.to_lowercase();
+    KINDS
+        .iter()
+        .find(|(kind, aliases, _)| *kind == name || aliases.contains(&name.as_str()))
+        .map_or(("note", "✎"), |(kind, _, icon)| (*kind, *icon))
+}
+
+/// Turns blockquotes starting with `[!TYPE]` into callouts: GitHub alerts
+/// (`> [!NOTE]`) and Obsidian callouts with a title (`> [!tip] Title`),
+/// folded (`[!tip]-`) or foldable (`[!tip]+`).
+pub fn callouts(html: &str) -> String {
+    let tag = regex!(r"<blockquote>|</blockquote>");
+    let marker = regex!(r"^\n?<p>\[!([A-Za-z][\w-]*)\]([+-]?)[ \t]*([^\n]*?)(\n|</p>\n?)");
+
+    let mut out = String::with_capacity(html.len());
+    // closing tags of the blockquotes currently open
+    let mut open: Vec<&str> = vec![];
+    let mut last = 0;
+
+    for found in tag.find_iter(html) {
+        if found.start() < last {
+            continue;
+        }
+        out.push_str(&html[last..found.start()]);
+        last = found.end();
+
+        if found.as_str() == "</blockquote>" {
+            out.push_str(open.pop().unwrap_or("</blockquote>"));
+            continue;
+        }
+        let Some(caps) = marker.captures(&html[last..]) else {
+            out.push_str("<blockquote>");
+            open.push("</blockquote>");
+            continue;
+        };
+
+        let (class, icon) = kind(&caps[1]);
+        let title = match &caps[3] {
+            "" => capitalize(&caps[1]),
+            title => title.to_string(),
+        };
+        let (element, attributes) = match &caps[2] {
+            "-" => ("details", ""),
+            "+" => ("details", " open"),
+            _ => ("div", ""),
+        };
+        let heading = if element == "details" { "summary" } else { "p" };
+
+        out.push_str(&format!(
+            "<{element} class=\"callout callout-{class}\" data-callout=\"{}\"{attributes}>\n\
+             <{heading} class=\"callout-title\"><span class=\"callout-icon\">{icon}</span>{title}</{heading}>\n\
+             <div class=\"callout-content\">\n",
+            caps[1].to_lowercase()
+        ));
+        // the rest of the first paragraph stays in the callout
+        if &caps[4] == "\n" {
+            out.push_str("<p>");
+        }
+        last += caps[0].len();
+        open.push(if element == "details" {
+            "</div>\n</details>"
+        } else {
+            "</div>\n</div>"
+        });
+    }
+    out.push_str(&html[last..]);
+    out
+}
+
+fn capitalize(name: &str) -> String {
+    let name = name.to_lowercase();
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dialect::Dialect, testing};
+    use insta::assert_snapshot;
+    use rstest::rstest;
+
+    fn render(input: &str) -> String {
+        callouts(&testing::render(input, Dialect::Gfm).0)
+    }
+
+    // ==================== GitHub Alert Tests ====================
+
+    #[rstest]
+    #[case("NOTE", "note")]
+    #[case("TIP", "tip")]
+    #[case("IMPORTANT", "important")]
+    #[case("WARNING", "warning")]
+    #[case("CAUTION", "caution")]
+    fn test_github_alerts(#[case] name: &str, #[case] class: &str) {
+        let html = render(&format!("> [!{name}]\n> Read this."));
+
+        assert!(html.starts_with(&format!("<div class=\"callout callout-{class}\"")));
+        assert!(html.contains(&format!("</span>{}</p>", capitalize(name))));
+        assert!(html.contains("<p>Read this.</p>"));
+        assert!(!html.contains("[!"));
+        assert!(!html.contains("blockquote"));
+    }
+
+    #[test]
+    fn test_github_alerts_snapshot() {
+        assert_snapshot!(render(
+            "> [!NOTE]\n> Useful information that users should know, even when skimming.\n\n\
+             > [!WARNING]\n> Urgent info that needs *immediate* attention.\n>\n> - first\n> - second"
+        ));
+    }
+
+    // ==================== Obsidian Callout Tests ====================
+
+    #[rstest]
+    #[case("summary", "abstract")]
+    #[case("tldr", "abstract")]
+    #[case("faq", "question")]
+    #[case("error", "danger")]
+    #[case("done", "success")]
+    #[case("cite", "quote")]
+    #[case("custom-thing", "note")]
+    fn test_callout_aliases(#[case] name: &str, #[case] class: &str) {
+        let html = render(&format!("> [!{name}]\n> body"));
+        assert!(html.contains(&format!(
+            "class=\"callout callout-{class}\" data-callout=\"{name}\""
+        )));
+    }
+
+    #[test]
+    fn test_callout_with_title() {
+        let html = render("> [!tip] Use **cargo** fmt\n> body");
+        assert!(html.contains("</span>Use <strong>cargo</strong> fmt</p>"));
+        assert!(html.contains("<p>body</p>"));
+    }
+
+    #[test]
+    fn test_callout_title_only() {
+        let html = render("> [!info] Just a title");
+        assert!(html.contains("</span>Just a title</p>\n<div class=\"callout-content\">\n</div>"));
+        assert!(!html.contains("<p></p>"));
+    }
+
+    #[rstest]
+    #[case(
+        "-",
+        "<details class=\"callout callout-warning\" data-callout=\"warning\">"
+    )]
+    #[case(
+        "+",
+        "<details class=\"callout callout-warning\" data-callout=\"warning\" open>"
+    )]
+    fn test_foldable_callouts(#[case] fold: &str, #[case] expected: &str) {
+        let html = render(&format!("> [!warning]{fold} Careful\n> body"));
+
+        assert!(html.starts_with(expected));
+        assert!(html.contains("<summary class=\"callout-title\">"));
+        assert!(html.ends_with("</div>\n</details>"));
+    }
+
+    #[test]
+    fn test_obsidian_callouts_snapshot() {
+        assert_snapshot!(render(
+            "> [!question]- Why is the sky **blue**?\n> Rayleigh scattering.\n>\n\
+             > > [!quote]+ Nested\n> > A callout in a callout.\n>\n> > plain quote\n\n\
+             > [!example] Title only"
+        ));
+    }
+
+    // ==================== Blockquote Tests ====================
+
+    #[rstest]
+    #[case("> plain quote")]
+    #[case("> see [!NOTE] later")]
+    #[case("> [NOTE]\n> no bang")]
+    fn test_plain_blockquotes_untouched(#[case] input: &str) {
+        let html = render(input);
+        assert!(html.starts_with("<blockquote>"));
+        assert!(html.ends_with("</blockquote>"));
+    }
+
+    #[test]
+    fn test_nested_closing_tags_balanced() {
+        let html = render("> [!note]\n> > quoted\n> > > deeper\n>\n> after");
+
+        assert_eq!(html.matches("<blockquote>").count(), 2);
+        assert_eq!(html.matches("</blockquote>").count(), 2);
+        assert!(html.ends_with("<p>after</p>\n</div>\n</div>"));
+    }
+}
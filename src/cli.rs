@@ -1,14 +1,149 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use clap_stdin::FileOrStdin;
+use std::net::IpAddr;
+use std::path::PathBuf;
 
-#[derive(Parser, Debug)]
+use crate::config::Overrides;
+
+#[derive(Parser, Debug, Clone)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
     #[arg(default_value = "-")]
     pub input: Option<FileOrStdin>,
     #[arg(short, long)]
     pub port: Option<u16>,
     #[arg(short, long, default_value_t = false)]
     pub no_browser: bool,
+    /// Session to open the file in, defaults to one per git repository or directory
+    #[arg(short, long)]
+    pub session: Option<String>,
+    /// Address the preview server listens on [default: 127.0.0.1]
+    #[arg(short, long)]
+    pub bind: Option<IpAddr>,
+    /// Listen on every interface so others on the network can open the preview
+    #[arg(long, default_value_t = false, conflicts_with = "bind")]
+    pub public: bool,
+    /// Token required to open the preview, generated automatically with --public
+    #[arg(short, long, value_parser = parse_token)]
+    pub token: Option<String>,
+    /// PEM certificate chain to serve the preview over HTTPS
+    #[arg(long, requires = "tls_key", value_name = "FILE")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of --tls-cert
+    #[arg(long, requires = "tls_cert", value_name = "FILE")]
+    pub tls_key: Option<PathBuf>,
+    /// Serve the preview over HTTPS with a certificate generated on startup
+    #[arg(long, default_value_t = false, conflicts_with = "tls_cert")]
+    pub tls_self_signed: bool,
+    /// Sanitize the HTML of every document, for reviewing untrusted files
+    #[arg(long, default_value_t = false)]
+    pub safe: bool,
+    /// Present the document as slides, split on `---` or `##` headings
+    #[arg(long, default_value_t = false)]
+    pub slides: bool,
+    /// Command that opens the preview instead of the default browser
+    #[arg(long, value_name = "CMD")]
+    pub browser: Option<String>,
+    /// Theme of the preview: github, academic, sepia or high-contrast
+    #[arg(long, value_name = "NAME")]
+    pub theme: Option<String>,
+    /// Directory with a page.hbs template replacing the built-in page
+    #[arg(long, value_name = "DIR")]
+    pub template: Option<PathBuf>,
+    /// Stylesheet added to the preview, reloaded when it changes
+    #[arg(long, value_name = "FILE")]
+    pub css: Option<PathBuf>,
+    /// Configuration file to use instead of ~/.config/glypho/config.toml
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Check Markdown files and print the findings as file:line:column,
+    /// exits with 1 when there are findings and 2 when a file cannot be read
+    Lint {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Print the word count, reading time and outline of a Markdown file as JSON
+    Stats { file: PathBuf },
+    /// Print the HTML of a Markdown file, `-` reads standard input
+    Render {
+        file: PathBuf,
+        /// Print a whole page, styled like the preview, instead of the document alone
+        #[arg(long)]
+        standalone: bool,
+    },
+    /// Write the print page of a Markdown file, or an EPUB book of several,
+    /// next to the first unless --output is given
+    Export {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Pdf)]
+        format: ExportFormat,
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// A standalone HTML page
+    Html,
+    /// A PDF printed by the `[export] pdf` program
+    Pdf,
+    /// An EPUB 3 book with one chapter per file, or per link of a SUMMARY.md
+    Epub,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Html => "html",
+            ExportFormat::Pdf => "pdf",
+            ExportFormat::Epub => "epub",
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum ConfigAction {
+    /// Print the configuration in effect, after applying the flags
+    Show,
+}
+
+impl From<&Args> for Overrides {
+    fn from(args: &Args) -> Self {
+        Overrides {
+            port: args.port,
+            bind: args.bind,
+            browser: args.browser.clone(),
+            safe: args.safe,
+            theme: args.theme.clone(),
+            template: args.template.clone(),
+            extra_css: args.css.clone(),
+        }
+    }
+}
+
+// The token travels in a query string and a cookie, keep it free of separators.
+fn parse_token(token: &str) -> Result<String, String> {
+    if !token.is_empty()
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~'))
+    {
+        Ok(token.to_string())
+    } else {
+        Err("use letters, digits, '-', '_', '.' or '~'".to_string())
+    }
 }
 
 #[cfg(test)]
@@ -27,6 +162,30 @@ mod tests {
 
         // no_browser should be false by default
         assert!(!args.no_browser);
+
+        // session is derived from the file by default
+        assert!(args.session.is_none());
+
+        // only reachable from this machine by default
+        assert!(args.bind.is_none());
+        assert!(!args.public);
+        assert!(args.token.is_none());
+
+        // plain HTTP by default
+        assert!(args.tls_cert.is_none());
+        assert!(args.tls_key.is_none());
+        assert!(!args.tls_self_signed);
+
+        // configuration comes from the XDG config directory by default
+        assert!(args.config.is_none());
+        assert!(args.browser.is_none());
+        assert!(args.theme.is_none());
+        assert!(args.template.is_none());
+        assert!(args.css.is_none());
+        assert!(args.command.is_none());
+
+        // the document is shown whole by default
+        assert!(!args.slides);
     }
 
     // ==================== Port Flag Tests ====================
@@ -65,6 +224,251 @@ mod tests {
         assert_eq!(parsed.no_browser, expected);
     }
 
+    // ==================== Session Flag Tests ====================
+
+    #[rstest]
+    #[case(&["glypho", "-s", "work"], Some("work"))]
+    #[case(&["glypho", "--session", "notes"], Some("notes"))]
+    #[case(&["glypho"], None)]
+    fn test_session_flag(#[case] args: &[&str], #[case] expected: Option<&str>) {
+        let parsed = Args::try_parse_from(args).unwrap();
+        assert_eq!(parsed.session.as_deref(), expected);
+    }
+
+    #[test]
+    fn test_session_flag_requires_value() {
+        let result = Args::try_parse_from(["glypho", "--session"]);
+        assert!(result.is_err());
+    }
+
+    // ==================== Network Flag Tests ====================
+
+    #[rstest]
+    #[case(&["glypho", "-b", "192.168.1.10"], "192.168.1.10")]
+    #[case(&["glypho", "--bind", "::1"], "::1")]
+    #[case(&["glypho", "--bind", "0.0.0.0"], "0.0.0.0")]
+    fn test_bind_flag(#[case] args: &[&str], #[case] expected: &str) {
+        let parsed = Args::try_parse_from(args).unwrap();
+        assert_eq!(parsed.bind, Some(expected.parse::<IpAddr>().unwrap()));
+    }
+
+    #[test]
+    fn test_bind_flag_invalid() {
+        let result = Args::try_parse_from(["glypho", "--bind", "localhost:80"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_public_conflicts_with_bind() {
+        let result = Args::try_parse_from(["glypho", "--public", "--bind", "10.0.0.1"]);
+        assert!(result.is_err());
+    }
+
+    #[rstest]
+    #[case(&["glypho", "-t", "secret"], Some("secret"))]
+    #[case(&["glypho", "--public", "--token", "secret"], Some("secret"))]
+    #[case(&["glypho", "--public"], None)]
+    fn test_token_flag(#[case] args: &[&str], #[case] expected: Option<&str>) {
+        let parsed = Args::try_parse_from(args).unwrap();
+        assert_eq!(parsed.token.as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("a;b")]
+    #[case("a b")]
+    #[case("a&b")]
+    fn test_token_flag_invalid(#[case] token: &str) {
+        let result = Args::try_parse_from(["glypho", "--token", token]);
+        assert!(result.is_err());
+    }
+
+    // ==================== TLS Flag Tests ====================
+
+    #[test]
+    fn test_tls_cert_and_key() {
+        let args =
+            Args::try_parse_from(["glypho", "--tls-cert", "cert.pem", "--tls-key", "key.pem"])
+                .unwrap();
+
+        assert_eq!(args.tls_cert, Some(PathBuf::from("cert.pem")));
+        assert_eq!(args.tls_key, Some(PathBuf::from("key.pem")));
+    }
+
+    #[rstest]
+    #[case(&["glypho", "--tls-cert", "cert.pem"])]
+    #[case(&["glypho", "--tls-key", "key.pem"])]
+    fn test_tls_cert_and_key_required_together(#[case] args: &[&str]) {
+        assert!(Args::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn test_tls_self_signed() {
+        let args = Args::try_parse_from(["glypho", "--public", "--tls-self-signed"]).unwrap();
+        assert!(args.tls_self_signed);
+    }
+
+    #[test]
+    fn test_tls_self_signed_conflicts_with_cert() {
+        let result = Args::try_parse_from([
+            "glypho",
+            "--tls-self-signed",
+            "--tls-cert",
+            "cert.pem",
+            "--tls-key",
+            "key.pem",
+        ]);
+        assert!(result.is_err());
+    }
+
+    // ==================== Config Flag Tests ====================
+
+    #[rstest]
+    #[case(&["glypho", "-c", "glypho.toml"])]
+    #[case(&["glypho", "--config", "glypho.toml"])]
+    fn test_config_flag(#[case] args: &[&str]) {
+        let parsed = Args::try_parse_from(args).unwrap();
+        assert_eq!(parsed.config, Some(PathBuf::from("glypho.toml")));
+    }
+
+    #[rstest]
+    #[case(&["glypho", "--safe"], true)]
+    #[case(&["glypho"], false)]
+    fn test_safe_flag(#[case] args: &[&str], #[case] expected: bool) {
+        let parsed = Args::try_parse_from(args).unwrap();
+        assert_eq!(parsed.safe, expected);
+    }
+
+    #[rstest]
+    #[case(&["glypho", "--slides", "talk.md"], true)]
+    #[case(&["glypho", "talk.md"], false)]
+    fn test_slides_flag(#[case] args: &[&str], #[case] expected: bool) {
+        let parsed = Args::try_parse_from(args).unwrap();
+        assert_eq!(parsed.slides, expected);
+    }
+
+    #[test]
+    fn test_browser_flag() {
+        let args = Args::try_parse_from(["glypho", "--browser", "firefox --new-window"]).unwrap();
+        assert_eq!(args.browser.as_deref(), Some("firefox --new-window"));
+    }
+
+    #[test]
+    fn test_theme_and_template_flags() {
+        let args =
+            Args::try_parse_from(["glypho", "--theme", "academic", "--template", "site"]).unwrap();
+        assert_eq!(args.theme.as_deref(), Some("academic"));
+        assert_eq!(args.template, Some(PathBuf::from("site")));
+    }
+
+    #[test]
+    fn test_css_flag() {
+        let args = Args::try_parse_from(["glypho", "--css", "house.css"]).unwrap();
+        assert_eq!(args.css, Some(PathBuf::from("house.css")));
+    }
+
+    // ==================== Subcommand Tests ====================
+
+    #[test]
+    fn test_config_show_subcommand() {
+        let args = Args::try_parse_from(["glypho", "config", "show"]).unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Config {
+                action: ConfigAction::Show
+            })
+        );
+    }
+
+    #[test]
+    fn test_config_show_with_flags() {
+        let args = Args::try_parse_from(["glypho", "-p", "8080", "config", "show"]).unwrap();
+        assert_eq!(args.port, Some(8080));
+        assert!(args.command.is_some());
+    }
+
+    #[test]
+    fn test_lint_subcommand() {
+        let args = Args::try_parse_from(["glypho", "lint", "a.md", "docs/b.md"]).unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Lint {
+                files: vec![PathBuf::from("a.md"), PathBuf::from("docs/b.md")]
+            })
+        );
+        assert!(Args::try_parse_from(["glypho", "lint"]).is_err());
+    }
+
+    #[test]
+    fn test_stats_subcommand() {
+        let args = Args::try_parse_from(["glypho", "stats", "notes.md"]).unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Stats {
+                file: PathBuf::from("notes.md")
+            })
+        );
+        assert!(Args::try_parse_from(["glypho", "stats"]).is_err());
+    }
+
+    #[rstest]
+    #[case(&["glypho", "render", "notes.md"], "notes.md", false)]
+    #[case(&["glypho", "render", "--standalone", "-"], "-", true)]
+    fn test_render_subcommand(#[case] args: &[&str], #[case] file: &str, #[case] standalone: bool) {
+        let args = Args::try_parse_from(args).unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Render {
+                file: PathBuf::from(file),
+                standalone,
+            })
+        );
+        assert!(Args::try_parse_from(["glypho", "render"]).is_err());
+    }
+
+    #[rstest]
+    #[case(&["glypho", "export", "notes.md"], ExportFormat::Pdf, None)]
+    #[case(&["glypho", "export", "notes.md", "--format", "html"], ExportFormat::Html, None)]
+    #[case(&["glypho", "export", "-f", "pdf", "-o", "out.pdf", "notes.md"], ExportFormat::Pdf, Some("out.pdf"))]
+    #[case(&["glypho", "export", "-f", "epub", "notes.md"], ExportFormat::Epub, None)]
+    fn test_export_subcommand(
+        #[case] args: &[&str],
+        #[case] format: ExportFormat,
+        #[case] output: Option<&str>,
+    ) {
+        let args = Args::try_parse_from(args).unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Export {
+                files: vec![PathBuf::from("notes.md")],
+                format,
+                output: output.map(PathBuf::from),
+            })
+        );
+    }
+
+    #[test]
+    fn test_export_several_files() {
+        let args =
+            Args::try_parse_from(["glypho", "export", "-f", "epub", "one.md", "two.md"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Export { files, .. }) if files.len() == 2
+        ));
+    }
+
+    #[test]
+    fn test_export_format_invalid() {
+        assert!(Args::try_parse_from(["glypho", "export", "-f", "docx", "notes.md"]).is_err());
+        assert!(Args::try_parse_from(["glypho", "export"]).is_err());
+    }
+
+    #[test]
+    fn test_config_requires_action() {
+        let result = Args::try_parse_from(["glypho", "config"]);
+        assert!(result.is_err());
+    }
+
     // ==================== Combined Flags Tests ====================
 
     #[test]
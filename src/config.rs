@@ -0,0 +1,927 @@
+use markdown::{Options, ParseOptions};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::warn;
+
+use crate::{
+    dialect::Dialect,
+    error::GlyphoError,
+    lint::Rule,
+    wikilinks::{SourceMap, rewrite_wikilinks},
+};
+
+/// Per-project overrides, looked up from the document's directory upward.
+pub const PROJECT_FILE: &str = ".glypho.toml";
+
+// Keys only the user configuration and the command line can set. A project
+// must not pick the programs glypho runs, nor expose the preview to the
+// network.
+const UNTRUSTED_KEYS: &[&str] = &[
+    "safe",
+    "trusted_roots",
+    "export",
+    "diagrams",
+    "browser",
+    "bind",
+    "port",
+];
+
+// Keys that put scripts and markup of a project into the preview page, only
+// honoured for projects in `trusted_roots`.
+const PAGE_KEYS: &[&str] = &["extra_css", "extra_js", "template"];
+
+/// Settings read from `~/.config/glypho/config.toml`. Every key is optional,
+/// command line flags take precedence over the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Port of the preview server, a free one is picked when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Address the preview server listens on
+    pub bind: IpAddr,
+    /// Command used to open the preview instead of the system default browser
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub browser: Option<String>,
+    /// How long a file has to stay unchanged before it is rendered again
+    pub debounce_ms: u64,
+    /// Name of the theme, exposed to stylesheets as `<html data-theme>`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    /// Stylesheet added to the preview page after the theme
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_css: Option<PathBuf>,
+    /// Script run in the preview page after the built-in ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_js: Option<PathBuf>,
+    /// Directory with a `page.hbs` used instead of the built-in page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<PathBuf>,
+    /// Sanitize the HTML of every document, even in trusted roots
+    pub safe: bool,
+    /// Directories whose documents may run raw HTML and scripts in the
+    /// preview, documents anywhere else are sanitized
+    pub trusted_roots: Vec<PathBuf>,
+    pub markdown: MarkdownConfig,
+    pub wikilinks: WikilinksConfig,
+    pub diagrams: DiagramsConfig,
+    pub lint: LintConfig,
+    pub export: ExportConfig,
+}
+
+/// Settings that win over every configuration file, the binary takes them
+/// from its command line flags.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Overrides {
+    pub port: Option<u16>,
+    pub bind: Option<IpAddr>,
+    pub browser: Option<String>,
+    /// Sanitize every document, an override cannot turn it off
+    pub safe: bool,
+    pub theme: Option<String>,
+    pub template: Option<PathBuf>,
+    pub extra_css: Option<PathBuf>,
+}
+
+/// The Markdown dialect and the constructs changed on top of it, unset
+/// constructs follow the dialect.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarkdownConfig {
+    /// `commonmark`, `gfm`, `gfm-math` or `mdx`, by file extension when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dialect: Option<Dialect>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tables: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_lists: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub footnotes: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strikethrough: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub autolinks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frontmatter: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indented_code: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub math: Option<bool>,
+    /// Pass raw HTML in documents through to the page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub html: Option<bool>,
+    /// Show `> [!NOTE]` blockquotes as callouts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callouts: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WikilinksConfig {
+    /// Turn `[[target|label]]` into links
+    pub enabled: bool,
+    /// Appended to targets without an extension, `"md"` links `[[notes]]` to `notes.md`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extension: Option<String>,
+}
+
+/// Commands rendering diagram code blocks to SVG, a missing command shows
+/// as an error below the block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiagramsConfig {
+    /// Render ```` ```mermaid ```` and ```` ```dot ```` blocks of documents
+    /// under `trusted_roots`, the others get a note below them
+    pub enabled: bool,
+    /// mermaid-cli, called as `mmdc -i <input> -o <output.svg>`
+    pub mermaid: String,
+    /// Graphviz, called as `dot -Tsvg -o <output.svg> <input>`
+    pub dot: String,
+}
+
+/// Checks run on every render, shown in the margin of the preview and
+/// by `glypho lint`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LintConfig {
+    /// Mark findings in the preview
+    pub enabled: bool,
+    /// Rules not checked, like `["line-length", "bare-url"]`
+    pub disable: Vec<Rule>,
+    /// Longest line allowed outside of code and tables, 0 for no limit
+    pub line_length: usize,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            enabled: true,
+            disable: vec![],
+            line_length: 120,
+        }
+    }
+}
+
+impl Default for DiagramsConfig {
+    fn default() -> Self {
+        DiagramsConfig {
+            enabled: true,
+            mermaid: "mmdc".to_string(),
+            dot: "dot".to_string(),
+        }
+    }
+}
+
+/// Programs `glypho export` hands the print page to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportConfig {
+    /// Chromium or Chrome, called as `<pdf> --print-to-pdf=<output.pdf> <input.html>`
+    pub pdf: String,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        ExportConfig {
+            pdf: "chromium --headless --no-pdf-header-footer --virtual-time-budget=5000"
+                .to_string(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            port: None,
+            bind: IpAddr::from(Ipv4Addr::LOCALHOST),
+            browser: None,
+            debounce_ms: 5000,
+            theme: None,
+            extra_css: None,
+            extra_js: None,
+            template: None,
+            safe: false,
+            trusted_roots: vec![],
+            markdown: MarkdownConfig::default(),
+            wikilinks: WikilinksConfig::default(),
+            diagrams: DiagramsConfig::default(),
+            lint: LintConfig::default(),
+            export: ExportConfig::default(),
+        }
+    }
+}
+
+impl Default for WikilinksConfig {
+    fn default() -> Self {
+        WikilinksConfig {
+            enabled: true,
+            extension: None,
+        }
+    }
+}
+
+impl WikilinksConfig {
+    /// `content` with its wikilinks turned into Markdown links when enabled,
+    /// and where they were in `content`.
+    pub fn rewrite(&self, content: &str) -> (String, SourceMap) {
+        match self.enabled {
+            false => (content.to_string(), SourceMap::default()),
+            true => rewrite_wikilinks(content, self.extension.as_deref()),
+        }
+    }
+}
+
+impl Config {
+    /// The user config in the XDG config directory, or the defaults when
+    /// there is none.
+    pub fn load() -> Result<Self, GlyphoError> {
+        let xdg_dirs = xdg::BaseDirectories::with_prefix("glypho");
+        match xdg_dirs.find_config_file("config.toml") {
+            Some(path) => Self::from_file(&path),
+            None => Ok(Config::default()),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, GlyphoError> {
+        let content = read(path)?;
+        toml::from_str(&content).map_err(|err| invalid(path, err.message()))
+    }
+
+    /// The configuration of documents in `dir`: the nearest `.glypho.toml`
+    /// in `dir` or one of its parents merged over `self`.
+    pub fn for_dir(&self, dir: &Path) -> Result<Self, GlyphoError> {
+        match project_file(dir) {
+            Some(path) => self.merged(&path),
+            None => Ok(self.clone()),
+        }
+    }
+
+    /// The configuration `file` is previewed with: its project file merged
+    /// over `self`, and the overrides over both.
+    pub fn for_file(&self, file: &Path, overrides: &Overrides) -> Result<Self, GlyphoError> {
+        let dir = file.parent().unwrap_or(Path::new("/"));
+        Ok(self.for_dir(dir)?.with_overrides(overrides))
+    }
+
+    /// `self` with the keys set in the project file at `path` replaced.
+    fn merged(&self, path: &Path) -> Result<Self, GlyphoError> {
+        let mut overrides: toml::Table =
+            toml::from_str(&read(path)?).map_err(|err| invalid(path, err.message()))?;
+        // a downloaded repository must not be able to trust itself
+        for key in UNTRUSTED_KEYS {
+            if overrides.remove(*key).is_some() {
+                warn!(
+                    "Ignoring {key} in {}, set it in the user configuration",
+                    path.display()
+                );
+            }
+        }
+        // the page scripts of an untrusted project could write tracked files
+        if !self.is_trusted(path) {
+            for key in PAGE_KEYS {
+                if overrides.remove(*key).is_some() {
+                    warn!(
+                        "Ignoring {key} in {}, the project is not trusted",
+                        path.display()
+                    );
+                }
+            }
+        }
+        let mut table = toml::Table::try_from(self).map_err(|err| invalid(path, &err))?;
+        merge(&mut table, overrides);
+
+        let mut config: Config = table.try_into().map_err(|err| invalid(path, &err))?;
+        // relative stylesheets, scripts and templates belong to the project,
+        // not to the working directory
+        if let Some(dir) = path.parent() {
+            for file in [
+                &mut config.extra_css,
+                &mut config.extra_js,
+                &mut config.template,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                if file.is_relative() {
+                    *file = dir.join(&file);
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// `self` with the settings of `overrides` replaced.
+    pub fn with_overrides(mut self, overrides: &Overrides) -> Self {
+        if overrides.port.is_some() {
+            self.port = overrides.port;
+        }
+        if let Some(bind) = overrides.bind {
+            self.bind = bind;
+        }
+        if overrides.browser.is_some() {
+            self.browser = overrides.browser.clone();
+        }
+        if overrides.safe {
+            self.safe = true;
+        }
+        if overrides.theme.is_some() {
+            self.theme = overrides.theme.clone();
+        }
+        if overrides.template.is_some() {
+            self.template = overrides.template.clone();
+        }
+        if overrides.extra_css.is_some() {
+            self.extra_css = overrides.extra_css.clone();
+        }
+        self
+    }
+
+    /// Whether raw HTML of `file` reaches the preview unsanitized.
+    pub fn is_trusted(&self, file: &Path) -> bool {
+        if self.safe {
+            return false;
+        }
+        let Ok(file) = file.canonicalize() else {
+            return false;
+        };
+        self.trusted_roots
+            .iter()
+            .filter_map(|root| root.canonicalize().ok())
+            .any(|root| file.starts_with(root))
+    }
+
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms)
+    }
+
+    pub fn to_toml(&self) -> eyre::Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+}
+
+/// The nearest project file in `dir` or one of its parents.
+pub fn project_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|d| d.join(PROJECT_FILE))
+        .find(|f| f.is_file())
+}
+
+/// Splits a command of the configuration into its words the way a shell
+/// would, with quotes and backslashes but no expansions. `None` when a quote
+/// is not closed.
+pub fn command_words(command: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let quoted = word.get_or_insert_default();
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => quoted.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let quoted = word.get_or_insert_default();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            c @ ('"' | '\\' | '$' | '`') => quoted.push(c),
+                            '\n' => {}
+                            c => quoted.extend(['\\', c]),
+                        },
+                        c => quoted.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next().filter(|&c| c != '\n') {
+                    word.get_or_insert_default().push(c);
+                }
+            }
+            c => word.get_or_insert_default().push(c),
+        }
+    }
+    words.extend(word);
+    Some(words)
+}
+
+// Tables are merged key by key so that a project can change one construct
+// without repeating the others.
+fn merge(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(value)) => merge(base, value),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<String, GlyphoError> {
+    std::fs::read_to_string(path).map_err(|err| invalid(path, &err))
+}
+
+fn invalid(path: &Path, reason: &(impl ToString + ?Sized)) -> GlyphoError {
+    GlyphoError::InvalidConfig {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    }
+}
+
+impl MarkdownConfig {
+    pub fn dialect(&self, file: &Path) -> Dialect {
+        self.dialect.unwrap_or_else(|| Dialect::for_file(file))
+    }
+
+    pub fn callouts(&self, file: &Path) -> bool {
+        self.callouts
+            .unwrap_or_else(|| self.dialect(file).callouts())
+    }
+
+    /// Options of the `markdown` crate for `file`.
+    pub fn options(&self, file: &Path) -> Options {
+        let dialect = self.dialect(file);
+        let mut constructs = dialect.constructs();
+        let mut compile = dialect.compile();
+        let mut single_tilde = dialect.single_tilde();
+
+        let set = |flag: &mut bool, value: Option<bool>| {
+            if let Some(value) = value {
+                *flag = value;
+            }
+        };
+        set(&mut constructs.gfm_table, self.tables);
+        set(&mut constructs.gfm_task_list_item, self.task_lists);
+        set(&mut constructs.gfm_footnote_definition, self.footnotes);
+        set(&mut constructs.gfm_label_start_footnote, self.footnotes);
+        set(&mut constructs.gfm_strikethrough, self.strikethrough);
+        set(&mut single_tilde, self.strikethrough);
+        set(&mut constructs.autolink, self.autolinks);
+        set(&mut constructs.gfm_autolink_literal, self.autolinks);
+        set(&mut constructs.frontmatter, self.frontmatter);
+        set(&mut constructs.code_indented, self.indented_code);
+        set(&mut constructs.math_flow, self.math);
+        set(&mut constructs.math_text, self.math);
+        set(&mut constructs.html_flow, self.html);
+        set(&mut constructs.html_text, self.html);
+        set(&mut compile.allow_dangerous_html, self.html);
+
+        Options {
+            parse: ParseOptions {
+                constructs,
+                gfm_strikethrough_single_tilde: single_tilde,
+                ..ParseOptions::default()
+            },
+            compile,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Args;
+    use clap::Parser;
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    fn write_config(content: &str) -> (TempDir, PathBuf) {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        std::fs::write(&path, content).unwrap();
+        (temp_dir, path)
+    }
+
+    fn trusting(temp_dir: &TempDir) -> Config {
+        Config {
+            trusted_roots: vec![temp_dir.path().to_path_buf()],
+            ..Config::default()
+        }
+    }
+
+    // ==================== Parsing Tests ====================
+
+    #[test]
+    fn test_empty_config_is_default() {
+        let (_temp_dir, path) = write_config("");
+        assert_eq!(Config::from_file(&path).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_full_config() {
+        let (_temp_dir, path) = write_config(
+            r#"
+port = 8080
+bind = "0.0.0.0"
+browser = "firefox"
+debounce_ms = 300
+theme = "solarized"
+extra_css = "/home/me/glypho.css"
+extra_js = "/home/me/glypho.js"
+
+[markdown]
+dialect = "gfm-math"
+tables = false
+html = false
+
+[wikilinks]
+extension = "md"
+
+[lint]
+disable = ["line-length", "bare-url"]
+line_length = 80
+
+[export]
+pdf = "google-chrome --headless"
+"#,
+        );
+
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.port, Some(8080));
+        assert_eq!(config.bind, IpAddr::from([0, 0, 0, 0]));
+        assert_eq!(config.browser.as_deref(), Some("firefox"));
+        assert_eq!(config.debounce(), Duration::from_millis(300));
+        assert_eq!(config.theme.as_deref(), Some("solarized"));
+        assert_eq!(config.extra_css, Some(PathBuf::from("/home/me/glypho.css")));
+        assert_eq!(config.extra_js, Some(PathBuf::from("/home/me/glypho.js")));
+        assert_eq!(config.markdown.tables, Some(false));
+        assert_eq!(config.markdown.html, Some(false));
+        assert_eq!(config.markdown.footnotes, None);
+        assert_eq!(config.markdown.dialect, Some(Dialect::GfmMath));
+        assert!(config.wikilinks.enabled);
+        assert_eq!(config.wikilinks.extension.as_deref(), Some("md"));
+        assert!(config.lint.enabled);
+        assert_eq!(config.lint.disable, vec![Rule::LineLength, Rule::BareUrl]);
+        assert_eq!(config.lint.line_length, 80);
+        assert_eq!(config.export.pdf, "google-chrome --headless");
+    }
+
+    #[rstest]
+    #[case("prot = 8080")]
+    #[case("port = \"eighty\"")]
+    #[case("[markdown]\ntable = false")]
+    #[case("[lint]\ndisable = [\"no-such-rule\"]")]
+    #[case("port = ")]
+    fn test_invalid_config(#[case] content: &str) {
+        let (_temp_dir, path) = write_config(content);
+        let result = Config::from_file(&path);
+        assert!(matches!(result, Err(GlyphoError::InvalidConfig { .. })));
+    }
+
+    #[test]
+    fn test_missing_config_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let result = Config::from_file(&temp_dir.path().join("nope.toml"));
+        assert!(matches!(result, Err(GlyphoError::InvalidConfig { .. })));
+    }
+
+    #[test]
+    fn test_show_round_trips() {
+        let config = Config {
+            port: Some(4000),
+            browser: Some("chromium --app".to_string()),
+            ..Config::default()
+        };
+
+        let shown = config.to_toml().unwrap();
+        assert_eq!(toml::from_str::<Config>(&shown).unwrap(), config);
+    }
+
+    // ==================== Project Config Tests ====================
+
+    #[test]
+    fn test_project_config_merged_over_user_config() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(
+            temp_dir.path().join(PROJECT_FILE),
+            "[wikilinks]\nenabled = false\n",
+        )
+        .unwrap();
+        let user = Config {
+            debounce_ms: 200,
+            wikilinks: WikilinksConfig {
+                enabled: true,
+                extension: Some("md".to_string()),
+            },
+            ..Config::default()
+        };
+
+        let config = user.for_dir(temp_dir.path()).unwrap();
+        assert!(!config.wikilinks.enabled);
+        // keys the project does not set keep the user's values
+        assert_eq!(config.wikilinks.extension.as_deref(), Some("md"));
+        assert_eq!(config.debounce_ms, 200);
+    }
+
+    #[test]
+    fn test_nearest_project_config_wins() {
+        let temp_dir = TempDir::new().unwrap();
+        let nested = temp_dir.path().join("docs/guide");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(temp_dir.path().join(PROJECT_FILE), "debounce_ms = 100").unwrap();
+        std::fs::write(
+            temp_dir.path().join("docs").join(PROJECT_FILE),
+            "debounce_ms = 300",
+        )
+        .unwrap();
+
+        assert_eq!(
+            project_file(&nested),
+            Some(temp_dir.path().join("docs").join(PROJECT_FILE))
+        );
+        let config = Config::default().for_dir(&nested).unwrap();
+        assert_eq!(config.debounce_ms, 300);
+    }
+
+    #[test]
+    fn test_without_project_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let user = Config {
+            port: Some(4000),
+            ..Config::default()
+        };
+        assert_eq!(user.for_dir(temp_dir.path()).unwrap(), user);
+    }
+
+    #[test]
+    fn test_project_extras_relative_to_project() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(
+            temp_dir.path().join(PROJECT_FILE),
+            "extra_css = \"style/docs.css\"\nextra_js = \"style/docs.js\"",
+        )
+        .unwrap();
+
+        let config = trusting(&temp_dir).for_dir(temp_dir.path()).unwrap();
+        assert_eq!(
+            config.extra_css,
+            Some(temp_dir.path().join("style/docs.css"))
+        );
+        assert_eq!(config.extra_js, Some(temp_dir.path().join("style/docs.js")));
+    }
+
+    #[test]
+    fn test_project_template_relative_to_project() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join(PROJECT_FILE), "template = \"site\"").unwrap();
+
+        let config = trusting(&temp_dir).for_dir(temp_dir.path()).unwrap();
+        assert_eq!(config.template, Some(temp_dir.path().join("site")));
+    }
+
+    #[rstest]
+    #[case::untrusted(Vec::new(), false)]
+    #[case::safe(vec![PathBuf::from("/")], true)]
+    fn test_untrusted_project_cannot_change_page(
+        #[case] trusted_roots: Vec<PathBuf>,
+        #[case] safe: bool,
+    ) {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(
+            temp_dir.path().join(PROJECT_FILE),
+            "extra_css = \"a.css\"\nextra_js = \"a.js\"\ntemplate = \"site\"\ndebounce_ms = 10\n",
+        )
+        .unwrap();
+        let user = Config {
+            trusted_roots,
+            safe,
+            ..Config::default()
+        };
+
+        let config = user.for_dir(temp_dir.path()).unwrap();
+        assert_eq!(config.extra_css, None);
+        assert_eq!(config.extra_js, None);
+        assert_eq!(config.template, None);
+        assert_eq!(config.debounce_ms, 10);
+    }
+
+    #[rstest]
+    #[case("[markdown]\ntables = \"no\"")]
+    #[case("colour = \"red\"")]
+    #[case("[wikilinks")]
+    fn test_invalid_project_config(#[case] content: &str) {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join(PROJECT_FILE), content).unwrap();
+
+        let result = Config::default().for_dir(temp_dir.path());
+        assert!(matches!(result, Err(GlyphoError::InvalidConfig { .. })));
+    }
+
+    #[test]
+    fn test_project_config_cannot_trust_itself() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(
+            temp_dir.path().join(PROJECT_FILE),
+            "safe = false\ntrusted_roots = [\"/\"]\ndebounce_ms = 10\nbind = \"0.0.0.0\"\nport = 80\nbrowser = \"sh payload.sh\"\n[export]\npdf = \"sh\"\n[diagrams]\ndot = \"sh payload.sh\"\n",
+        )
+        .unwrap();
+        let user = Config {
+            safe: true,
+            ..Config::default()
+        };
+
+        let config = user.for_dir(temp_dir.path()).unwrap();
+        assert!(config.safe);
+        assert!(config.trusted_roots.is_empty());
+        assert_eq!(config.export, ExportConfig::default());
+        assert_eq!(config.diagrams, DiagramsConfig::default());
+        assert_eq!(config.bind, Config::default().bind);
+        assert_eq!(config.port, None);
+        assert_eq!(config.browser, None);
+        assert_eq!(config.debounce_ms, 10);
+    }
+
+    // ==================== Trust Tests ====================
+
+    #[test]
+    fn test_trusted_roots() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mine, other) = (temp_dir.path().join("mine"), temp_dir.path().join("other"));
+        std::fs::create_dir_all(mine.join("docs")).unwrap();
+        std::fs::create_dir_all(&other).unwrap();
+        std::fs::write(mine.join("docs/a.md"), "").unwrap();
+        std::fs::write(other.join("b.md"), "").unwrap();
+        let config = Config {
+            trusted_roots: vec![mine.clone()],
+            ..Config::default()
+        };
+
+        assert!(config.is_trusted(&mine.join("docs/a.md")));
+        assert!(!config.is_trusted(&other.join("b.md")));
+        // `..` cannot escape a trusted root
+        assert!(!config.is_trusted(&mine.join("../other/b.md")));
+    }
+
+    #[test]
+    fn test_nothing_trusted_by_default() {
+        let (_temp_dir, path) = write_config("");
+        assert!(!Config::default().is_trusted(&path));
+    }
+
+    #[test]
+    fn test_safe_overrides_trusted_roots() {
+        let (temp_dir, path) = write_config("");
+        let config = Config {
+            safe: true,
+            trusted_roots: vec![temp_dir.path().to_path_buf()],
+            ..Config::default()
+        };
+        assert!(!config.is_trusted(&path));
+    }
+
+    // ==================== Override Tests ====================
+
+    #[test]
+    fn test_flags_override_file() {
+        let config = Config {
+            port: Some(4000),
+            browser: Some("firefox".to_string()),
+            ..Config::default()
+        };
+        let args = Args::try_parse_from([
+            "glypho",
+            "-p",
+            "5000",
+            "-b",
+            "::1",
+            "--browser",
+            "lynx",
+            "--safe",
+            "--theme",
+            "sepia",
+            "--template",
+            "site",
+            "--css",
+            "house.css",
+        ])
+        .unwrap();
+
+        let config = config.with_overrides(&(&args).into());
+        assert_eq!(config.port, Some(5000));
+        assert_eq!(config.bind, "::1".parse::<IpAddr>().unwrap());
+        assert_eq!(config.browser.as_deref(), Some("lynx"));
+        assert!(config.safe);
+        assert_eq!(config.theme.as_deref(), Some("sepia"));
+        assert_eq!(config.template, Some(PathBuf::from("site")));
+        assert_eq!(config.extra_css, Some(PathBuf::from("house.css")));
+    }
+
+    #[test]
+    fn test_file_kept_without_flags() {
+        let config = Config {
+            port: Some(4000),
+            bind: IpAddr::from([10, 0, 0, 1]),
+            ..Config::default()
+        };
+        let args = Args::try_parse_from(["glypho"]).unwrap();
+
+        assert_eq!(config.clone().with_overrides(&(&args).into()), config);
+    }
+
+    // ==================== Command Tests ====================
+
+    #[rstest]
+    #[case("firefox", &["firefox"])]
+    #[case("  chromium   --app ", &["chromium", "--app"])]
+    #[case(r#""/opt/My Browser/browser" --new-window"#, &["/opt/My Browser/browser", "--new-window"])]
+    #[case("open -a 'Google Chrome'", &["open", "-a", "Google Chrome"])]
+    #[case(r"my\ browser", &["my browser"])]
+    #[case(r#"sh -c "echo \"\$1\" \x""#, &["sh", "-c", r#"echo "$1" \x"#])]
+    #[case("'' \"\"", &["", ""])]
+    #[case("", &[])]
+    fn test_command_words(#[case] command: &str, #[case] words: &[&str]) {
+        assert_eq!(command_words(command).unwrap(), words);
+    }
+
+    #[rstest]
+    #[case("open -a 'Google Chrome")]
+    #[case(r#"browser "--app"#)]
+    fn test_command_words_unclosed_quote(#[case] command: &str) {
+        assert_eq!(command_words(command), None);
+    }
+
+    // ==================== Markdown Options Tests ====================
+
+    #[test]
+    fn test_disabled_tables_render_as_text() {
+        let markdown = MarkdownConfig {
+            tables: Some(false),
+            ..MarkdownConfig::default()
+        };
+        let input = "| a | b |\n|---|---|\n| 1 | 2 |";
+        let options = markdown.options(Path::new("a.md"));
+
+        let html = markdown::to_html_with_options(input, &options).unwrap();
+        assert!(!html.contains("<table>"));
+    }
+
+    #[test]
+    fn test_disabled_html_is_escaped() {
+        let markdown = MarkdownConfig {
+            html: Some(false),
+            ..MarkdownConfig::default()
+        };
+        let options = markdown.options(Path::new("a.md"));
+
+        let html = markdown::to_html_with_options("<b>bold</b>", &options).unwrap();
+        assert!(!html.contains("<b>"));
+    }
+
+    #[test]
+    fn test_construct_overrides_dialect() {
+        let markdown = MarkdownConfig {
+            dialect: Some(Dialect::Commonmark),
+            tables: Some(true),
+            ..MarkdownConfig::default()
+        };
+        let options = markdown.options(Path::new("a.md"));
+
+        assert!(options.parse.constructs.gfm_table);
+        assert!(!options.parse.constructs.gfm_strikethrough);
+    }
+
+    #[rstest]
+    #[case(None, "a.md", Dialect::Gfm)]
+    #[case(None, "a.mdx", Dialect::Mdx)]
+    #[case(Some(Dialect::Commonmark), "a.mdx", Dialect::Commonmark)]
+    fn test_dialect_from_config_or_extension(
+        #[case] configured: Option<Dialect>,
+        #[case] file: &str,
+        #[case] expected: Dialect,
+    ) {
+        let markdown = MarkdownConfig {
+            dialect: configured,
+            ..MarkdownConfig::default()
+        };
+        assert_eq!(markdown.dialect(Path::new(file)), expected);
+    }
+
+    #[rstest]
+    #[case(None, None, true)]
+    #[case(Some(Dialect::Commonmark), None, false)]
+    #[case(Some(Dialect::Commonmark), Some(true), true)]
+    #[case(Some(Dialect::Gfm), Some(false), false)]
+    fn test_callouts_from_dialect_or_override(
+        #[case] dialect: Option<Dialect>,
+        #[case] callouts: Option<bool>,
+        #[case] expected: bool,
+    ) {
+        let markdown = MarkdownConfig {
+            dialect,
+            callouts,
+            ..MarkdownConfig::default()
+        };
+        assert_eq!(markdown.callouts(Path::new("a.md")), expected);
+    }
+}
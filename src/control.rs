@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::Notify,
+};
+use tracing::*;
+
+use crate::session::Session;
+use crate::state::{AddFileRequest, AddFileResponse, AppState, add_file, remove_file, switch_file};
+
+/// A command sent to a running instance, one JSON object per line.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    Add(AddFileRequest),
+    Remove(AddFileRequest),
+    Switch(AddFileRequest),
+    Stop,
+}
+
+/// The control socket of `session` inside the glypho XDG runtime directory.
+pub fn socket_path(session: &Session) -> eyre::Result<PathBuf> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("glypho");
+    let socket = format!("sessions/{}.sock", session.name());
+    Ok(xdg_dirs.place_runtime_file(socket)?)
+}
+
+/// Binds the control socket, replacing a stale one left by a crashed instance.
+/// Only the owner can connect to it.
+pub fn bind(path: &Path) -> eyre::Result<UnixListener> {
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+pub async fn serve(listener: UnixListener, state: Arc<AppState>, shutdown: Arc<Notify>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!("Control socket error: {err}");
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(stream, state, shutdown).await {
+                warn!("Control connection error: {err}");
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    state: Arc<AppState>,
+    shutdown: Arc<Notify>,
+) -> eyre::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let ok = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => dispatch(request, state.clone(), &shutdown).await,
+            Err(err) => {
+                debug!("Invalid control request: {err}");
+                false
+            }
+        };
+
+        let mut response = serde_json::to_vec(&AddFileResponse { ok })?;
+        response.push(b'\n');
+        writer.write_all(&response).await?;
+    }
+
+    Ok(())
+}
+
+async fn dispatch(request: ControlRequest, state: Arc<AppState>, shutdown: &Notify) -> bool {
+    match request {
+        ControlRequest::Add(AddFileRequest { file }) => {
+            if !file.is_file() {
+                return false;
+            }
+            info!("Adding {}", file.display());
+            add_file(state, file).await;
+            true
+        }
+        ControlRequest::Remove(AddFileRequest { file }) => remove_file(state, file).await,
+        ControlRequest::Switch(AddFileRequest { file }) => switch_file(state, file).await,
+        ControlRequest::Stop => {
+            shutdown.notify_one();
+            true
+        }
+    }
+}
+
+/// Sends `request` to the instance listening on `path`.
+pub async fn send(path: &Path, request: &ControlRequest) -> eyre::Result<AddFileResponse> {
+    let stream = UnixStream::connect(path).await?;
+    let (reader, mut writer) = stream.into_split();
+
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+
+    let response = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| eyre::eyre!("Control socket closed without a response"))?;
+
+    Ok(serde_json::from_str(&response)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::InnerState;
+    use tempfile::TempDir;
+    use tokio::sync::Mutex;
+
+    fn start(dir: &TempDir, file: PathBuf) -> (PathBuf, Arc<AppState>, Arc<Notify>) {
+        let path = dir.path().join("control.sock");
+        let listener = bind(&path).unwrap();
+        let state = Arc::new(Mutex::new(InnerState::new(file)));
+        let shutdown = Arc::new(Notify::new());
+        tokio::spawn(serve(listener, state.clone(), shutdown.clone()));
+        (path, state, shutdown)
+    }
+
+    // ==================== Wire Format Tests ====================
+
+    #[test]
+    fn test_request_wire_format() {
+        let request = ControlRequest::Add(AddFileRequest {
+            file: PathBuf::from("/tmp/test.md"),
+        });
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(json, r#"{"command":"add","file":"/tmp/test.md"}"#);
+
+        let json = serde_json::to_string(&ControlRequest::Stop).unwrap();
+        assert_eq!(json, r#"{"command":"stop"}"#);
+    }
+
+    #[test]
+    fn test_request_unknown_command_rejected() {
+        let result = serde_json::from_str::<ControlRequest>(r#"{"command":"delete"}"#);
+        assert!(result.is_err());
+    }
+
+    // ==================== Socket Tests ====================
+
+    #[tokio::test]
+    async fn test_bind_is_owner_only() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("control.sock");
+        let _listener = bind(&path).unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[tokio::test]
+    async fn test_bind_replaces_stale_socket() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("control.sock");
+        std::fs::write(&path, "").unwrap();
+
+        assert!(bind(&path).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_add_and_switch_over_socket() {
+        let dir = TempDir::new().unwrap();
+        let file1 = dir.path().join("file1.md");
+        let file2 = dir.path().join("file2.md");
+        std::fs::write(&file1, "# File 1").unwrap();
+        std::fs::write(&file2, "# File 2").unwrap();
+        let (path, state, _) = start(&dir, file1.clone());
+
+        let add = ControlRequest::Add(AddFileRequest {
+            file: file2.clone(),
+        });
+        assert!(send(&path, &add).await.unwrap().ok);
+
+        let switch = ControlRequest::Switch(AddFileRequest {
+            file: file2.clone(),
+        });
+        assert!(send(&path, &switch).await.unwrap().ok);
+        assert_eq!(state.lock().await.active_file(), file2.as_path());
+    }
+
+    #[tokio::test]
+    async fn test_add_missing_file_fails() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file.md");
+        std::fs::write(&file, "# File").unwrap();
+        let (path, _, _) = start(&dir, file);
+
+        let add = ControlRequest::Add(AddFileRequest {
+            file: dir.path().join("missing.md"),
+        });
+        assert!(!send(&path, &add).await.unwrap().ok);
+    }
+
+    #[tokio::test]
+    async fn test_remove_over_socket() {
+        let dir = TempDir::new().unwrap();
+        let file1 = dir.path().join("file1.md");
+        let file2 = dir.path().join("file2.md");
+        std::fs::write(&file1, "# File 1").unwrap();
+        std::fs::write(&file2, "# File 2").unwrap();
+        let (path, state, _) = start(&dir, file1.clone());
+
+        let add = ControlRequest::Add(AddFileRequest {
+            file: file2.clone(),
+        });
+        assert!(send(&path, &add).await.unwrap().ok);
+
+        let remove = ControlRequest::Remove(AddFileRequest {
+            file: file1.clone(),
+        });
+        assert!(send(&path, &remove).await.unwrap().ok);
+        assert_eq!(state.lock().await.active_file(), file2.as_path());
+
+        // the last file stays
+        let remove = ControlRequest::Remove(AddFileRequest { file: file2 });
+        assert!(!send(&path, &remove).await.unwrap().ok);
+    }
+
+    #[tokio::test]
+    async fn test_stop_notifies_shutdown() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file.md");
+        std::fs::write(&file, "# File").unwrap();
+        let (path, _, shutdown) = start(&dir, file);
+
+        let notified = shutdown.notified();
+        assert!(send(&path, &ControlRequest::Stop).await.unwrap().ok);
+        tokio::time::timeout(std::time::Duration::from_secs(1), notified)
+            .await
+            .expect("shutdown was not requested");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_request_gets_error_response() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file.md");
+        std::fs::write(&file, "# File").unwrap();
+        let (path, _, _) = start(&dir, file);
+
+        let stream = UnixStream::connect(&path).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        writer.write_all(b"not json\n").await.unwrap();
+        let line = BufReader::new(reader).lines().next_line().await.unwrap();
+
+        let response: AddFileResponse = serde_json::from_str(&line.unwrap()).unwrap();
+        assert!(!response.ok);
+    }
+}
@@ -0,0 +1,269 @@
+use markdown::{message::Place, unist::Point};
+use std::{
+    io::IsTerminal,
+    path::{Path, PathBuf},
+};
+
+use crate::{error::GlyphoError, frontmatter::escape_html};
+
+// Lines of source shown before and after the failing one.
+const CONTEXT: usize = 2;
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Why a document could not be rendered, shown above its last good render
+/// and printed in the terminal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    pub message: String,
+    /// `source:rule-id` of the Markdown parser
+    pub rule: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// Numbered source lines around `line`
+    pub snippet: Vec<(usize, String)>,
+}
+
+impl Diagnostic {
+    /// The diagnostic of a failed render of `file`, with the source around
+    /// the error when the parser reported where it is. The source is the
+    /// text that was parsed, the file may have changed since.
+    pub fn new(file: &Path, err: &eyre::Report) -> Self {
+        let mut diagnostic = Diagnostic {
+            file: file.to_path_buf(),
+            message: err.to_string(),
+            rule: None,
+            line: None,
+            column: None,
+            snippet: vec![],
+        };
+
+        if let Some(GlyphoError::MarkdownError {
+            place,
+            reason,
+            rule_id,
+            m_source,
+            content,
+        }) = err.downcast_ref::<GlyphoError>()
+        {
+            diagnostic.message = reason.clone();
+            diagnostic.rule = Some(format!("{m_source}:{rule_id}"));
+            if let Some(point) = place.as_deref().map(start) {
+                diagnostic.line = Some(point.line);
+                diagnostic.column = Some(point.column);
+                diagnostic.snippet = snippet(content, point.line);
+            }
+        }
+        diagnostic
+    }
+
+    /// `file:line:column` of the error.
+    pub fn location(&self) -> String {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => format!("{}:{line}:{column}", self.file.display()),
+            _ => self.file.display().to_string(),
+        }
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut html = format!(
+            "<div class=\"diagnostic\" role=\"alert\">\n\
+             <p class=\"diagnostic-title\"><strong>Cannot render</strong> <code>{}</code></p>\n\
+             <p class=\"diagnostic-message\">{}",
+            escape_html(&self.location()),
+            escape_html(&self.message)
+        );
+        if let Some(rule) = &self.rule {
+            html.push_str(&format!(
+                " <span class=\"diagnostic-rule\">{}</span>",
+                escape_html(rule)
+            ));
+        }
+        html.push_str("</p>\n");
+
+        if !self.snippet.is_empty() {
+            html.push_str("<pre class=\"diagnostic-snippet\">");
+            for (number, line) in &self.snippet {
+                let line = escape_html(line);
+                if Some(*number) == self.line {
+                    html.push_str(&format!(
+                        "<mark><span class=\"diagnostic-line\">{number:>4}</span> {line}</mark>\n"
+                    ));
+                    html.push_str(&format!(
+                        "<span class=\"diagnostic-caret\">{}^</span>\n",
+                        " ".repeat(4 + self.column.unwrap_or(1))
+                    ));
+                } else {
+                    html.push_str(&format!(
+                        "<span class=\"diagnostic-line\">{number:>4}</span> {line}\n"
+                    ));
+                }
+            }
+            html.push_str("</pre>\n");
+        }
+        html.push_str("</div>\n");
+        html
+    }
+
+    /// The diagnostic for the terminal, colored like compiler errors.
+    pub fn to_terminal(&self, color: bool) -> String {
+        let paint = |code: &'static str| if color { code } else { "" };
+        let (red, blue, bold, dim, reset) = (
+            paint(RED),
+            paint(BLUE),
+            paint(BOLD),
+            paint(DIM),
+            paint(RESET),
+        );
+
+        let mut out = format!("{red}error{reset}{bold}: {}{reset}", self.message);
+        if let Some(rule) = &self.rule {
+            out.push_str(&format!(" {dim}[{rule}]{reset}"));
+        }
+        out.push_str(&format!("\n  {blue}-->{reset} {}\n", self.location()));
+
+        for (number, line) in &self.snippet {
+            out.push_str(&format!("{blue}{number:>4} |{reset} {line}\n"));
+            if Some(*number) == self.line {
+                let column = self.column.unwrap_or(1);
+                out.push_str(&format!(
+                    "{blue}     |{reset} {}{red}^{reset}\n",
+                    " ".repeat(column - 1)
+                ));
+            }
+        }
+        out
+    }
+
+    /// Prints the diagnostic on stderr, in color when it is a terminal.
+    pub fn print(&self) {
+        let stderr = std::io::stderr();
+        let color = stderr.is_terminal() && std::env::var_os("NO_COLOR").is_none();
+        eprint!("{}", self.to_terminal(color));
+    }
+}
+
+fn start(place: &Place) -> &Point {
+    match place {
+        Place::Position(position) => &position.start,
+        Place::Point(point) => point,
+    }
+}
+
+fn snippet(content: &str, line: usize) -> Vec<(usize, String)> {
+    let first = line.saturating_sub(CONTEXT).max(1);
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, text)| (index + 1, text.to_string()))
+        .skip(first - 1)
+        .take(line + CONTEXT + 1 - first)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dialect::Dialect, testing::options};
+    use insta::assert_snapshot;
+
+    const MDX: &str = "# Title\n\nSome text\n\n<Chart size=1 />\n\nMore text\n";
+
+    // A document the MDX parser rejects and the error it reports. The file
+    // is not on disk, the source comes with the error.
+    fn mdx_error() -> (PathBuf, eyre::Report) {
+        let message = markdown::to_html_with_options(MDX, &options(Dialect::Mdx)).unwrap_err();
+        (
+            PathBuf::from("/docs/page.mdx"),
+            GlyphoError::markdown(message, MDX).into(),
+        )
+    }
+
+    // ==================== Diagnostic Tests ====================
+
+    #[test]
+    fn test_markdown_error_located() {
+        let (file, err) = mdx_error();
+        let diagnostic = Diagnostic::new(&file, &err);
+
+        assert_eq!(diagnostic.line, Some(5));
+        assert!(diagnostic.column.is_some());
+        assert_eq!(
+            diagnostic.rule.as_deref(),
+            Some("markdown-rs:unexpected-character")
+        );
+        assert_eq!(diagnostic.snippet.first().unwrap().0, 3);
+        assert_eq!(diagnostic.snippet.last().unwrap().0, 7);
+    }
+
+    #[test]
+    fn test_io_error_without_location() {
+        let err = eyre::eyre!("The file or directory does not exist");
+        let diagnostic = Diagnostic::new(Path::new("/docs/gone.md"), &err);
+
+        assert_eq!(diagnostic.location(), "/docs/gone.md");
+        assert!(diagnostic.snippet.is_empty());
+        assert!(
+            diagnostic
+                .to_html()
+                .contains("The file or directory does not exist")
+        );
+    }
+
+    #[test]
+    fn test_snippet_at_start_of_file() {
+        assert_eq!(
+            snippet("one\ntwo\nthree\nfour", 1),
+            vec![
+                (1, "one".to_string()),
+                (2, "two".to_string()),
+                (3, "three".to_string())
+            ]
+        );
+    }
+
+    // ==================== Output Tests ====================
+
+    #[test]
+    fn test_html_snapshot() {
+        let (file, err) = mdx_error();
+        let diagnostic = Diagnostic::new(&file, &err);
+        assert_snapshot!(diagnostic.to_html());
+    }
+
+    #[test]
+    fn test_html_escaped() {
+        let diagnostic = Diagnostic {
+            file: PathBuf::from("/docs/<b>.md"),
+            message: "<script>".to_string(),
+            rule: None,
+            line: Some(1),
+            column: Some(1),
+            snippet: vec![(1, "<img onerror=x>".to_string())],
+        };
+        let html = diagnostic.to_html();
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<b>"));
+        assert!(!html.contains("<img"));
+    }
+
+    #[test]
+    fn test_terminal_output() {
+        let (file, err) = mdx_error();
+        let diagnostic = Diagnostic::new(&file, &err);
+
+        let plain = diagnostic.to_terminal(false);
+        assert!(plain.starts_with("error: "));
+        assert!(plain.contains(&format!("--> {}:5:", file.display())));
+        assert!(plain.contains("   5 | <Chart size=1 />\n     |             ^\n"));
+        assert!(!plain.contains('\x1b'));
+
+        let colored = diagnostic.to_terminal(true);
+        assert!(colored.starts_with("\x1b[1;31merror\x1b[0m"));
+    }
+}
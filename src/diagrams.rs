@@ -0,0 +1,450 @@
+use lazy_regex::*;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    thread,
+    time::Duration,
+};
+use tokio::{process::Command, time::timeout};
+
+use crate::{
+    config::{DiagramsConfig, command_words},
+    frontmatter::escape_html,
+};
+
+/// Rendered diagrams by renderer and source, so that editing the text
+/// around a diagram does not run the renderer again.
+pub type DiagramCache = HashMap<u64, Result<String, String>>;
+
+// Mermaid starts a headless browser, which takes a while on the first run.
+const TIMEOUT: Duration = Duration::from_secs(30);
+const CACHE_SIZE: usize = 256;
+
+static BLOCK: Lazy<Regex> =
+    lazy_regex!(r#"<pre><code class="language-([\w-]+)">([\s\S]*?)</code></pre>"#);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Renderer {
+    Mermaid,
+    Dot,
+}
+
+impl Renderer {
+    fn for_language(language: &str) -> Option<Self> {
+        match language {
+            "mermaid" => Some(Renderer::Mermaid),
+            "dot" | "graphviz" => Some(Renderer::Dot),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Renderer::Mermaid => "mermaid",
+            Renderer::Dot => "dot",
+        }
+    }
+
+    fn command(self, config: &DiagramsConfig) -> &str {
+        match self {
+            Renderer::Mermaid => &config.mermaid,
+            Renderer::Dot => &config.dot,
+        }
+    }
+
+    fn args(self, input: &Path, output: &Path) -> Vec<PathBuf> {
+        let (input, output) = (input.to_path_buf(), output.to_path_buf());
+        match self {
+            Renderer::Mermaid => vec!["-q".into(), "-i".into(), input, "-o".into(), output],
+            Renderer::Dot => vec!["-Tsvg".into(), "-o".into(), output, input],
+        }
+    }
+
+    fn install_hint(self) -> &'static str {
+        match self {
+            Renderer::Mermaid => "install mermaid-cli (npm install -g @mermaid-js/mermaid-cli)",
+            Renderer::Dot => "install Graphviz",
+        }
+    }
+}
+
+/// Adds a note below the diagram code blocks of a document outside of the
+/// `trusted_roots`, which are not handed to the renderers.
+pub fn untrusted_diagrams(html: &str) -> String {
+    BLOCK
+        .replace_all(html, |caps: &Captures| match Renderer::for_language(&caps[1]) {
+            Some(_) => format!(
+                "{}<div class=\"diagram-note\">Diagrams are only rendered in documents under the <code>trusted_roots</code> of the configuration</div>",
+                &caps[0]
+            ),
+            None => caps[0].to_string(),
+        })
+        .into_owned()
+}
+
+/// A diagram code block whose renderer has not run yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagram {
+    key: u64,
+    renderer: Renderer,
+    command: String,
+    source: String,
+}
+
+/// Rendered diagrams to keep in a `DiagramCache`.
+pub type Rendered = Vec<(u64, Result<String, String>)>;
+
+/// Replaces the diagram code blocks of rendered HTML with their SVG from
+/// `cache`. A block that cannot be rendered stays as code with the error
+/// below it, one not rendered yet stays as code and is added to `pending`.
+pub fn render_diagrams(
+    html: &str,
+    config: &DiagramsConfig,
+    cache: &DiagramCache,
+    pending: &mut Vec<Diagram>,
+) -> String {
+    BLOCK
+        .replace_all(html, |caps: &Captures| {
+            let Some(renderer) = Renderer::for_language(&caps[1]) else {
+                return caps[0].to_string();
+            };
+            let source = unescape_html(&caps[2]);
+            let command = renderer.command(config);
+
+            let mut hasher = DefaultHasher::new();
+            (renderer, command, &source).hash(&mut hasher);
+            let key = hasher.finish();
+            let Some(rendered) = cache.get(&key) else {
+                if !pending.iter().any(|diagram| diagram.key == key) {
+                    pending.push(Diagram {
+                        key,
+                        renderer,
+                        command: command.to_string(),
+                        source,
+                    });
+                }
+                return caps[0].to_string();
+            };
+
+            match rendered {
+                // as an image the SVG cannot run scripts, even from untrusted documents
+                Ok(svg) => format!(
+                    "<figure class=\"diagram diagram-{0}\"><img alt=\"{0} diagram\" src=\"data:image/svg+xml,{1}\"></figure>",
+                    renderer.name(),
+                    utf8_percent_encode(svg, NON_ALPHANUMERIC)
+                ),
+                Err(err) => format!(
+                    "{}<div class=\"diagram-error\">{}</div>",
+                    &caps[0],
+                    escape_html(err)
+                ),
+            }
+        })
+        .into_owned()
+}
+
+/// Runs the renderers of `diagrams`, without blocking the runtime.
+pub async fn render_all(diagrams: Vec<Diagram>) -> Rendered {
+    let mut rendered = Vec::with_capacity(diagrams.len());
+    for diagram in diagrams {
+        let result = render(diagram.renderer, &diagram.command, &diagram.source).await;
+        rendered.push((diagram.key, result));
+    }
+    rendered
+}
+
+/// `render_all` for callers outside of the server, which may run inside
+/// the runtime of `main` but do not hold its state.
+pub fn render_all_blocking(diagrams: Vec<Diagram>) -> Rendered {
+    thread::spawn(move || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map(|runtime| runtime.block_on(render_all(diagrams)))
+            .unwrap_or_default()
+    })
+    .join()
+    .unwrap_or_default()
+}
+
+/// Keeps `rendered` diagrams in `cache`.
+pub fn cache_rendered(cache: &mut DiagramCache, rendered: Rendered) {
+    if cache.len() + rendered.len() > CACHE_SIZE {
+        cache.clear();
+    }
+    cache.extend(rendered);
+}
+
+async fn render(renderer: Renderer, command: &str, source: &str) -> Result<String, String> {
+    let Some(words) = command_words(command) else {
+        return Err(format!(
+            "The {} command of the configuration has an unclosed quote",
+            renderer.name()
+        ));
+    };
+    let mut words = words.into_iter();
+    let Some(program) = words.next() else {
+        return Err(format!("No {} command configured", renderer.name()));
+    };
+
+    // a private directory only this render uses, removed when dropped
+    let dir = tempfile::Builder::new()
+        .prefix("glypho-diagram-")
+        .tempdir()
+        .map_err(|err| format!("Cannot render the diagram: {err}"))?;
+    run(renderer, &program, words.collect(), dir.path(), source).await
+}
+
+async fn run(
+    renderer: Renderer,
+    program: &str,
+    extra_args: Vec<String>,
+    dir: &Path,
+    source: &str,
+) -> Result<String, String> {
+    let (input, output, errors) = (
+        dir.join("input"),
+        dir.join("output.svg"),
+        dir.join("errors"),
+    );
+    let io_error = |err: io::Error| format!("Cannot render the diagram: {err}");
+    fs::write(&input, source).map_err(io_error)?;
+
+    // the renderer writes files instead of pipes, so that a large diagram
+    // cannot block it while it is waited for
+    let child = Command::new(program)
+        .args(extra_args)
+        .args(renderer.args(&input, &output))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(File::create(&errors).map_err(io_error)?)
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(format!(
+                "{program} was not found, {} to render {} diagrams",
+                renderer.install_hint(),
+                renderer.name()
+            ));
+        }
+        Err(err) => return Err(io_error(err)),
+    };
+
+    let status = match timeout(TIMEOUT, child.wait()).await {
+        Ok(status) => status.map_err(io_error)?,
+        Err(_) => {
+            let _ = child.kill().await;
+            return Err(format!("{program} did not finish within {TIMEOUT:?}"));
+        }
+    };
+
+    if !status.success() {
+        let errors = fs::read_to_string(&errors).unwrap_or_default();
+        return Err(match errors.trim() {
+            "" => format!("{program} failed with {status}"),
+            errors => errors.to_string(),
+        });
+    }
+    let svg = fs::read_to_string(&output).map_err(io_error)?;
+    Ok(strip_prolog(&svg).to_string())
+}
+
+// Graphviz starts its output with an XML declaration and a doctype.
+fn strip_prolog(svg: &str) -> &str {
+    svg.find("<svg").map_or(svg, |start| &svg[start..])
+}
+
+fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    const MERMAID: &str =
+        "<pre><code class=\"language-mermaid\">graph TD;\nA--&gt;B;\n</code></pre>";
+
+    // A stand-in renderer wrapping its input in `<svg>` into the file after
+    // `-o`, or failing with `error` on stderr.
+    fn fake_renderer(temp_dir: &TempDir, error: Option<&str>) -> String {
+        let path = temp_dir.path().join("renderer");
+        let body = match error {
+            Some(error) => format!("echo '{error}' >&2\nexit 1"),
+            None => "while [ $# -gt 0 ]; do\n  if [ \"$1\" = -o ]; then out=\"$2\"; fi\n  shift\ndone\n\
+                     printf '<?xml version=\"1.0\"?>\\n<svg>%s</svg>' \"$(cat \"$(dirname \"$out\")/input\")\" > \"$out\""
+                .to_string(),
+        };
+        fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    // Renders the diagrams of `html` the way the preview does, a second time
+    // once their renderers ran.
+    fn render_twice(html: &str, config: &DiagramsConfig, cache: &mut DiagramCache) -> String {
+        let mut pending = Vec::new();
+        render_diagrams(html, config, cache, &mut pending);
+        cache_rendered(cache, render_all_blocking(pending));
+        render_diagrams(html, config, cache, &mut Vec::new())
+    }
+
+    fn config(command: &str) -> DiagramsConfig {
+        DiagramsConfig {
+            mermaid: command.to_string(),
+            dot: command.to_string(),
+            ..DiagramsConfig::default()
+        }
+    }
+
+    // ==================== Rendering Tests ====================
+
+    #[rstest]
+    #[case("mermaid", "diagram-mermaid")]
+    #[case("dot", "diagram-dot")]
+    #[case("graphviz", "diagram-dot")]
+    fn test_diagram_rendered_as_image(#[case] language: &str, #[case] class: &str) {
+        let temp_dir = TempDir::new().unwrap();
+        let command = fake_renderer(&temp_dir, None);
+        let html = format!("<pre><code class=\"language-{language}\">A--&gt;B</code></pre>");
+
+        let html = render_twice(&html, &config(&command), &mut DiagramCache::new());
+        assert!(html.starts_with(&format!("<figure class=\"diagram {class}\"><img")));
+        // the renderer gets the unescaped source and its prolog is dropped
+        assert!(html.contains("src=\"data:image/svg+xml,%3Csvg%3EA%2D%2D%3EB%3C%2Fsvg%3E\""));
+        assert!(!html.contains("<pre>"));
+    }
+
+    #[test]
+    fn test_prolog_stripped() {
+        let svg = "<?xml version=\"1.0\"?>\n<!DOCTYPE svg>\n<svg></svg>";
+        assert_eq!(strip_prolog(svg), "<svg></svg>");
+    }
+
+    #[test]
+    fn test_source_unescaped() {
+        assert_eq!(unescape_html("A--&gt;B &amp;&lt;"), "A-->B &<");
+        assert_eq!(unescape_html("&amp;lt;"), "&lt;");
+    }
+
+    #[test]
+    fn test_other_code_blocks_untouched() {
+        let html = "<pre><code class=\"language-rust\">fn main() {}</code></pre>";
+        assert_eq!(
+            render_twice(html, &DiagramsConfig::default(), &mut DiagramCache::new()),
+            html
+        );
+    }
+
+    // ==================== Error Tests ====================
+
+    #[test]
+    fn test_missing_renderer_shown_below_block() {
+        let html = render_twice(
+            MERMAID,
+            &config("/nonexistent/mmdc"),
+            &mut DiagramCache::new(),
+        );
+
+        assert!(html.starts_with(MERMAID));
+        assert!(html.contains("<div class=\"diagram-error\">/nonexistent/mmdc was not found"));
+    }
+
+    #[test]
+    fn test_quoted_command_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("Mermaid CLI");
+        fs::create_dir(&dir).unwrap();
+        let renderer = fake_renderer(&temp_dir, None);
+        let command = dir.join("mmdc");
+        fs::rename(&renderer, &command).unwrap();
+
+        let config = config(&format!("\"{}\"", command.display()));
+        let html = render_twice(MERMAID, &config, &mut DiagramCache::new());
+        assert!(html.starts_with("<figure class=\"diagram diagram-mermaid\"><img"));
+    }
+
+    #[test]
+    fn test_unclosed_quote_not_run() {
+        let html = render_twice(
+            MERMAID,
+            &config("\"/opt/Mermaid CLI/mmdc"),
+            &mut DiagramCache::new(),
+        );
+        assert!(html.contains(
+            "<div class=\"diagram-error\">The mermaid command of the configuration has an unclosed quote"
+        ));
+    }
+
+    #[test]
+    fn test_failing_diagram_shows_error() {
+        let temp_dir = TempDir::new().unwrap();
+        let command = fake_renderer(&temp_dir, Some("Parse error on line 2"));
+        let html = format!("{MERMAID}<p>after</p>");
+
+        let html = render_twice(&html, &config(&command), &mut DiagramCache::new());
+        assert!(html.contains("</pre><div class=\"diagram-error\">Parse error on line 2</div>"));
+        assert!(html.ends_with("<p>after</p>"));
+    }
+
+    #[test]
+    fn test_error_escaped() {
+        let temp_dir = TempDir::new().unwrap();
+        let command = fake_renderer(&temp_dir, Some("<img src=x onerror=alert(1)>"));
+
+        let html = render_twice(MERMAID, &config(&command), &mut DiagramCache::new());
+        assert!(html.contains("&lt;img src=x onerror=alert(1)&gt;"));
+    }
+
+    #[test]
+    fn test_untrusted_diagrams_noted() {
+        let rust = "<pre><code class=\"language-rust\">fn main() {}</code></pre>";
+        let html = untrusted_diagrams(&format!("{MERMAID}{rust}"));
+
+        assert!(html.starts_with(&format!("{MERMAID}<div class=\"diagram-note\">")));
+        assert!(html.ends_with(&format!("</div>{rust}")));
+    }
+
+    // ==================== Cache Tests ====================
+
+    #[test]
+    fn test_rendered_once_per_source() {
+        let temp_dir = TempDir::new().unwrap();
+        let command = fake_renderer(&temp_dir, None);
+        let mut cache = DiagramCache::new();
+
+        let first = render_twice(MERMAID, &config(&command), &mut cache);
+        fs::remove_file(temp_dir.path().join("renderer")).unwrap();
+        let second = render_twice(MERMAID, &config(&command), &mut cache);
+
+        assert_eq!(first, second);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_missing_diagrams_pending_once() {
+        let html = format!("{MERMAID}{MERMAID}");
+        let mut pending = Vec::new();
+
+        let rendered = render_diagrams(&html, &config("mmdc"), &DiagramCache::new(), &mut pending);
+        // shown as code until the renderer ran
+        assert_eq!(rendered, html);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].source, "graph TD;\nA-->B;\n");
+    }
+}
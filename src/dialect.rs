@@ -0,0 +1,178 @@
+use lazy_regex::*;
+use markdown::{CompileOptions, Constructs};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The Markdown flavour a document is written in, matching the renderer it
+/// is eventually published with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dialect {
+    /// Strict CommonMark, no extensions
+    Commonmark,
+    /// GitHub flavoured Markdown with frontmatter
+    Gfm,
+    /// GFM with `$inline$` and `$$display$$` math
+    GfmMath,
+    /// MDX: JSX and expressions instead of raw HTML
+    Mdx,
+}
+
+impl Dialect {
+    /// The dialect of `file` when none is configured, `.mdx` files are MDX.
+    pub fn for_file(file: &Path) -> Self {
+        match file.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("mdx") => Dialect::Mdx,
+            _ => Dialect::Gfm,
+        }
+    }
+
+    pub fn constructs(self) -> Constructs {
+        match self {
+            Dialect::Commonmark => Constructs::default(),
+            Dialect::Gfm => Constructs {
+                frontmatter: true,
+                ..Constructs::gfm()
+            },
+            Dialect::GfmMath => Constructs {
+                frontmatter: true,
+                math_flow: true,
+                math_text: true,
+                ..Constructs::gfm()
+            },
+            Dialect::Mdx => Constructs {
+                frontmatter: true,
+                ..Constructs::mdx()
+            },
+        }
+    }
+
+    pub fn compile(self) -> CompileOptions {
+        match self {
+            Dialect::Commonmark | Dialect::Mdx => CompileOptions {
+                allow_dangerous_html: true,
+                ..CompileOptions::default()
+            },
+            Dialect::Gfm | Dialect::GfmMath => CompileOptions {
+                allow_dangerous_html: true,
+                ..CompileOptions::gfm()
+            },
+        }
+    }
+
+    /// Whether `> [!NOTE]` blockquotes are callouts, every dialect but
+    /// strict CommonMark.
+    pub fn callouts(self) -> bool {
+        self != Dialect::Commonmark
+    }
+
+    /// Whether `~one~` tilde strikes through like on GitHub.
+    pub fn single_tilde(self) -> bool {
+        matches!(self, Dialect::Gfm | Dialect::GfmMath)
+    }
+}
+
+/// Rewrites the math elements of the `markdown` crate into the delimiters
+/// MathJax typesets in the browser.
+pub fn math_for_mathjax(html: &str) -> String {
+    let display =
+        regex!(r#"<pre><code class="language-math math-display">([\s\S]*?)\n?</code></pre>"#);
+    let inline = regex!(r#"<code class="language-math math-inline">([\s\S]*?)</code>"#);
+
+    let html = display.replace_all(html, r#"<div class="math">\[$1\]</div>"#);
+    inline.replace_all(&html, r"\($1\)").into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use markdown::{Options, ParseOptions};
+    use rstest::rstest;
+
+    fn render(input: &str, dialect: Dialect) -> String {
+        let options = Options {
+            parse: ParseOptions {
+                constructs: dialect.constructs(),
+                gfm_strikethrough_single_tilde: dialect.single_tilde(),
+                ..ParseOptions::default()
+            },
+            compile: dialect.compile(),
+        };
+        markdown::to_html_with_options(input, &options).unwrap()
+    }
+
+    // ==================== Dialect Selection Tests ====================
+
+    #[rstest]
+    #[case("notes.md", Dialect::Gfm)]
+    #[case("page.mdx", Dialect::Mdx)]
+    #[case("PAGE.MDX", Dialect::Mdx)]
+    #[case("README", Dialect::Gfm)]
+    fn test_dialect_for_file(#[case] file: &str, #[case] expected: Dialect) {
+        assert_eq!(Dialect::for_file(Path::new(file)), expected);
+    }
+
+    #[rstest]
+    #[case("\"commonmark\"", Dialect::Commonmark)]
+    #[case("\"gfm\"", Dialect::Gfm)]
+    #[case("\"gfm-math\"", Dialect::GfmMath)]
+    #[case("\"mdx\"", Dialect::Mdx)]
+    fn test_dialect_names(#[case] name: &str, #[case] expected: Dialect) {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            dialect: Dialect,
+        }
+        let parsed: Wrapper = toml::from_str(&format!("dialect = {name}")).unwrap();
+        assert_eq!(parsed.dialect, expected);
+    }
+
+    // ==================== Rendering Tests ====================
+
+    #[rstest]
+    #[case(Dialect::Commonmark, false)]
+    #[case(Dialect::Gfm, true)]
+    #[case(Dialect::GfmMath, true)]
+    fn test_tables_per_dialect(#[case] dialect: Dialect, #[case] table: bool) {
+        let html = render("| a |\n|---|\n| 1 |", dialect);
+        assert_eq!(html.contains("<table>"), table);
+    }
+
+    #[rstest]
+    #[case(Dialect::Commonmark, false)]
+    #[case(Dialect::Gfm, false)]
+    #[case(Dialect::GfmMath, true)]
+    fn test_math_per_dialect(#[case] dialect: Dialect, #[case] math: bool) {
+        let html = render("$x^2$", dialect);
+        assert_eq!(html.contains("math-inline"), math);
+    }
+
+    #[test]
+    fn test_commonmark_shows_frontmatter() {
+        let html = render("---\ntitle: x\n---\n\nbody", Dialect::Commonmark);
+        assert!(html.contains("<hr />"));
+    }
+
+    #[test]
+    fn test_mdx_drops_jsx_and_expressions() {
+        let html = render("# Hi\n\n<Chart data={points} />\n\n{1 + 1}", Dialect::Mdx);
+        assert_eq!(html, "<h1>Hi</h1>\n");
+    }
+
+    // ==================== MathJax Tests ====================
+
+    #[test]
+    fn test_math_for_mathjax() {
+        let html = render("Inline $a < b$\n\n$$\n\\sum_i i\n$$", Dialect::GfmMath);
+        let html = math_for_mathjax(&html);
+
+        assert!(html.contains(r"\(a &lt; b\)"));
+        assert!(html.contains(r#"<div class="math">\[\sum_i i\]</div>"#));
+        assert!(!html.contains("language-math"));
+    }
+
+    #[test]
+    fn test_math_for_mathjax_leaves_code_alone() {
+        let html = "<pre><code class=\"language-rust\">let x = 1;</code></pre>";
+        assert_eq!(math_for_mathjax(html), html);
+    }
+}
@@ -0,0 +1,356 @@
+use asynk_strim::{Yielder, stream_fn};
+use axum::{
+    extract::State,
+    response::{
+        IntoResponse,
+        sse::{Event, Sse},
+    },
+};
+use datastar::{
+    axum::ReadSignals,
+    consts::ElementPatchMode,
+    prelude::{ExecuteScript, PatchElements, PatchSignals},
+};
+use serde::Deserialize;
+use std::{
+    convert::Infallible,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+use tracing::*;
+
+use crate::{
+    error::GlyphoError,
+    frontmatter::escape_html,
+    state::{AppState, InnerState, render_with_diagrams, stats_patch},
+    tasks::version,
+};
+
+/// The editor of the preview page, left out in safe mode.
+pub const EDITOR: &str = include_str!("templates/editor.html");
+
+/// The text in the editor and the file it was loaded from.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SourceSignals {
+    /// Local to the page, only sent with the requests of the editor
+    #[serde(rename = "_source")]
+    pub source: String,
+    pub source_file: PathBuf,
+    /// Version of the file the source was loaded from or last saved as
+    pub source_version: String,
+}
+
+/// Replaces `path` with `content` through a temporary file next to it, so
+/// that the watcher and other programs never see a half written document.
+pub fn write_atomic(path: &Path, content: &str) -> io::Result<()> {
+    // a symlinked document keeps its link
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let name = path
+        .file_name()
+        .ok_or(io::ErrorKind::InvalidInput)?
+        .to_string_lossy();
+    // each write has its own temporary file, saves and task toggles of the
+    // same document may run at once
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let temp = path.with_file_name(format!(
+        ".{name}.glypho-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    // never opens a file left behind or planted by someone else
+    let mut file = File::options().write(true).create_new(true).open(&temp)?;
+    let mut write = || {
+        file.write_all(content.as_bytes())?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp, &path)
+    };
+    write().inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+/// Writes `signals.source` to its file unless the file changed on disk
+/// since it was loaded, and returns the version of the saved file.
+pub fn save(signals: &SourceSignals) -> Result<String, GlyphoError> {
+    let file = &signals.source_file;
+    let on_disk = fs::read_to_string(file).map_err(|err| GlyphoError::from(err.kind()))?;
+    if version(&on_disk) != signals.source_version {
+        return Err(GlyphoError::StaleDocument);
+    }
+    let content = with_line_endings(&signals.source, &on_disk);
+    write_atomic(file, &content).map_err(|err| GlyphoError::from(err.kind()))?;
+    Ok(version(&content))
+}
+
+// Browsers turn the line endings of a textarea into `\n`, a file written
+// with `\r\n` keeps them.
+fn with_line_endings(source: &str, on_disk: &str) -> String {
+    if on_disk.contains("\r\n") {
+        source.replace("\r\n", "\n").replace('\n', "\r\n")
+    } else {
+        source.to_string()
+    }
+}
+
+/// Loads the active document into the editor.
+pub async fn load_source(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let file = { state.lock().await.active_file().to_path_buf() };
+    let events = match fs::read_to_string(&file) {
+        Ok(source) => {
+            let signals = serde_json::json!({
+                "_source": source,
+                "sourceFile": file,
+                "sourceVersion": version(&source),
+            });
+            vec![
+                PatchSignals::new(signals.to_string()).write_as_axum_sse_event(),
+                status(""),
+            ]
+        }
+        Err(err) => vec![status(&format!(
+            "Cannot read {}: {err}",
+            escape_html(&file.to_string_lossy())
+        ))],
+    };
+    send(events)
+}
+
+/// Previews the text in the editor as it is typed.
+pub async fn preview_source(
+    State(state): State<Arc<AppState>>,
+    ReadSignals(signals): ReadSignals<SourceSignals>,
+) -> impl IntoResponse {
+    {
+        let s = state.lock().await;
+        // the article shows the active document, another one is not previewed
+        if s.active_file() != signals.source_file || !editable(&s, &signals.source_file) {
+            return send(vec![]);
+        }
+    }
+
+    let html =
+        render_with_diagrams(&state, |s| s.preview(&signals.source_file, &signals.source)).await;
+    let s = state.lock().await;
+    let mut events = vec![
+        PatchElements::new(html)
+            .selector("article#markdown")
+            .mode(ElementPatchMode::Inner)
+            .write_as_axum_sse_event(),
+        ExecuteScript::new(
+            "Prism.highlightAllUnder(document.querySelector('article#markdown'));MathJax.typeset();",
+        )
+        .write_as_axum_sse_event(),
+    ];
+    if let Some(stats) = s.stats(&signals.source_file) {
+        events.push(stats_patch(stats));
+    }
+    send(events)
+}
+
+/// Saves the text in the editor. The watcher renders the saved file for
+/// every preview.
+pub async fn save_source(
+    State(state): State<Arc<AppState>>,
+    ReadSignals(signals): ReadSignals<SourceSignals>,
+) -> impl IntoResponse {
+    let allowed = {
+        let s = state.lock().await;
+        editable(&s, &signals.source_file)
+    };
+    if !allowed {
+        return send(vec![status("This document cannot be edited")]);
+    }
+
+    let events = match save(&signals) {
+        Ok(version) => vec![
+            PatchSignals::new(serde_json::json!({ "sourceVersion": version }).to_string())
+                .write_as_axum_sse_event(),
+            status("Saved"),
+        ],
+        Err(GlyphoError::StaleDocument) => vec![conflict()],
+        Err(err) => {
+            warn!("Cannot save {}: {err}", signals.source_file.display());
+            vec![status(&format!(
+                "Cannot save: {}",
+                escape_html(&err.to_string())
+            ))]
+        }
+    };
+    send(events)
+}
+
+// Only documents of the session are written, and none in safe mode.
+fn editable(s: &InnerState, file: &Path) -> bool {
+    s.is_tracked(file) && !s.config_for(file).safe
+}
+
+fn status(message: &str) -> Event {
+    PatchElements::new(format!("<span id=\"editor-status\">{message}</span>"))
+        .selector("#editor-status")
+        .mode(ElementPatchMode::Outer)
+        .write_as_axum_sse_event()
+}
+
+fn conflict() -> Event {
+    PatchElements::new(
+        "<span id=\"editor-status\" class=\"editor-conflict\">The file changed on disk since it was loaded. \
+         <button type=\"button\" data-on:click=\"@get('/source')\">Load it</button> to replace your changes.</span>",
+    )
+    .selector("#editor-status")
+    .mode(ElementPatchMode::Outer)
+    .write_as_axum_sse_event()
+}
+
+fn send(events: Vec<Event>) -> impl IntoResponse {
+    let stream = stream_fn(
+        move |mut yielder: Yielder<Result<Event, Infallible>>| async move {
+            for event in events {
+                yielder.yield_item(Ok(event)).await;
+            }
+        },
+    );
+    Sse::new(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn signals(file: &Path, source: &str, loaded: &str) -> SourceSignals {
+        SourceSignals {
+            source: source.to_string(),
+            source_file: file.to_path_buf(),
+            source_version: version(loaded),
+        }
+    }
+
+    // ==================== Save Tests ====================
+
+    #[test]
+    fn test_save() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("notes.md");
+        fs::write(&file, "# Typo\n").unwrap();
+
+        let saved = save(&signals(&file, "# Fixed\n", "# Typo\n")).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "# Fixed\n");
+        assert_eq!(saved, version("# Fixed\n"));
+    }
+
+    #[rstest]
+    #[case::crlf("one\r\ntwo\r\n", "one\nthree\n", "one\r\nthree\r\n")]
+    #[case::lf("one\ntwo\n", "one\nthree\n", "one\nthree\n")]
+    fn test_save_keeps_line_endings(
+        #[case] on_disk: &str,
+        #[case] source: &str,
+        #[case] saved: &str,
+    ) {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("notes.md");
+        fs::write(&file, on_disk).unwrap();
+
+        let version = save(&signals(&file, source, on_disk)).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), saved);
+        // the next save compares with the file as written
+        assert_eq!(version, super::version(saved));
+    }
+
+    #[test]
+    fn test_source_signal_local_to_page() {
+        let signals: SourceSignals =
+            serde_json::from_str(r##"{"_source": "# Notes", "sourceFile": "/notes.md"}"##).unwrap();
+        assert_eq!(signals.source, "# Notes");
+        assert_eq!(signals.source_file, PathBuf::from("/notes.md"));
+    }
+
+    #[test]
+    fn test_conflict_not_saved() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("notes.md");
+        fs::write(&file, "# Changed elsewhere\n").unwrap();
+
+        let result = save(&signals(&file, "# Mine\n", "# Original\n"));
+        assert!(matches!(result, Err(GlyphoError::StaleDocument)));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "# Changed elsewhere\n");
+    }
+
+    #[test]
+    fn test_saves_in_a_row() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("notes.md");
+        fs::write(&file, "one").unwrap();
+
+        let version = save(&signals(&file, "two", "one")).unwrap();
+        let next = SourceSignals {
+            source_version: version,
+            ..signals(&file, "three", "")
+        };
+        save(&next).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "three");
+    }
+
+    // ==================== Atomic Write Tests ====================
+
+    #[test]
+    fn test_write_atomic_keeps_permissions() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("notes.md");
+        fs::write(&file, "old").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+
+        write_atomic(&file, "new").unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "new");
+        let mode = fs::metadata(&file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // no temporary file is left behind
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_write_atomic_follows_symlinks() {
+        let temp_dir = TempDir::new().unwrap();
+        let target = temp_dir.path().join("real.md");
+        let link = temp_dir.path().join("link.md");
+        fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write_atomic(&link, "new").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_write_atomic_in_a_row() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("notes.md");
+        fs::write(&file, "old").unwrap();
+
+        std::thread::scope(|scope| {
+            for content in ["one", "two", "three"] {
+                let file = &file;
+                scope.spawn(move || write_atomic(file, content).unwrap());
+            }
+        });
+        assert!(["one", "two", "three"].contains(&fs::read_to_string(&file).unwrap().as_str()));
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_write_atomic_missing_dir() {
+        let result = write_atomic(Path::new("/nonexistent/dir/notes.md"), "x");
+        assert!(result.is_err());
+    }
+}
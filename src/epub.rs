@@ -0,0 +1,656 @@
+use eyre::eyre;
+use handlebars::Handlebars;
+use lazy_regex::*;
+use markdown::{ParseOptions, mdast::Node};
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    assets::{doc_id, resolve, sanitize},
+    config::{Config, Overrides},
+    diagnostic::Diagnostic,
+    error::GlyphoError,
+    frontmatter::{Frontmatter, is_markdown},
+    print::URL_PATH,
+    state::{InnerState, Output},
+    xhtml::xhtml,
+    zip::ZipWriter,
+};
+
+/// An mdBook table of contents, its links are the chapters of the book.
+pub const SUMMARY: &str = "SUMMARY.md";
+
+static CONTAINER: &str = include_str!("templates/epub/container.xml");
+static PACKAGE: &str = include_str!("templates/epub/content.opf.hbs");
+static NAV: &str = include_str!("templates/epub/nav.xhtml.hbs");
+static CHAPTER: &str = include_str!("templates/epub/chapter.xhtml.hbs");
+
+static STYLES: &[&str] = &[
+    include_str!("styles/callouts.css"),
+    include_str!("styles/diagrams.css"),
+    include_str!("styles/epub.css"),
+];
+
+/// A file of the book and the title it has in the table of contents, when
+/// the `SUMMARY.md` gives one.
+#[derive(Debug, PartialEq)]
+struct Chapter {
+    file: PathBuf,
+    title: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Package {
+    identifier: String,
+    title: String,
+    language: String,
+    creator: Option<String>,
+    description: Option<String>,
+    date: Option<String>,
+    modified: String,
+    chapters: Vec<Item>,
+    resources: Vec<Resource>,
+}
+
+#[derive(Debug, Serialize)]
+struct Item {
+    id: String,
+    href: String,
+    title: String,
+    /// Has inline SVG, which the manifest has to declare
+    svg: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Resource {
+    id: String,
+    href: String,
+    media_type: &'static str,
+}
+
+// The file that failed and why, to report with its source.
+type Failure = (PathBuf, eyre::Report);
+
+fn at<E: Into<eyre::Report>>(file: &Path) -> impl FnOnce(E) -> Failure + '_ {
+    move |err| (file.to_path_buf(), err.into())
+}
+
+/// `glypho export --format epub`: writes an EPUB 3 book with one chapter
+/// per file, or per link of a `SUMMARY.md`, and returns the exit code, 2
+/// when it could not be written.
+pub fn export(
+    files: &[PathBuf],
+    output: Option<&Path>,
+    user_config: &Config,
+    overrides: &Overrides,
+) -> i32 {
+    match write_book(files, output, user_config, overrides) {
+        Ok(output) => {
+            println!("{}", output.display());
+            0
+        }
+        Err((file, err)) => {
+            Diagnostic::new(&file, &err).print();
+            2
+        }
+    }
+}
+
+fn write_book(
+    files: &[PathBuf],
+    output: Option<&Path>,
+    user_config: &Config,
+    overrides: &Overrides,
+) -> Result<PathBuf, Failure> {
+    let first = files
+        .first()
+        .ok_or_else(|| (PathBuf::new(), eyre!("No files to export")))?;
+    let first = std::path::absolute(first).map_err(at(first))?;
+    let output = match output {
+        Some(output) => std::path::absolute(output).map_err(at(output))?,
+        None => first.with_extension("epub"),
+    };
+
+    if files
+        .iter()
+        .any(|file| std::path::absolute(file).is_ok_and(|file| file == output))
+    {
+        return Err((
+            first,
+            eyre!("The export would replace {}", output.display()),
+        ));
+    }
+    let book = book(files, user_config, overrides, SystemTime::now())?;
+    let mut zip = ZipWriter::new();
+    for (name, content) in &book {
+        zip.add(name, content).map_err(at(&output))?;
+    }
+    let archive = zip.finish().map_err(at(&output))?;
+    fs::write(&output, archive).map_err(at(&output))?;
+    Ok(output)
+}
+
+/// The files of the book `files` make, by their name in the archive, the
+/// `mimetype` first.
+fn book(
+    files: &[PathBuf],
+    user_config: &Config,
+    overrides: &Overrides,
+    now: SystemTime,
+) -> Result<Vec<(String, Vec<u8>)>, Failure> {
+    let files = files
+        .iter()
+        .map(|file| std::path::absolute(file).map_err(at(file)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let summary = match files.as_slice() {
+        [file] if file.file_name().is_some_and(|name| name == SUMMARY) => Some(file.clone()),
+        _ => None,
+    };
+    let chapters = match &summary {
+        Some(summary) => summary_chapters(summary).map_err(at(summary))?,
+        None => files
+            .iter()
+            .map(|file| Chapter {
+                file: file.clone(),
+                title: None,
+            })
+            .collect(),
+    };
+    let chapter_files: Vec<PathBuf> = chapters
+        .iter()
+        .map(|chapter| fs::canonicalize(&chapter.file).unwrap_or_else(|_| chapter.file.clone()))
+        .collect();
+
+    // the metadata of the book is the frontmatter of the summary, or of the
+    // first chapter
+    let frontmatter = summary
+        .iter()
+        .chain(chapters.first().map(|chapter| &chapter.file))
+        .find_map(|file| Frontmatter::parse(&fs::read_to_string(file).ok()?))
+        .unwrap_or_default();
+    let language = frontmatter
+        .field(&["lang", "language"])
+        .unwrap_or("en")
+        .to_string();
+
+    let handlebars = Handlebars::new();
+    let render = |template: &str, data: serde_json::Value| {
+        handlebars
+            .render_template(template, &data)
+            .expect("built-in EPUB template renders")
+    };
+
+    let mut archive = vec![
+        ("mimetype".to_string(), b"application/epub+zip".to_vec()),
+        (
+            "META-INF/container.xml".to_string(),
+            CONTAINER.as_bytes().to_vec(),
+        ),
+        ("OEBPS/style.css".to_string(), STYLES.concat().into_bytes()),
+    ];
+    let mut resources = BTreeMap::new();
+    let mut items = vec![];
+    for (n, chapter) in chapters.iter().enumerate() {
+        let file = &chapter.file;
+        let mut state = InnerState::new(file.clone())
+            .with_config(user_config.clone())
+            .with_overrides(overrides.clone());
+        let html = state
+            .with_diagrams(|s| s.render_as(file, Output::Epub))
+            .map_err(at(file))?;
+        let title = chapter
+            .title
+            .clone()
+            .or_else(|| Frontmatter::parse(&fs::read_to_string(file).ok()?)?.title)
+            .or_else(|| Some(state.stats(file)?.outline.first()?.text.clone()))
+            .unwrap_or_else(|| state.label(file));
+
+        let body = xhtml(&book_links(&html, file, &chapter_files, &mut resources));
+        let href = format!("chapter-{}.xhtml", n + 1);
+        let page = render(
+            CHAPTER,
+            serde_json::json!({ "title": title, "language": language, "body": body }),
+        );
+        archive.push((format!("OEBPS/{href}"), page.into_bytes()));
+        items.push(Item {
+            id: format!("chapter-{}", n + 1),
+            href,
+            title,
+            svg: body.contains("<svg"),
+        });
+    }
+
+    let mut manifest = vec![];
+    for (n, (href, file)) in resources.into_iter().enumerate() {
+        let content = fs::read(&file).map_err(at(&file))?;
+        archive.push((format!("OEBPS/{href}"), content));
+        manifest.push(Resource {
+            id: format!("file-{}", n + 1),
+            media_type: media_type(&file).unwrap_or("application/octet-stream"),
+            href: utf8_percent_encode(&href, URL_PATH).to_string(),
+        });
+    }
+
+    let package = Package {
+        identifier: format!("urn:glypho:{}", doc_id(&files[0])),
+        title: frontmatter
+            .title
+            .clone()
+            .or_else(|| items.first().map(|item| item.title.clone()))
+            .unwrap_or_default(),
+        language,
+        creator: frontmatter
+            .field(&["author", "authors", "creator"])
+            .map(String::from),
+        description: frontmatter.field(&["description"]).map(String::from),
+        date: frontmatter
+            .field(&["date"])
+            .filter(|date| regex_is_match!(r"^\d{4}(-\d\d){0,2}$", date))
+            .map(String::from),
+        modified: timestamp(now),
+        chapters: items,
+        resources: manifest,
+    };
+    let package = serde_json::to_value(&package).expect("package serializes");
+    archive.push((
+        "OEBPS/nav.xhtml".to_string(),
+        render(NAV, package.clone()).into_bytes(),
+    ));
+    archive.push((
+        "OEBPS/content.opf".to_string(),
+        render(PACKAGE, package).into_bytes(),
+    ));
+    Ok(archive)
+}
+
+/// The chapters a `SUMMARY.md` links to, in order, titled by their link.
+fn summary_chapters(summary: &Path) -> eyre::Result<Vec<Chapter>> {
+    let content = fs::read_to_string(summary)?;
+    let tree = markdown::to_mdast(&content, &ParseOptions::gfm())
+        .map_err(|message| GlyphoError::markdown(message, &content))?;
+    let dir = summary.parent().unwrap_or(Path::new("/"));
+
+    let mut links = vec![];
+    summary_links(&tree, &mut links);
+    let mut chapters: Vec<Chapter> = vec![];
+    for (url, title) in links {
+        // drafts have no file yet, and a chapter is only in the book once
+        let path = url.split(['#', '?']).next().unwrap_or_default();
+        let Some(relative) = sanitize(path).filter(|_| !url.contains("://")) else {
+            continue;
+        };
+        let file = dir.join(relative);
+        if is_markdown(&file) && !chapters.iter().any(|chapter| chapter.file == file) {
+            chapters.push(Chapter {
+                file,
+                title: Some(title),
+            });
+        }
+    }
+
+    if chapters.is_empty() {
+        return Err(eyre!("{SUMMARY} does not link to any chapter"));
+    }
+    Ok(chapters)
+}
+
+fn summary_links(node: &Node, links: &mut Vec<(String, String)>) {
+    if let Node::Link(link) = node {
+        links.push((link.url.clone(), node.to_string().trim().to_string()));
+        return;
+    }
+    for child in node.children().into_iter().flatten() {
+        summary_links(child, links);
+    }
+}
+
+/// Points the local links of a rendered chapter into the book: links to
+/// other chapters at their XHTML file, images at a copy added to
+/// `resources` by its name in the book. Anything else keeps the relative
+/// link the document was written with.
+fn book_links(
+    html: &str,
+    file: &Path,
+    chapters: &[PathBuf],
+    resources: &mut BTreeMap<String, PathBuf>,
+) -> String {
+    let dir = file.parent().unwrap_or(Path::new("/"));
+    let id = doc_id(file);
+    let prefix = format!("/files/{id}/");
+    let chapter = |path: &str, fragment: &str| {
+        let path = percent_decode_str(path).decode_utf8().ok()?;
+        let target = fs::canonicalize(dir.join(path.as_ref())).ok()?;
+        let n = chapters.iter().position(|chapter| *chapter == target)?;
+        Some(format!("chapter-{}.xhtml{fragment}", n + 1))
+    };
+
+    regex_replace_all!(
+        r#"\b(src|href)="([^"]*)""#,
+        html,
+        |attr: &str, name: &str, url: &str| {
+            let local = url.strip_prefix(&prefix);
+            // links out of the directory of the document are not served,
+            // and not rewritten, but may still lead to another chapter
+            let relative_up = url.starts_with("../") || url.starts_with("./../");
+            let Some(url) = local.or(relative_up.then_some(url)) else {
+                return attr.to_string();
+            };
+            let end = url.find(['#', '?']).unwrap_or(url.len());
+            let fragment = url[end..].strip_prefix('?').map_or(&url[end..], |_| "");
+            if let Some(href) = chapter(&url[..end], fragment) {
+                return format!("{name}=\"{href}\"");
+            }
+            let Some(relative) = sanitize(&url[..end]).filter(|_| local.is_some()) else {
+                return format!("{name}=\"{url}\"");
+            };
+            match resolve(dir, &relative) {
+                Some(found) if media_type(&found).is_some() => {
+                    let href = format!("files/{id}/{}", relative.to_string_lossy());
+                    let encoded = utf8_percent_encode(&href, URL_PATH).to_string();
+                    resources.insert(href, found);
+                    format!("{name}=\"{encoded}\"")
+                }
+                _ => format!("{name}=\"{url}\""),
+            }
+        }
+    )
+    .into_owned()
+}
+
+// Images EPUB readers have to support.
+fn media_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "svg" => Some("image/svg+xml"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// `time` as the UTC `dcterms:modified` date of a book, like
+/// `2024-05-01T12:00:00Z`.
+fn timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, rest) = (seconds / 86_400, seconds % 86_400);
+
+    // the civil calendar from days since 1970-01-01, after Howard Hinnant
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip::read_entries;
+    use rstest::rstest;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn entries(files: &[PathBuf]) -> BTreeMap<String, String> {
+        book(files, &Config::default(), &Overrides::default(), UNIX_EPOCH)
+            .unwrap()
+            .into_iter()
+            .map(|(name, content)| (name, String::from_utf8_lossy(&content).into_owned()))
+            .collect()
+    }
+
+    // Two chapters linking each other, the first with frontmatter and an image
+    fn write_book_files(temp_dir: &TempDir) -> Vec<PathBuf> {
+        let dir = temp_dir.path();
+        fs::create_dir(dir.join("img")).unwrap();
+        fs::write(dir.join("img/logo.png"), b"\x89PNG").unwrap();
+        fs::write(
+            dir.join("intro.md"),
+            "---\ntitle: The Guide\nauthor: Ada\nlang: de\ndate: 2024-05-01\n---\n\n# Introduction\n\n![Logo](img/logo.png)\n\nRead [the usage](usage.md#install) &copy; 2024.\n\n<!-- a note -->\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("usage.md"),
+            "# Usage\n\n## Install\n\nBack to [the start](intro.md).\n\n---\n\nLine  \nbreak\n",
+        )
+        .unwrap();
+        vec![dir.join("intro.md"), dir.join("usage.md")]
+    }
+
+    // ==================== Book Tests ====================
+
+    #[test]
+    fn test_book_structure() {
+        let temp_dir = TempDir::new().unwrap();
+        let files = write_book_files(&temp_dir);
+        let book = book(
+            &files,
+            &Config::default(),
+            &Overrides::default(),
+            UNIX_EPOCH,
+        )
+        .unwrap();
+
+        assert_eq!(
+            book[0],
+            ("mimetype".to_string(), b"application/epub+zip".to_vec())
+        );
+        let names: Vec<&str> = book.iter().map(|(name, _)| name.as_str()).collect();
+        let image = format!("OEBPS/files/{}/img/logo.png", doc_id(&files[0]));
+        for name in [
+            "META-INF/container.xml",
+            "OEBPS/content.opf",
+            "OEBPS/nav.xhtml",
+            "OEBPS/style.css",
+            "OEBPS/chapter-1.xhtml",
+            "OEBPS/chapter-2.xhtml",
+            &image,
+        ] {
+            assert!(names.contains(&name), "{name} missing from {names:?}");
+        }
+    }
+
+    #[test]
+    fn test_book_metadata() {
+        let temp_dir = TempDir::new().unwrap();
+        let files = write_book_files(&temp_dir);
+        let entries = entries(&files);
+
+        let package = &entries["OEBPS/content.opf"];
+        assert!(package.contains("<dc:title>The Guide</dc:title>"));
+        assert!(package.contains("<dc:creator>Ada</dc:creator>"));
+        assert!(package.contains("<dc:language>de</dc:language>"));
+        assert!(package.contains("<dc:date>2024-05-01</dc:date>"));
+        assert!(
+            package.contains("<meta property=\"dcterms:modified\">1970-01-01T00:00:00Z</meta>")
+        );
+        assert!(
+            package
+                .contains("<itemref idref=\"chapter-1\" />\n    <itemref idref=\"chapter-2\" />")
+        );
+        assert!(package.contains(&format!(
+            "href=\"files/{}/img/logo.png\" media-type=\"image/png\"",
+            doc_id(&files[0])
+        )));
+
+        // chapters are titled by their frontmatter or first heading
+        let nav = &entries["OEBPS/nav.xhtml"];
+        assert!(nav.contains("<li><a href=\"chapter-1.xhtml\">The Guide</a></li>"));
+        assert!(nav.contains("<li><a href=\"chapter-2.xhtml\">Usage</a></li>"));
+    }
+
+    #[test]
+    fn test_book_chapters() {
+        let temp_dir = TempDir::new().unwrap();
+        let files = write_book_files(&temp_dir);
+        let entries = entries(&files);
+
+        let intro = &entries["OEBPS/chapter-1.xhtml"];
+        assert!(intro.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
+        assert!(intro.contains("<a href=\"chapter-2.xhtml#install\""));
+        assert!(intro.contains(&format!(
+            "<img src=\"files/{}/img/logo.png\" alt=\"Logo\" />",
+            doc_id(&files[0])
+        )));
+        assert!(intro.contains("© 2024"));
+        assert!(!intro.contains("a note"));
+        // the frontmatter is the metadata of the book, not shown
+        assert!(!intro.contains("Ada"));
+
+        let usage = &entries["OEBPS/chapter-2.xhtml"];
+        assert!(usage.contains("<a href=\"chapter-1.xhtml\""));
+        assert!(usage.contains("<hr />"));
+        assert!(usage.contains("<br />"));
+    }
+
+    #[test]
+    fn test_book_without_frontmatter() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("notes.md");
+        fs::write(&file, "Some text without a heading\n").unwrap();
+        let entries = entries(&[file]);
+
+        let package = &entries["OEBPS/content.opf"];
+        assert!(package.contains("<dc:title>notes.md</dc:title>"));
+        assert!(package.contains("<dc:language>en</dc:language>"));
+        assert!(!package.contains("<dc:creator>"));
+    }
+
+    // ==================== Summary Tests ====================
+
+    #[test]
+    fn test_summary_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        fs::create_dir(dir.join("guide")).unwrap();
+        fs::write(dir.join("intro.md"), "# Intro\n").unwrap();
+        fs::write(
+            dir.join("guide/setup.md"),
+            "# Setup\n\nFirst read [the intro](../intro.md#start).\n",
+        )
+        .unwrap();
+        let summary = dir.join(SUMMARY);
+        fs::write(
+            &summary,
+            "# Summary\n\n[Welcome](intro.md)\n\n- [Setting up](guide/setup.md)\n  - [Again](intro.md#more)\n- [Draft]()\n- [Site](https://example.com/page.md)\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            summary_chapters(&summary).unwrap(),
+            vec![
+                Chapter {
+                    file: dir.join("intro.md"),
+                    title: Some("Welcome".to_string()),
+                },
+                Chapter {
+                    file: dir.join("guide/setup.md"),
+                    title: Some("Setting up".to_string()),
+                },
+            ]
+        );
+
+        let entries = entries(&[summary]);
+        let nav = &entries["OEBPS/nav.xhtml"];
+        assert!(nav.contains(
+            "<li><a href=\"chapter-1.xhtml\">Welcome</a></li>\n<li><a href=\"chapter-2.xhtml\">Setting up</a></li>"
+        ));
+        assert!(entries["OEBPS/chapter-2.xhtml"].contains("<a href=\"chapter-1.xhtml#start\""));
+    }
+
+    #[test]
+    fn test_summary_without_chapters() {
+        let temp_dir = TempDir::new().unwrap();
+        let summary = temp_dir.path().join(SUMMARY);
+        fs::write(&summary, "# Summary\n\nNothing yet.\n").unwrap();
+        assert!(summary_chapters(&summary).is_err());
+    }
+
+    // ==================== Export Tests ====================
+
+    #[test]
+    fn test_export_archive() {
+        let temp_dir = TempDir::new().unwrap();
+        let files = write_book_files(&temp_dir);
+
+        assert_eq!(
+            export(&files, None, &Config::default(), &Overrides::default()),
+            0
+        );
+        let archive = fs::read(temp_dir.path().join("intro.epub")).unwrap();
+        let entries = read_entries(&archive);
+        assert_eq!(entries[0].0, "mimetype");
+        assert_eq!(&archive[30..58], b"mimetypeapplication/epub+zip");
+        assert!(
+            entries
+                .iter()
+                .any(|(name, _)| name == "OEBPS/chapter-2.xhtml")
+        );
+    }
+
+    #[test]
+    fn test_export_does_not_replace_chapter() {
+        let temp_dir = TempDir::new().unwrap();
+        let files = write_book_files(&temp_dir);
+
+        let code = export(
+            &files,
+            Some(&files[1]),
+            &Config::default(),
+            &Overrides::default(),
+        );
+        assert_eq!(code, 2);
+        assert!(
+            fs::read_to_string(&files[1])
+                .unwrap()
+                .starts_with("# Usage")
+        );
+    }
+
+    #[test]
+    fn test_export_missing_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("missing.md");
+
+        assert_eq!(
+            export(&[file], None, &Config::default(), &Overrides::default()),
+            2
+        );
+        assert!(!temp_dir.path().join("missing.epub").exists());
+    }
+
+    // ==================== Date Tests ====================
+
+    #[rstest]
+    #[case(0, "1970-01-01T00:00:00Z")]
+    #[case(951_782_400, "2000-02-29T00:00:00Z")]
+    #[case(1_700_000_000, "2023-11-14T22:13:20Z")]
+    fn test_timestamp(#[case] seconds: u64, #[case] expected: &str) {
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_secs(seconds)),
+            expected
+        );
+    }
+}
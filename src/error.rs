@@ -1,6 +1,6 @@
-use std::sync::PoisonError;
+use std::{path::PathBuf, sync::PoisonError};
 
-use markdown::message::Place;
+use markdown::message::{Message, Place};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -9,14 +9,27 @@ pub enum GlyphoError {
     NotProvided,
     #[error("File not found")]
     NotFound,
-    #[error("Markdown parsing{place:?}, {reason:?}, {rule_id:?}, {m_source:?}")]
+    #[error("{reason}{} ({m_source}:{rule_id})", at(.place))]
     MarkdownError {
         place: Option<Box<Place>>,
         reason: String,
         rule_id: String,
         m_source: String,
+        /// The text that was parsed
+        content: String,
     },
 
+    #[error("Invalid session name {0:?}, use letters, digits, '-', '_' or '.'")]
+    InvalidSession(String),
+
+    #[error("Invalid configuration in {}: {reason}", path.display())]
+    InvalidConfig { path: PathBuf, reason: String },
+
+    #[error("The document changed since it was rendered")]
+    StaleDocument,
+    #[error("No task on line {0}")]
+    NoTask(usize),
+
     #[error("File format is not text")]
     InvalidData,
     #[error("Unknown Error")]
@@ -27,6 +40,26 @@ pub enum GlyphoError {
     ErrReport(#[from] eyre::ErrReport),
 }
 
+fn at(place: &Option<Box<Place>>) -> String {
+    place
+        .as_ref()
+        .map(|place| format!(" at {place}"))
+        .unwrap_or_default()
+}
+
+impl GlyphoError {
+    /// The error the Markdown parser reported for `content`.
+    pub fn markdown(message: Message, content: &str) -> Self {
+        Self::MarkdownError {
+            place: message.place,
+            reason: message.reason,
+            rule_id: *message.rule_id,
+            m_source: *message.source,
+            content: content.to_string(),
+        }
+    }
+}
+
 impl<T> From<PoisonError<T>> for GlyphoError {
     fn from(_: PoisonError<T>) -> Self {
         Self::PoisonError
@@ -128,12 +161,30 @@ mod tests {
             reason: "test".to_string(),
             rule_id: "rule".to_string(),
             m_source: "src".to_string(),
+            content: String::new(),
         };
         let debug_str = format!("{:?}", err);
         assert!(debug_str.contains("MarkdownError"));
         assert!(debug_str.contains("test"));
     }
 
+    #[test]
+    fn test_markdown_error_display() {
+        let err = GlyphoError::MarkdownError {
+            place: Some(Box::new(Place::Point(markdown::unist::Point::new(
+                3, 7, 20,
+            )))),
+            reason: "Unexpected character".to_string(),
+            rule_id: "unexpected-character".to_string(),
+            m_source: "micromark-extension-mdx-jsx".to_string(),
+            content: String::new(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Unexpected character at 3:7 (micromark-extension-mdx-jsx:unexpected-character)"
+        );
+    }
+
     // ==================== Error Trait Tests ====================
 
     #[test]
@@ -164,13 +215,21 @@ mod tests {
                 reason: String::new(),
                 rule_id: String::new(),
                 m_source: String::new(),
+                content: String::new(),
+            },
+            GlyphoError::InvalidSession(String::new()),
+            GlyphoError::InvalidConfig {
+                path: PathBuf::new(),
+                reason: String::new(),
             },
+            GlyphoError::StaleDocument,
+            GlyphoError::NoTask(0),
             GlyphoError::InvalidData,
             GlyphoError::Unknown,
             GlyphoError::PoisonError,
             GlyphoError::ErrReport(eyre::eyre!("test")),
         ];
 
-        assert_eq!(variants.len(), 7);
+        assert_eq!(variants.len(), 11);
     }
 }
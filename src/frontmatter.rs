@@ -0,0 +1,470 @@
+use asynk_strim::{Yielder, stream_fn};
+use axum::{
+    extract::{Path as UrlPath, State},
+    response::{
+        IntoResponse,
+        sse::{Event, Sse},
+    },
+};
+use datastar::{consts::ElementPatchMode, prelude::PatchElements};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use std::{
+    convert::Infallible,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{session::workspace_root, state::AppState};
+
+/// Extensions of the documents searched for tags.
+pub const MARKDOWN_EXTENSIONS: &[&str] = &["md", "mdx", "markdown"];
+
+// keeps a tag search bounded in huge workspaces
+const MAX_DEPTH: usize = 8;
+const MAX_FILES: usize = 2000;
+
+/// The metadata at the top of a document, between `---` (YAML) or `+++`
+/// (TOML) fences.
+#[derive(Debug, Default, PartialEq)]
+pub struct Frontmatter {
+    pub title: Option<String>,
+    pub tags: Vec<String>,
+    /// Every other field, as displayed in the metadata card
+    pub fields: Vec<(String, String)>,
+}
+
+impl Frontmatter {
+    /// The frontmatter of `content`, `None` when there is none or it does not
+    /// parse.
+    pub fn parse(content: &str) -> Option<Self> {
+        let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+        let fence = content.get(..3).filter(|f| *f == "---" || *f == "+++")?;
+        let mut lines = content.split_inclusive('\n');
+        if lines.next()?.trim_end() != fence {
+            return None;
+        }
+
+        let mut raw = String::new();
+        let mut closed = false;
+        for line in lines {
+            if line.trim_end() == fence {
+                closed = true;
+                break;
+            }
+            raw.push_str(line);
+        }
+        if !closed {
+            return None;
+        }
+
+        let entries = if fence == "---" {
+            let mapping: serde_norway::Mapping = serde_norway::from_str(&raw).ok()?;
+            mapping
+                .into_iter()
+                .filter_map(|(key, value)| Some((yaml_text(&key)?, Value::from_yaml(value))))
+                .collect()
+        } else {
+            let table: toml::Table = toml::from_str(&raw).ok()?;
+            table
+                .into_iter()
+                .map(|(key, value)| (key, Value::from_toml(value)))
+                .collect()
+        };
+
+        Some(Self::from_entries(entries))
+    }
+
+    fn from_entries(entries: Vec<(String, Value)>) -> Self {
+        let mut frontmatter = Frontmatter::default();
+        for (key, value) in entries {
+            match (key.as_str(), value) {
+                ("title", Value::Text(title)) => frontmatter.title = Some(title),
+                ("tags", Value::List(tags)) => frontmatter.tags = tags,
+                ("tags", Value::Text(tags)) => {
+                    frontmatter.tags = tags
+                        .split(',')
+                        .map(|t| t.trim().to_string())
+                        .filter(|t| !t.is_empty())
+                        .collect()
+                }
+                (_, Value::Text(text)) => frontmatter.fields.push((key, text)),
+                (_, Value::List(items)) => frontmatter.fields.push((key, items.join(", "))),
+                (_, Value::Empty) => {}
+            }
+        }
+        frontmatter
+    }
+
+    /// The value of the first of `keys` the frontmatter has.
+    pub fn field(&self, keys: &[&str]) -> Option<&str> {
+        keys.iter().find_map(|key| {
+            self.fields
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value.as_str())
+        })
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+
+    /// The collapsible metadata card shown above the article, empty when
+    /// there is nothing besides the title.
+    pub fn card(&self) -> String {
+        if self.fields.is_empty() && self.tags.is_empty() {
+            return String::new();
+        }
+
+        let mut card = String::from(
+            "<details class=\"frontmatter\" open>\n<summary>Metadata</summary>\n<dl>\n",
+        );
+        for (key, value) in &self.fields {
+            card.push_str(&format!(
+                "<dt>{}</dt><dd>{}</dd>\n",
+                escape_html(key),
+                escape_html(value)
+            ));
+        }
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = self
+                .tags
+                .iter()
+                .map(|tag| {
+                    let url = utf8_percent_encode(tag, NON_ALPHANUMERIC);
+                    format!(
+                        "<button class=\"tag\" data-on:click=\"@get('/tags/{url}')\">{}</button>",
+                        escape_html(tag)
+                    )
+                })
+                .collect();
+            card.push_str(&format!("<dt>tags</dt><dd>{}</dd>\n", tags.join(" ")));
+        }
+        card.push_str("</dl>\n<div id=\"tag-results\"></div>\n</details>\n");
+        card
+    }
+}
+
+/// A frontmatter value reduced to what the card displays.
+enum Value {
+    Text(String),
+    List(Vec<String>),
+    Empty,
+}
+
+impl Value {
+    fn from_yaml(value: serde_norway::Value) -> Self {
+        match value {
+            serde_norway::Value::Sequence(items) => {
+                Value::List(items.iter().filter_map(yaml_text).collect())
+            }
+            value => yaml_text(&value).map_or(Value::Empty, Value::Text),
+        }
+    }
+
+    fn from_toml(value: toml::Value) -> Self {
+        match value {
+            toml::Value::Array(items) => Value::List(items.iter().map(toml_text).collect()),
+            value => Value::Text(toml_text(&value)),
+        }
+    }
+}
+
+fn yaml_text(value: &serde_norway::Value) -> Option<String> {
+    use serde_norway::Value::*;
+    match value {
+        Null => None,
+        Bool(b) => Some(b.to_string()),
+        Number(n) => Some(n.to_string()),
+        String(s) => Some(s.clone()),
+        Sequence(items) => Some(
+            items
+                .iter()
+                .filter_map(yaml_text)
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        Mapping(mapping) => Some(
+            mapping
+                .iter()
+                .filter_map(|(k, v)| Some(format!("{}: {}", yaml_text(k)?, yaml_text(v)?)))
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        Tagged(tagged) => yaml_text(&tagged.value),
+    }
+}
+
+fn toml_text(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Array(items) => items.iter().map(toml_text).collect::<Vec<_>>().join(", "),
+        toml::Value::Table(table) => table
+            .iter()
+            .map(|(k, v)| format!("{k}: {}", toml_text(v)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        value => value.to_string(),
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes `text` for a single quoted string in a `data-on:*` expression.
+pub fn js_string(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| MARKDOWN_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Markdown documents under `root` whose frontmatter carries `tag`, with
+/// their title. Hidden directories are skipped.
+pub fn tagged_files(root: &Path, tag: &str) -> Vec<(PathBuf, Option<String>)> {
+    let mut found = vec![];
+    let mut seen = 0;
+    let mut dirs = vec![(root.to_path_buf(), 0)];
+
+    while let Some((dir, depth)) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut entries: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        entries.sort();
+
+        for path in entries {
+            let hidden = path
+                .file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with('.'));
+            if hidden {
+                continue;
+            }
+            if path.is_dir() {
+                if depth < MAX_DEPTH && !path.ends_with("node_modules") {
+                    dirs.push((path, depth + 1));
+                }
+            } else if is_markdown(&path) {
+                seen += 1;
+                if seen > MAX_FILES {
+                    return found;
+                }
+                let frontmatter = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|content| Frontmatter::parse(&content));
+                if let Some(frontmatter) = frontmatter.filter(|f| f.has_tag(tag)) {
+                    found.push((path, frontmatter.title));
+                }
+            }
+        }
+    }
+
+    found.sort();
+    found
+}
+
+/// Lists the workspace documents sharing a tag under the metadata card.
+pub async fn list_tagged(
+    State(state): State<Arc<AppState>>,
+    UrlPath(tag): UrlPath<String>,
+) -> impl IntoResponse {
+    let active = { state.lock().await.active_file().to_path_buf() };
+    let root = workspace_root(&active).to_path_buf();
+    let files = tokio::task::spawn_blocking({
+        let tag = tag.clone();
+        move || tagged_files(&root, &tag)
+    })
+    .await
+    .unwrap_or_default();
+
+    let items: Vec<String> = files
+        .iter()
+        .filter(|(file, _)| *file != active)
+        .map(|(file, title)| {
+            let path = escape_html(&js_string(&file.to_string_lossy()));
+            let label = title.clone().unwrap_or_else(|| {
+                file.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into()
+            });
+            format!(
+                "<li><button data-on:click=\"$file = '{path}';@post('/open')\">{}</button></li>",
+                escape_html(&label)
+            )
+        })
+        .collect();
+
+    let html = if items.is_empty() {
+        format!("<p>No other documents tagged {}</p>", escape_html(&tag))
+    } else {
+        format!("<ul>\n{}\n</ul>", items.join("\n"))
+    };
+
+    let stream = stream_fn(
+        move |mut yielder: Yielder<Result<Event, Infallible>>| async move {
+            let patch = PatchElements::new(html)
+                .selector("#tag-results")
+                .mode(ElementPatchMode::Inner);
+            yielder
+                .yield_item(Ok(patch.write_as_axum_sse_event()))
+                .await;
+        },
+    );
+    Sse::new(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::assert_snapshot;
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    // ==================== Parsing Tests ====================
+
+    #[test]
+    fn test_parse_yaml() {
+        let content = "---\ntitle: Release notes\nauthor: Ada\ntags: [rust, cli]\ndate: 2024-05-01\ndraft: false\n---\n\n# Body\n";
+        let frontmatter = Frontmatter::parse(content).unwrap();
+
+        assert_eq!(frontmatter.title.as_deref(), Some("Release notes"));
+        assert_eq!(frontmatter.tags, vec!["rust", "cli"]);
+        assert_eq!(
+            frontmatter.fields,
+            vec![
+                ("author".to_string(), "Ada".to_string()),
+                ("date".to_string(), "2024-05-01".to_string()),
+                ("draft".to_string(), "false".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let content = "+++\ntitle = \"Guide\"\ntags = [\"docs\"]\nauthors = [\"Ada\", \"Linus\"]\ndate = 2024-05-01\n+++\n\nBody\n";
+        let frontmatter = Frontmatter::parse(content).unwrap();
+
+        assert_eq!(frontmatter.title.as_deref(), Some("Guide"));
+        assert_eq!(frontmatter.tags, vec!["docs"]);
+        assert!(
+            frontmatter
+                .fields
+                .contains(&("authors".to_string(), "Ada, Linus".to_string()))
+        );
+        assert!(
+            frontmatter
+                .fields
+                .contains(&("date".to_string(), "2024-05-01".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_field() {
+        let frontmatter =
+            Frontmatter::parse("---\nAuthors: [Ada, Linus]\nlang: de\n---\n").unwrap();
+        assert_eq!(
+            frontmatter.field(&["author", "authors"]),
+            Some("Ada, Linus")
+        );
+        assert_eq!(frontmatter.field(&["language", "lang"]), Some("de"));
+        assert_eq!(frontmatter.field(&["description"]), None);
+    }
+
+    #[test]
+    fn test_parse_comma_separated_tags() {
+        let frontmatter = Frontmatter::parse("---\ntags: rust, web ,\n---\n").unwrap();
+        assert_eq!(frontmatter.tags, vec!["rust", "web"]);
+    }
+
+    #[rstest]
+    #[case("# No frontmatter\n")]
+    #[case("---\ntitle: never closed\n")]
+    #[case("---\n: [broken\n---\n")]
+    #[case("+++\ntitle = \n+++\n")]
+    #[case("----\ntitle: x\n----\n")]
+    #[case("text\n---\ntitle: x\n---\n")]
+    fn test_parse_none(#[case] content: &str) {
+        assert_eq!(Frontmatter::parse(content), None);
+    }
+
+    // ==================== Card Tests ====================
+
+    #[test]
+    fn test_card_snapshot() {
+        let frontmatter = Frontmatter::parse(
+            "---\ntitle: Notes\nauthor: Ada <ada@example.com>\ntags: [rust, \"c++\"]\n---\n",
+        )
+        .unwrap();
+        assert_snapshot!(frontmatter.card());
+    }
+
+    #[test]
+    fn test_card_empty_with_only_title() {
+        let frontmatter = Frontmatter::parse("---\ntitle: Notes\n---\n").unwrap();
+        assert_eq!(frontmatter.card(), "");
+    }
+
+    // ==================== Tag Search Tests ====================
+
+    #[test]
+    fn test_tagged_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("guide")).unwrap();
+        std::fs::create_dir_all(root.join(".hidden")).unwrap();
+        std::fs::write(root.join("a.md"), "---\ntitle: A\ntags: [rust]\n---\n").unwrap();
+        std::fs::write(root.join("guide/b.md"), "---\ntags: [Rust, web]\n---\n").unwrap();
+        std::fs::write(root.join("c.md"), "---\ntags: [web]\n---\n").unwrap();
+        std::fs::write(root.join(".hidden/d.md"), "---\ntags: [rust]\n---\n").unwrap();
+        std::fs::write(root.join("e.txt"), "---\ntags: [rust]\n---\n").unwrap();
+
+        let files = tagged_files(root, "rust");
+        assert_eq!(
+            files,
+            vec![
+                (root.join("a.md"), Some("A".to_string())),
+                (root.join("guide/b.md"), None),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("notes.md", true)]
+    #[case("page.MDX", true)]
+    #[case("README.markdown", true)]
+    #[case("image.png", false)]
+    #[case("Makefile", false)]
+    fn test_is_markdown(#[case] file: &str, #[case] expected: bool) {
+        assert_eq!(is_markdown(Path::new(file)), expected);
+    }
+
+    #[test]
+    fn test_js_string() {
+        assert_eq!(js_string(r"it's C:\notes"), r"it\'s C:\\notes");
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+    }
+}
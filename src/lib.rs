@@ -0,0 +1,50 @@
+//! Glypho renders Markdown documents and serves a live preview of them.
+//!
+//! The `glypho` binary is a thin wrapper around this library. To embed the
+//! preview, build a [`Server`] from an [`InnerState`] and serve its router;
+//! to render documents without a server, use a [`Renderer`].
+
+mod app;
+mod assets;
+mod auth;
+mod blocks;
+mod callouts;
+mod cli;
+mod config;
+mod control;
+mod diagnostic;
+mod diagrams;
+mod dialect;
+mod editor;
+mod epub;
+mod error;
+mod frontmatter;
+mod lint;
+mod page;
+mod print;
+mod render;
+mod safe;
+mod server;
+mod session;
+mod slides;
+mod state;
+mod stats;
+mod tasks;
+mod template;
+#[cfg(test)]
+mod testing;
+mod tls;
+mod toc;
+mod wikilinks;
+mod xhtml;
+mod zip;
+
+pub use app::run;
+pub use auth::AccessToken;
+pub use config::{Config, Overrides};
+pub use dialect::Dialect;
+pub use error::GlyphoError;
+pub use render::Renderer;
+pub use server::Server;
+pub use state::{AppState, InnerState, Output, SignalEvents, add_file, remove_file, switch_file};
+pub use wikilinks::{wikilinks_to_markdown, wikilinks_with_extension};
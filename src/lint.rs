@@ -0,0 +1,578 @@
+use markdown::{
+    ParseOptions,
+    mdast::{List, Node},
+    message::Message,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    blocks::blocks,
+    config::{Config, LintConfig, Overrides, WikilinksConfig},
+    diagnostic::Diagnostic,
+    error::GlyphoError,
+    frontmatter::escape_html,
+};
+
+/// The checks of the linter, named like in `disable = [...]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    /// A heading more than one level below the previous one
+    HeadingIncrement,
+    /// Two headings with the same text
+    DuplicateHeading,
+    /// Whitespace at the end of a line, other than a two space line break
+    TrailingSpaces,
+    /// A URL that is not written as a link
+    BareUrl,
+    /// An image without alternative text
+    ImageAlt,
+    /// Bullet lists using different markers
+    ListMarker,
+    /// A line longer than `line_length`
+    LineLength,
+}
+
+impl Rule {
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::HeadingIncrement => "heading-increment",
+            Rule::DuplicateHeading => "duplicate-heading",
+            Rule::TrailingSpaces => "trailing-spaces",
+            Rule::BareUrl => "bare-url",
+            Rule::ImageAlt => "image-alt",
+            Rule::ListMarker => "list-marker",
+            Rule::LineLength => "line-length",
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub rule: Rule,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.line, self.column, self.rule, self.message
+        )
+    }
+}
+
+/// Parses `content` with its wikilinks rewritten and checks it, the error is
+/// the one rendering fails with. Findings and errors are placed in `content`.
+pub fn lint(
+    content: &str,
+    options: &ParseOptions,
+    config: &LintConfig,
+    wikilinks: &WikilinksConfig,
+) -> Result<Vec<Finding>, Message> {
+    let (parsed, source_map) = wikilinks.rewrite(content);
+    let mut tree =
+        markdown::to_mdast(&parsed, options).map_err(|message| source_map.message(message))?;
+    source_map.tree(&mut tree);
+    Ok(check(&tree, content, config))
+}
+
+/// The findings of the enabled rules in the document `tree` parsed from
+/// `content`, ordered by position.
+pub fn check(tree: &Node, content: &str, config: &LintConfig) -> Vec<Finding> {
+    let mut linter = Linter {
+        content,
+        findings: vec![],
+        previous_depth: None,
+        headings: HashMap::new(),
+        list_marker: None,
+        verbatim: BTreeSet::new(),
+    };
+    linter.walk(tree);
+    linter.check_lines(config.line_length);
+
+    let mut findings = linter.findings;
+    findings.retain(|finding| !config.disable.contains(&finding.rule));
+    findings.sort_by_key(|finding| (finding.line, finding.column, finding.rule));
+    findings
+}
+
+struct Linter<'a> {
+    content: &'a str,
+    findings: Vec<Finding>,
+    previous_depth: Option<u8>,
+    // first line of each heading text
+    headings: HashMap<String, usize>,
+    list_marker: Option<char>,
+    // lines of code, tables, HTML and frontmatter, written as they must be
+    verbatim: BTreeSet<usize>,
+}
+
+impl Linter<'_> {
+    fn walk(&mut self, node: &Node) {
+        let Some(start) = node.position().map(|p| &p.start) else {
+            self.walk_children(node);
+            return;
+        };
+        let (line, column) = (start.line, start.column);
+
+        match node {
+            Node::Heading(heading) => {
+                if let Some(previous) = self.previous_depth
+                    && heading.depth > previous + 1
+                {
+                    self.report(
+                        Rule::HeadingIncrement,
+                        line,
+                        column,
+                        format!("Heading level jumps from h{previous} to h{}", heading.depth),
+                    );
+                }
+                self.previous_depth = Some(heading.depth);
+
+                let text = node.to_string().trim().to_lowercase();
+                match self.headings.get(&text) {
+                    Some(first) => {
+                        let message = format!("Same heading as on line {first}");
+                        self.report(Rule::DuplicateHeading, line, column, message);
+                    }
+                    None => {
+                        self.headings.insert(text, line);
+                    }
+                }
+            }
+            Node::Link(link) => {
+                // GFM turns bare URLs into links without brackets around them
+                let source = self.content.get(start.offset..).unwrap_or_default();
+                if !source.starts_with(['<', '[']) {
+                    let message = format!("Bare URL {}, write it as <{}>", link.url, link.url);
+                    self.report(Rule::BareUrl, line, column, message);
+                }
+            }
+            Node::Image(image) if image.alt.trim().is_empty() => {
+                let message = format!("Image {} has no alternative text", image.url);
+                self.report(Rule::ImageAlt, line, column, message);
+            }
+            Node::ImageReference(image) if image.alt.trim().is_empty() => {
+                let message = format!("Image [{}] has no alternative text", image.identifier);
+                self.report(Rule::ImageAlt, line, column, message);
+            }
+            Node::List(list) => self.check_list_markers(list),
+            Node::Code(_)
+            | Node::Math(_)
+            | Node::Html(_)
+            | Node::Table(_)
+            | Node::Yaml(_)
+            | Node::Toml(_) => {
+                let end = node.position().map_or(line, |p| p.end.line);
+                self.verbatim.extend(line..=end);
+            }
+            _ => {}
+        }
+        self.walk_children(node);
+    }
+
+    fn walk_children(&mut self, node: &Node) {
+        for child in node.children().into_iter().flatten() {
+            self.walk(child);
+        }
+    }
+
+    fn check_list_markers(&mut self, list: &List) {
+        if list.ordered {
+            return;
+        }
+        for item in &list.children {
+            let Some(start) = item.position().map(|p| &p.start) else {
+                continue;
+            };
+            let Some(marker) = self
+                .content
+                .get(start.offset..)
+                .and_then(|s| s.chars().next())
+            else {
+                continue;
+            };
+            match self.list_marker {
+                None => self.list_marker = Some(marker),
+                Some(expected) if expected != marker => {
+                    let message = format!("List marker {marker}, the document uses {expected}");
+                    self.report(Rule::ListMarker, start.line, start.column, message);
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    fn check_lines(&mut self, line_length: usize) {
+        let lines: Vec<&str> = self.content.lines().collect();
+        for (index, text) in lines.iter().enumerate() {
+            let line = index + 1;
+            if self.verbatim.contains(&line) {
+                continue;
+            }
+
+            let trimmed = text.trim_end_matches([' ', '\t']);
+            let trailing = &text[trimmed.len()..];
+            // two spaces are a line break, but not at the end of a paragraph
+            let next_is_text = lines.get(line).is_some_and(|next| !next.trim().is_empty());
+            if !trailing.is_empty() && !trimmed.is_empty() && !(trailing == "  " && next_is_text) {
+                let message = "Trailing whitespace".to_string();
+                self.report(Rule::TrailingSpaces, line, trimmed.len() + 1, message);
+            }
+
+            let length = text.chars().count();
+            // a single long word, usually a URL, cannot be wrapped
+            if line_length > 0 && length > line_length && trimmed.trim().contains(' ') {
+                let message =
+                    format!("Line is {length} characters long, the limit is {line_length}");
+                self.report(Rule::LineLength, line, line_length + 1, message);
+            }
+        }
+    }
+
+    fn report(&mut self, rule: Rule, line: usize, column: usize, message: String) {
+        self.findings.push(Finding {
+            rule,
+            line,
+            column,
+            message,
+        });
+    }
+}
+
+/// `glypho lint`: prints the findings of every file and returns the exit
+/// code, 1 when something was found and 2 when a file could not be checked.
+pub fn lint_files(files: &[PathBuf], user_config: &Config, overrides: &Overrides) -> i32 {
+    let mut code = 0;
+    for file in files {
+        match lint_file(file, user_config, overrides) {
+            Ok(findings) => {
+                for finding in &findings {
+                    println!("{}:{finding}", file.display());
+                }
+                if !findings.is_empty() {
+                    code = code.max(1);
+                }
+            }
+            Err(err) => {
+                Diagnostic::new(file, &err).print();
+                code = 2;
+            }
+        }
+    }
+    code
+}
+
+// Checked the way the preview renders it, with the configuration of its directory.
+fn lint_file(
+    file: &Path,
+    user_config: &Config,
+    overrides: &Overrides,
+) -> eyre::Result<Vec<Finding>> {
+    let content = fs::read_to_string(file)?;
+    let path = std::path::absolute(file)?;
+    let config = user_config.for_file(&path, overrides)?;
+
+    let options = config.markdown.options(&path);
+    lint(&content, &options.parse, &config.lint, &config.wikilinks)
+        .map_err(|message| GlyphoError::markdown(message, &content).into())
+}
+
+/// Adds a marker in the margin of the preview next to every top level
+/// block of `html` with findings. `tree` is the document `html` was
+/// rendered from, its blocks tagged by `tag_blocks`.
+pub fn gutter(html: &str, tree: &Node, findings: &[Finding]) -> String {
+    let (mut html, blocks) = blocks(html, tree);
+    if findings.is_empty() || blocks.is_empty() {
+        return html;
+    }
+
+    // findings between blocks belong to the next one
+    let mut marked: BTreeMap<usize, Vec<&Finding>> = BTreeMap::new();
+    for finding in findings {
+        let block = blocks
+            .iter()
+            .position(|(_, node)| node.position().is_some_and(|p| finding.line <= p.end.line))
+            .unwrap_or(blocks.len() - 1);
+        marked.entry(block).or_default().push(finding);
+    }
+
+    for (block, findings) in marked.iter().rev() {
+        let title = findings
+            .iter()
+            .map(|finding| escape_html(&finding.to_string()))
+            .collect::<Vec<_>>()
+            .join("&#10;");
+        let marker = format!(
+            "<div class=\"lint-gutter\"><span class=\"lint-marker\" title=\"{title}\">{}</span></div>",
+            findings.len()
+        );
+        html.insert_str(blocks[*block].0, &marker);
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dialect::Dialect,
+        safe::sanitize_html,
+        testing::{self, tagged},
+    };
+    use insta::assert_snapshot;
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    fn options() -> ParseOptions {
+        testing::options(Dialect::Gfm).parse
+    }
+
+    fn findings(content: &str) -> Vec<Finding> {
+        lint(
+            content,
+            &options(),
+            &LintConfig::default(),
+            &WikilinksConfig::default(),
+        )
+        .unwrap()
+    }
+
+    fn rules(content: &str) -> Vec<(Rule, usize)> {
+        findings(content)
+            .into_iter()
+            .map(|finding| (finding.rule, finding.line))
+            .collect()
+    }
+
+    // ==================== Rule Tests ====================
+
+    #[rstest]
+    #[case("# One\n\n### Three", vec![(Rule::HeadingIncrement, 3)])]
+    #[case("# One\n\n## Two\n\n### Three\n\n# One again", vec![])]
+    #[case("## Usage\n\n## Install\n\n## usage", vec![(Rule::DuplicateHeading, 5)])]
+    #[case("Some text \nmore", vec![(Rule::TrailingSpaces, 1)])]
+    #[case("Line break  \nmore", vec![])]
+    #[case("End of paragraph  \n\nNext", vec![(Rule::TrailingSpaces, 1)])]
+    #[case("See https://example.com for more", vec![(Rule::BareUrl, 1)])]
+    #[case("See <https://example.com> and [it](https://example.com)", vec![])]
+    #[case("![](logo.png)", vec![(Rule::ImageAlt, 1)])]
+    #[case("![Logo](logo.png)", vec![])]
+    #[case("- one\n- two\n\n* three", vec![(Rule::ListMarker, 4)])]
+    #[case("- one\n  - nested\n- two", vec![])]
+    #[case("1. one\n2) two", vec![])]
+    fn test_rules(#[case] content: &str, #[case] expected: Vec<(Rule, usize)>) {
+        assert_eq!(rules(content), expected);
+    }
+
+    #[rstest]
+    #[case(&"word ".repeat(30), vec![(Rule::LineLength, 1)])]
+    #[case(&format!("https://example.com/{}", "a".repeat(200)), vec![(Rule::BareUrl, 1)])]
+    #[case(&format!("```\n{}\n```", "code ".repeat(30)), vec![])]
+    fn test_line_length(#[case] content: &str, #[case] expected: Vec<(Rule, usize)>) {
+        let content = content.trim_end();
+        assert_eq!(rules(content), expected);
+    }
+
+    #[test]
+    fn test_code_blocks_not_checked() {
+        assert_eq!(rules("```\ntrailing   \n```\n\n    indented \n"), vec![]);
+    }
+
+    #[test]
+    fn test_frontmatter_not_checked() {
+        assert_eq!(rules("---\ntitle: x   \n---\n\n# Title"), vec![]);
+    }
+
+    #[test]
+    fn test_findings_snapshot() {
+        let content = "# Guide\n\n### Setup \n\nGet it from https://example.com\n\n![](shot.png)\n\n- a\n+ b\n\n# Guide\n";
+        let report = findings(content)
+            .iter()
+            .map(Finding::to_string)
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_snapshot!(report);
+    }
+
+    #[test]
+    fn test_findings_placed_in_source() {
+        let findings = findings("See [[Some Page|label]] and https://example.com \n");
+        let places: Vec<_> = findings
+            .iter()
+            .map(|finding| (finding.rule, finding.line, finding.column))
+            .collect();
+        assert_eq!(
+            places,
+            vec![(Rule::BareUrl, 1, 29), (Rule::TrailingSpaces, 1, 48)]
+        );
+    }
+
+    #[test]
+    fn test_line_length_of_source() {
+        let config = LintConfig {
+            line_length: 24,
+            ..LintConfig::default()
+        };
+        let content = "See [[a/long/target]] ok";
+        let findings = lint(content, &options(), &config, &WikilinksConfig::default()).unwrap();
+        assert!(findings.is_empty());
+    }
+
+    // ==================== Config Tests ====================
+
+    #[test]
+    fn test_disabled_rules() {
+        let config = LintConfig {
+            disable: vec![Rule::BareUrl, Rule::ImageAlt],
+            ..LintConfig::default()
+        };
+        let findings = lint(
+            "https://example.com\n\n![](a.png)",
+            &options(),
+            &config,
+            &WikilinksConfig::default(),
+        )
+        .unwrap();
+        assert!(findings.is_empty());
+    }
+
+    #[test]
+    fn test_line_length_from_config() {
+        let config = LintConfig {
+            line_length: 10,
+            ..LintConfig::default()
+        };
+        let findings = lint(
+            "a line of some words",
+            &options(),
+            &config,
+            &WikilinksConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(findings[0].rule, Rule::LineLength);
+        assert_eq!(findings[0].column, 11);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let options = testing::options(Dialect::Mdx).parse;
+        assert!(
+            lint(
+                "<Chart size=1 />",
+                &options,
+                &LintConfig::default(),
+                &WikilinksConfig::default()
+            )
+            .is_err()
+        );
+    }
+
+    // ==================== Command Tests ====================
+
+    #[rstest]
+    #[case("# Clean\n\nText\n", 0)]
+    #[case("# Title\n\n### Skipped\n", 1)]
+    #[case("<Chart size=1 />\n", 2)]
+    fn test_exit_codes(#[case] content: &str, #[case] expected: i32) {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("doc.mdx");
+        fs::write(&file, content).unwrap();
+
+        assert_eq!(
+            lint_files(&[file], &Config::default(), &Overrides::default()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let files = [PathBuf::from("/nonexistent/gone.md")];
+        assert_eq!(
+            lint_files(&files, &Config::default(), &Overrides::default()),
+            2
+        );
+    }
+
+    #[test]
+    fn test_project_config_applies() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(
+            temp_dir.path().join(".glypho.toml"),
+            "[lint]\ndisable = [\"heading-increment\"]\n",
+        )
+        .unwrap();
+        let file = temp_dir.path().join("doc.md");
+        fs::write(&file, "# Title\n\n### Skipped\n").unwrap();
+
+        assert!(
+            lint_file(&file, &Config::default(), &Overrides::default())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    // ==================== Gutter Tests ====================
+
+    fn gutter_for(content: &str) -> String {
+        let (html, tree) = tagged(content, Dialect::Gfm);
+        gutter(&html, &tree, &check(&tree, content, &LintConfig::default()))
+    }
+
+    #[test]
+    fn test_marker_before_block() {
+        let html = gutter_for("# One\n\nText\n\n### Three\n\nMore");
+        assert!(html.contains(
+            "<p>Text</p>\n<div class=\"lint-gutter\"><span class=\"lint-marker\" title=\"5:1: heading-increment: Heading level jumps from h1 to h3\">1</span></div><h3>"
+        ));
+    }
+
+    #[test]
+    fn test_marker_after_dropped_html() {
+        let content = "# One\n\n<script>x()</script>\n\n### Three\n\n- item\n";
+        // without the tagfilter of GFM the script reaches the sanitizer
+        let (html, tree) = tagged(content, Dialect::Commonmark);
+        let html = sanitize_html(&html);
+
+        let html = gutter(&html, &tree, &check(&tree, content, &LintConfig::default()));
+        assert!(html.contains("</span></div><h3>Three</h3>"));
+    }
+
+    #[test]
+    fn test_markers_skip_definitions() {
+        let html = gutter_for("[a]: https://example.com\n\n# One\n\n![](x.png)");
+        assert!(html.contains(
+            "<div class=\"lint-gutter\"><span class=\"lint-marker\" title=\"5:1: image-alt"
+        ));
+        assert!(html.ends_with("</span></div><p><img src=\"x.png\" alt=\"\" /></p>"));
+    }
+
+    #[test]
+    fn test_findings_grouped_per_block() {
+        let html = gutter_for("Text with https://a.example \nand https://b.example");
+        assert_eq!(html.matches("lint-gutter").count(), 1);
+        assert!(html.contains("&#10;"));
+        assert!(html.contains(">3</span>"));
+    }
+
+    #[test]
+    fn test_no_findings_no_markers() {
+        let html = gutter_for("# Clean\n\nText");
+        assert!(!html.contains("lint-gutter"));
+    }
+}
@@ -0,0 +1,514 @@
+use handlebars::Handlebars;
+use serde::Serialize;
+use std::{fs, path::Path, sync::LazyLock};
+use tracing::warn;
+
+use crate::{
+    config::Config, editor::EDITOR, slides::SLIDE_CONTROLS, template::TEMPLATE, toc::toc_html,
+};
+
+/// Template of a user template directory rendered for the preview page,
+/// the other `.hbs` files next to it are available as partials.
+pub const PAGE_TEMPLATE: &str = "page.hbs";
+
+static DEFAULT_PAGE: &str = include_str!("templates/page.hbs");
+
+static PRINT_PAGE: &str = include_str!("templates/print.hbs");
+static STANDALONE_PAGE: &str = include_str!("templates/standalone.hbs");
+
+/// Themes shipped with glypho, selected with `--theme` or `theme = "..."`.
+pub const THEMES: &[(&str, &str)] = &[
+    ("github", include_str!("themes/github.css")),
+    ("academic", include_str!("themes/academic.css")),
+    ("sepia", include_str!("themes/sepia.css")),
+    ("high-contrast", include_str!("themes/high-contrast.css")),
+];
+
+// Styles of the markup glypho adds to documents, the web bundle only
+// styles plain Markdown.
+static STYLES: &[&str] = &[
+    include_str!("styles/callouts.css"),
+    include_str!("styles/diagrams.css"),
+    include_str!("styles/diagnostics.css"),
+    include_str!("styles/lint.css"),
+    include_str!("styles/stats.css"),
+    include_str!("styles/editor.css"),
+    include_str!("styles/slides.css"),
+    include_str!("styles/print.css"),
+];
+
+/// The stylesheet of a bundled theme.
+pub fn theme_css(name: &str) -> Option<&'static str> {
+    THEMES
+        .iter()
+        .find(|(theme, _)| *theme == name)
+        .map(|(_, css)| *css)
+}
+
+/// The built-in page cut into the pieces templates place, found by the
+/// elements `glypho-web/src/index.html` is made of.
+struct Parts {
+    head: &'static str,
+    navbar: &'static str,
+    article: &'static str,
+    scripts: &'static str,
+}
+
+// offsets of the parts in TEMPLATE, checked by build.rs
+include!(concat!(env!("OUT_DIR"), "/template_parts.rs"));
+
+static PARTS: LazyLock<Parts> = LazyLock::new(|| Parts {
+    head: &TEMPLATE[HEAD],
+    navbar: &TEMPLATE[NAVBAR],
+    article: &TEMPLATE[ARTICLE],
+    scripts: &TEMPLATE[SCRIPTS],
+});
+
+/// Slots a page template fills in, `{{title}}` is escaped, the others are
+/// HTML and placed with `{{{...}}}`.
+#[derive(Debug, Serialize)]
+pub struct Slots {
+    pub title: String,
+    pub theme: Option<String>,
+    /// Built-in styles and scripts, the theme and `extra_css`
+    pub head: String,
+    pub navbar: &'static str,
+    /// Filled with the headings of the document while it is previewed
+    pub toc: &'static str,
+    pub article: &'static str,
+    /// Status bar with the word count of the document
+    pub stats: &'static str,
+    /// Toggle and pane of the source editor, empty in safe mode
+    pub editor: &'static str,
+    /// Slide toggle, keyboard navigation and presenter view button
+    pub slides: &'static str,
+    /// Built-in scripts and `extra_js`
+    pub scripts: String,
+}
+
+impl Slots {
+    pub fn new(config: &Config, title: &str) -> Self {
+        let mut head = PARTS.head.to_string();
+        head.push_str(&format!("<style id=\"glypho\">{}</style>", STYLES.concat()));
+        if let Some(css) = config.theme.as_deref().and_then(theme_css) {
+            head.push_str(&format!("<style id=\"theme\">{css}</style>"));
+        }
+        if let Some(css) = &config.extra_css {
+            head.push_str(&user_css(css));
+        }
+        let mut scripts = PARTS.scripts.to_string();
+        if let Some(js) = &config.extra_js {
+            scripts.push_str(&user_js(js));
+        }
+
+        Slots {
+            title: title.to_string(),
+            theme: config.theme.clone(),
+            head,
+            navbar: PARTS.navbar,
+            toc: "<nav id=\"toc\"></nav>",
+            article: PARTS.article,
+            stats: "<footer id=\"stats\"></footer>",
+            editor: if config.safe { "" } else { EDITOR },
+            slides: SLIDE_CONTROLS,
+            scripts,
+        }
+    }
+}
+
+/// What `/print`, `glypho export` and `glypho render --standalone` fill
+/// their page with.
+#[derive(Debug, Serialize)]
+struct PrintSlots {
+    title: String,
+    theme: Option<String>,
+    head: String,
+    toc: String,
+    article: String,
+    scripts: String,
+}
+
+/// A page with rendered `html` alone, laid out for printing with numbered
+/// headings and a table of contents.
+pub fn render_print(config: &Config, title: &str, html: &str) -> String {
+    let Slots {
+        title,
+        theme,
+        mut head,
+        scripts,
+        ..
+    } = Slots::new(config, title);
+    head.push_str(&format!(
+        "<style id=\"print\">{}</style>",
+        include_str!("styles/print-page.css")
+    ));
+    let toc = toc_html(html);
+    let slots = PrintSlots {
+        title,
+        theme,
+        head,
+        // a document without headings has nothing to list
+        toc: if toc.contains("<li") {
+            toc
+        } else {
+            String::new()
+        },
+        article: html.to_string(),
+        scripts,
+    };
+
+    Handlebars::new()
+        .render_template(PRINT_PAGE, &slots)
+        .expect("built-in print template renders")
+}
+
+/// A page with rendered `html` alone, styled like the preview.
+pub fn render_standalone(config: &Config, title: &str, html: &str) -> String {
+    let Slots {
+        title,
+        theme,
+        head,
+        scripts,
+        ..
+    } = Slots::new(config, title);
+    let slots = PrintSlots {
+        title,
+        theme,
+        head,
+        toc: String::new(),
+        article: html.to_string(),
+        scripts,
+    };
+
+    Handlebars::new()
+        .render_template(STANDALONE_PAGE, &slots)
+        .expect("built-in standalone template renders")
+}
+
+/// The `extra_css` element, patched again when the file changes. It is
+/// kept empty while the file cannot be read.
+pub fn user_css(path: &Path) -> String {
+    let css = read_extra(path).replace("</style", "<\\/style");
+    format!("<style id=\"user-css\">{css}</style>")
+}
+
+/// The `extra_js` element, a module so that it runs after the page loaded.
+pub fn user_js(path: &Path) -> String {
+    let js = read_extra(path).replace("</script", "<\\/script");
+    format!("<script type=\"module\" id=\"user-js\">{js}</script>")
+}
+
+fn read_extra(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_else(|err| {
+        warn!("Cannot read {}: {err}", path.display());
+        String::new()
+    })
+}
+
+/// The preview page of `config`, from its template directory or the
+/// built-in template.
+pub fn render_page(config: &Config, title: &str) -> String {
+    let slots = Slots::new(config, title);
+
+    if let Some(dir) = &config.template {
+        match render_template_dir(dir, &slots) {
+            Ok(html) => return html,
+            Err(err) => warn!(
+                "Cannot render the template in {}, using the built-in page: {err}",
+                dir.display()
+            ),
+        }
+    }
+
+    Handlebars::new()
+        .render_template(DEFAULT_PAGE, &slots)
+        .expect("built-in page template renders")
+}
+
+// Read on every page load so that changes to the template show on reload.
+fn render_template_dir(dir: &Path, slots: &Slots) -> eyre::Result<String> {
+    let mut handlebars = Handlebars::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "hbs")
+            && let Some(name) = path.file_stem().and_then(|s| s.to_str())
+        {
+            handlebars.register_template_file(name, &path)?;
+        }
+    }
+    let page = PAGE_TEMPLATE.trim_end_matches(".hbs");
+    if !handlebars.has_template(page) {
+        eyre::bail!("{PAGE_TEMPLATE} is missing");
+    }
+    Ok(handlebars.render(page, slots)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    fn template_dir(files: &[(&str, &str)]) -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        for (name, content) in files {
+            std::fs::write(temp_dir.path().join(name), content).unwrap();
+        }
+        temp_dir
+    }
+
+    // ==================== Built-in Page Tests ====================
+
+    #[test]
+    fn test_parts_cover_template() {
+        assert!(PARTS.head.contains("<script"));
+        assert!(PARTS.navbar.starts_with("<nav id=navbar"));
+        assert!(PARTS.navbar.ends_with("</nav>"));
+        assert!(PARTS.article.contains("<article id=markdown"));
+        assert!(PARTS.article.ends_with("</main>"));
+        assert!(PARTS.scripts.contains("Prism"));
+    }
+
+    #[test]
+    fn test_default_page() {
+        let html = render_page(&Config::default(), "notes.md");
+
+        assert!(html.starts_with("<!DOCTYPE html><html><head><title>notes.md</title>"));
+        assert!(html.contains(PARTS.head));
+        assert!(html.contains(PARTS.navbar));
+        assert!(html.contains(PARTS.article));
+        assert!(html.contains("</main>\n<footer id=\"stats\"></footer>"));
+        assert!(html.ends_with("</body></html>\n"));
+        assert!(!html.contains("id=\"toc\""));
+    }
+
+    #[test]
+    fn test_editor_left_out_in_safe_mode() {
+        assert!(render_page(&Config::default(), "notes.md").contains("<aside id=\"editor\""));
+
+        let config = Config {
+            safe: true,
+            ..Config::default()
+        };
+        assert!(!render_page(&config, "notes.md").contains("id=\"editor\""));
+    }
+
+    #[test]
+    fn test_slide_controls() {
+        let html = render_page(&Config::default(), "talk.md");
+        assert!(html.contains("<div id=\"slides-controls\""));
+        assert!(html.contains("@post('/slides/toggle')"));
+    }
+
+    // ==================== Print Page Tests ====================
+
+    #[test]
+    fn test_print_page() {
+        let html = render_print(
+            &Config::default(),
+            "Guide <draft>",
+            "<h1 id=\"guide\">Guide</h1>\n<p>Text</p>",
+        );
+
+        assert!(html.starts_with("<!DOCTYPE html><html><head><title>Guide &lt;draft&gt;</title>"));
+        assert!(html.contains("<style id=\"print\">"));
+        assert!(html.contains("<body class=\"print-page\">"));
+        assert!(html.contains(
+            "<nav id=\"toc\"><ul><li class=\"toc-h1\"><a href=\"#guide\">Guide</a></li></ul></nav>"
+        ));
+        assert!(html.contains("<h1 id=\"guide\">Guide</h1>\n<p>Text</p>"));
+        // nothing of the preview around the document
+        assert!(!html.contains("id=navbar"));
+        assert!(!html.contains("id=\"editor\""));
+        assert!(!html.contains("@get('/sse')"));
+    }
+
+    #[test]
+    fn test_print_page_without_headings() {
+        let html = render_print(&Config::default(), "notes.md", "<p>Text</p>");
+        assert!(!html.contains("id=\"toc\""));
+    }
+
+    #[test]
+    fn test_standalone_page() {
+        let config = Config {
+            theme: Some("sepia".to_string()),
+            ..Config::default()
+        };
+        let html = render_standalone(&config, "Guide", "<h1 id=\"guide\">Guide</h1>");
+
+        assert!(
+            html.starts_with(
+                "<!DOCTYPE html><html data-theme=\"sepia\"><head><title>Guide</title>"
+            )
+        );
+        assert!(html.contains("<style id=\"theme\">"));
+        assert!(html.contains("<h1 id=\"guide\">Guide</h1>"));
+        assert!(!html.contains("<style id=\"print\">"));
+        assert!(!html.contains("id=navbar"));
+        assert!(!html.contains("@get('/sse')"));
+    }
+
+    #[test]
+    fn test_builtin_styles_before_theme() {
+        let config = Config {
+            theme: Some("sepia".to_string()),
+            ..Config::default()
+        };
+        let html = render_page(&config, "notes.md");
+
+        let builtin = html.find("<style id=\"glypho\">").unwrap();
+        assert!(html[builtin..].contains(".callout-title"));
+        assert!(builtin < html.find("<style id=\"theme\">").unwrap());
+    }
+
+    #[test]
+    fn test_title_escaped() {
+        let html = render_page(&Config::default(), "<script>alert(1)</script>");
+        assert!(html.contains("<title>&lt;script&gt;alert(1)&lt;/script&gt;</title>"));
+    }
+
+    // ==================== Theme Tests ====================
+
+    #[rstest]
+    #[case("github")]
+    #[case("academic")]
+    #[case("sepia")]
+    #[case("high-contrast")]
+    fn test_bundled_theme(#[case] theme: &str) {
+        let config = Config {
+            theme: Some(theme.to_string()),
+            ..Config::default()
+        };
+        let html = render_page(&config, "notes.md");
+
+        assert!(html.contains(&format!("<html data-theme=\"{theme}\">")));
+        assert!(html.contains(&format!("html[data-theme=\"{theme}\"]")));
+    }
+
+    #[test]
+    fn test_unknown_theme_only_sets_attribute() {
+        let config = Config {
+            theme: Some("solarized\"><script>".to_string()),
+            ..Config::default()
+        };
+        let html = render_page(&config, "notes.md");
+
+        assert!(html.contains("<html data-theme=\"solarized&quot;&gt;&lt;script&gt;\">"));
+        assert!(!html.contains("<style id=\"theme\">"));
+    }
+
+    #[test]
+    fn test_user_css_after_theme() {
+        let temp_dir = TempDir::new().unwrap();
+        let css = temp_dir.path().join("custom.css");
+        std::fs::write(&css, "body { font-size: 20px }").unwrap();
+        let config = Config {
+            theme: Some("sepia".to_string()),
+            extra_css: Some(css),
+            ..Config::default()
+        };
+        let html = render_page(&config, "notes.md");
+
+        let theme = html.find("<style id=\"theme\">").unwrap();
+        let user = html
+            .find("<style id=\"user-css\">body { font-size: 20px }</style>")
+            .unwrap();
+        assert!(theme < user);
+        // the minified scripts mention </head> too
+        assert!(user < html.rfind("</head>").unwrap());
+    }
+
+    // ==================== Extra CSS and JS Tests ====================
+
+    #[test]
+    fn test_missing_css_left_empty() {
+        let html = user_css(Path::new("/nonexistent/custom.css"));
+        assert_eq!(html, "<style id=\"user-css\"></style>");
+    }
+
+    #[test]
+    fn test_extra_js_after_builtin_scripts() {
+        let temp_dir = TempDir::new().unwrap();
+        let js = temp_dir.path().join("house.js");
+        std::fs::write(&js, "console.log('house')").unwrap();
+        let config = Config {
+            extra_js: Some(js),
+            ..Config::default()
+        };
+        let html = render_page(&config, "notes.md");
+
+        let script = "<script type=\"module\" id=\"user-js\">console.log('house')</script>";
+        assert!(html.contains(&format!("{}{script}", PARTS.scripts)));
+    }
+
+    #[test]
+    fn test_extra_css_cannot_close_element() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("house.css");
+        std::fs::write(&path, "a::after { content: '</style><script>x</script>' }").unwrap();
+
+        let html = user_css(&path);
+        assert!(html.ends_with("</style>"));
+        assert_eq!(html.matches("</style").count(), 1);
+    }
+
+    #[test]
+    fn test_extra_js_cannot_close_element() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("house.js");
+        std::fs::write(&path, "document.write('</script>')").unwrap();
+
+        assert_eq!(user_js(&path).matches("</script").count(), 1);
+    }
+
+    // ==================== Template Directory Tests ====================
+
+    #[test]
+    fn test_template_dir_with_partials() {
+        let temp_dir = template_dir(&[
+            (
+                "page.hbs",
+                "<html><head>{{{head}}}</head><body>{{> header}}{{{toc}}}{{{article}}}{{{scripts}}}</body></html>",
+            ),
+            ("header.hbs", "<header>{{title}}</header>"),
+        ]);
+        let config = Config {
+            template: Some(temp_dir.path().to_path_buf()),
+            ..Config::default()
+        };
+        let html = render_page(&config, "Notes");
+
+        assert!(html.contains("<body><header>Notes</header><nav id=\"toc\"></nav><main"));
+        assert!(html.contains(PARTS.article));
+        assert!(!html.contains("<nav id=navbar"));
+    }
+
+    #[rstest]
+    #[case(&[("header.hbs", "<header></header>")])]
+    #[case(&[("page.hbs", "{{#if title}}unclosed")])]
+    fn test_broken_template_dir_falls_back(#[case] files: &[(&str, &str)]) {
+        let temp_dir = template_dir(files);
+        let config = Config {
+            template: Some(temp_dir.path().to_path_buf()),
+            ..Config::default()
+        };
+        assert_eq!(
+            render_page(&config, "notes.md"),
+            render_page(&Config::default(), "notes.md")
+        );
+    }
+
+    #[test]
+    fn test_missing_template_dir_falls_back() {
+        let config = Config {
+            template: Some(PathBuf::from("/nonexistent/templates")),
+            ..Config::default()
+        };
+        assert_eq!(
+            render_page(&config, "notes.md"),
+            render_page(&Config::default(), "notes.md")
+        );
+    }
+}
@@ -0,0 +1,335 @@
+use axum::{extract::State, response::Html};
+use eyre::{bail, eyre};
+use lazy_regex::*;
+use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+use crate::{
+    assets::doc_id,
+    cli::ExportFormat,
+    config::{Config, Overrides, command_words},
+    diagnostic::Diagnostic,
+    epub,
+    page::render_print,
+    state::{AppState, InnerState, Output, render_with_diagrams},
+};
+
+/// Characters escaped in the path of a URL.
+pub const URL_PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`');
+
+/// Writes the address of external links after them, paper cannot follow a
+/// link. Links showing their own address are left alone.
+pub fn expand_links(html: &str) -> String {
+    regex_replace_all!(
+        r#"<a href="(https?://[^"]*)"[^>]*>([\s\S]*?)</a>"#,
+        html,
+        |link: &str, href: &str, text: &str| {
+            let text = regex_replace_all!(r"<[^>]*>", text, "");
+            let bare = href.split_once("://").map_or(href, |(_, rest)| rest);
+            if text.trim() == href || text.trim() == bare {
+                link.to_string()
+            } else {
+                format!("{link} <span class=\"link-url\">({href})</span>")
+            }
+        }
+    )
+    .into_owned()
+}
+
+/// The active document alone, to print or save as PDF from the browser.
+pub async fn print_page(State(state): State<Arc<AppState>>) -> Html<String> {
+    let file = state.lock().await.active_file().to_path_buf();
+    let html = render_with_diagrams(&state, |s| s.render_as(&file, Output::Print))
+        .await
+        .unwrap_or_else(|err| Diagnostic::new(&file, &err).to_html());
+    let s = state.lock().await;
+    Html(render_print(&s.config_for(&file), &s.label(&file), &html))
+}
+
+/// `glypho export`: writes the print page of `files` as `format`, or the
+/// book they make as EPUB, and prints where. Returns the exit code, 2 when
+/// it could not be exported.
+pub fn export(
+    files: &[PathBuf],
+    format: ExportFormat,
+    output: Option<&Path>,
+    user_config: &Config,
+    overrides: &Overrides,
+) -> i32 {
+    let file = match (format, files) {
+        (ExportFormat::Epub, _) => return epub::export(files, output, user_config, overrides),
+        (_, [file]) => file,
+        _ => {
+            eprintln!("Only EPUB books are made of several files, export them one at a time");
+            return 2;
+        }
+    };
+    match export_file(file, format, output, user_config, overrides) {
+        Ok(output) => {
+            println!("{}", output.display());
+            0
+        }
+        Err(err) => {
+            Diagnostic::new(file, &err).print();
+            2
+        }
+    }
+}
+
+fn export_file(
+    file: &Path,
+    format: ExportFormat,
+    output: Option<&Path>,
+    user_config: &Config,
+    overrides: &Overrides,
+) -> eyre::Result<PathBuf> {
+    let path = std::path::absolute(file)?;
+    let config = user_config.for_file(&path, overrides)?;
+    let output = match output {
+        Some(output) => std::path::absolute(output)?,
+        None => path.with_extension(format.extension()),
+    };
+    if output == path {
+        bail!("The export would replace {}", file.display());
+    }
+
+    let mut state = InnerState::new(path.clone())
+        .with_config(user_config.clone())
+        .with_overrides(overrides.clone());
+    let html = state.with_diagrams(|s| s.render_as(&path, Output::Print))?;
+    let page = file_urls(&render_print(&config, &state.label(&path), &html), &path);
+    match format {
+        ExportFormat::Html => fs::write(&output, page)?,
+        ExportFormat::Pdf => print_pdf(&config.export.pdf, &page, &output)?,
+        ExportFormat::Epub => unreachable!("EPUB books are exported by epub::export"),
+    }
+    Ok(output)
+}
+
+// The page is opened without the server, its local images and links point
+// at the directory of the document instead of the asset route.
+fn file_urls(html: &str, file: &Path) -> String {
+    let dir = file.parent().unwrap_or(Path::new("/")).to_string_lossy();
+    let url = format!(
+        "file://{}/",
+        utf8_percent_encode(dir.trim_end_matches('/'), URL_PATH)
+    );
+    html.replace(&format!("/files/{}/", doc_id(file)), &url)
+}
+
+fn print_pdf(command: &str, page: &str, output: &Path) -> eyre::Result<()> {
+    let words = command_words(command)
+        .ok_or_else(|| eyre!("The pdf command in [export] has an unclosed quote"))?;
+    let mut words = words.into_iter();
+    let program = words
+        .next()
+        .ok_or_else(|| eyre!("No PDF command configured, set pdf in [export]"))?;
+
+    // created exclusively and removed when dropped, another user cannot
+    // plant the page or read it
+    let mut input = tempfile::Builder::new()
+        .prefix("glypho-export-")
+        .suffix(".html")
+        .tempfile()?;
+    input.write_all(page.as_bytes())?;
+    let result = Command::new(&program)
+        .args(words)
+        .arg(format!("--print-to-pdf={}", output.display()))
+        .arg(input.path())
+        .stdin(Stdio::null())
+        .output();
+
+    let result = match result {
+        Ok(result) => result,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(eyre!(
+                "{program} was not found, install Chromium or set the pdf command in [export]"
+            ));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if !result.status.success() || !output.is_file() {
+        let errors = String::from_utf8_lossy(&result.stderr);
+        bail!(match errors.trim() {
+            "" => format!("{program} failed with {}", result.status),
+            errors => errors.to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    // ==================== Link Tests ====================
+
+    #[rstest]
+    #[case(
+        "<a href=\"https://example.com/docs\">the docs</a>",
+        "<a href=\"https://example.com/docs\">the docs</a> <span class=\"link-url\">(https://example.com/docs)</span>"
+    )]
+    #[case(
+        "<a href=\"https://example.com\">https://example.com</a>",
+        "<a href=\"https://example.com\">https://example.com</a>"
+    )]
+    #[case(
+        "<a href=\"http://www.example.com\">www.example.com</a>",
+        "<a href=\"http://www.example.com\">www.example.com</a>"
+    )]
+    #[case("<a href=\"#intro\">Intro</a>", "<a href=\"#intro\">Intro</a>")]
+    #[case(
+        "<a href=\"/files/0123/guide.md\">Guide</a>",
+        "<a href=\"/files/0123/guide.md\">Guide</a>"
+    )]
+    fn test_expand_links(#[case] html: &str, #[case] expected: &str) {
+        assert_eq!(expand_links(html), expected);
+    }
+
+    #[test]
+    fn test_file_urls() {
+        let file = Path::new("/home/me/my notes/guide.md");
+        let html = format!("<img src=\"/files/{}/img/logo.png\">", doc_id(file));
+        assert_eq!(
+            file_urls(&html, file),
+            "<img src=\"file:///home/me/my%20notes/img/logo.png\">"
+        );
+    }
+
+    // ==================== Export Tests ====================
+
+    fn document(temp_dir: &TempDir) -> PathBuf {
+        let file = temp_dir.path().join("guide.md");
+        fs::write(
+            &file,
+            "# Guide\n\n- [ ] task\n\nSee [the site](https://example.com).\n\n![Logo](logo.png)\n",
+        )
+        .unwrap();
+        file
+    }
+
+    #[test]
+    fn test_export_html() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = document(&temp_dir);
+
+        let code = export(
+            &[file],
+            ExportFormat::Html,
+            None,
+            &Config::default(),
+            &Overrides::default(),
+        );
+        assert_eq!(code, 0);
+
+        let html = fs::read_to_string(temp_dir.path().join("guide.html")).unwrap();
+        assert!(html.contains("<body class=\"print-page\">"));
+        assert!(html.contains("<span class=\"link-url\">(https://example.com)</span>"));
+        assert!(html.contains(&format!(
+            "src=\"file://{}/logo.png\"",
+            temp_dir.path().display()
+        )));
+        // the document alone, without the controls of the preview
+        assert!(!html.contains("<div class=\"lint-gutter\">"));
+        assert!(!html.contains("@post('/task')"));
+    }
+
+    #[test]
+    fn test_export_pdf() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = document(&temp_dir);
+        // stands in for Chromium, copies the page to the PDF path
+        fs::create_dir(temp_dir.path().join("Google Chrome")).unwrap();
+        let program = temp_dir.path().join("Google Chrome/fake-chromium");
+        fs::write(&program, "#!/bin/sh\ncp \"$3\" \"${2#--print-to-pdf=}\"\n").unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+        let mut config = Config::default();
+        config.export.pdf = format!("'{}' --headless", program.display());
+        let output = temp_dir.path().join("out/guide.pdf");
+        fs::create_dir(temp_dir.path().join("out")).unwrap();
+
+        let code = export(
+            &[file],
+            ExportFormat::Pdf,
+            Some(&output),
+            &config,
+            &Overrides::default(),
+        );
+        assert_eq!(code, 0);
+        assert!(fs::read_to_string(&output).unwrap().contains("print-page"));
+    }
+
+    #[rstest]
+    #[case("no-such-pdf-program --headless")]
+    #[case("false")]
+    #[case("")]
+    #[case("\"/Applications/Google Chrome.app/chrome --headless")]
+    fn test_export_pdf_fails(#[case] command: &str) {
+        let temp_dir = TempDir::new().unwrap();
+        let file = document(&temp_dir);
+        let mut config = Config::default();
+        config.export.pdf = command.to_string();
+
+        assert_eq!(
+            export(
+                &[file],
+                ExportFormat::Pdf,
+                None,
+                &config,
+                &Overrides::default()
+            ),
+            2
+        );
+        assert!(!temp_dir.path().join("guide.pdf").exists());
+    }
+
+    #[test]
+    fn test_export_does_not_replace_document() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = document(&temp_dir);
+
+        let code = export(
+            std::slice::from_ref(&file),
+            ExportFormat::Html,
+            Some(&file),
+            &Config::default(),
+            &Overrides::default(),
+        );
+        assert_eq!(code, 2);
+        assert!(fs::read_to_string(&file).unwrap().starts_with("# Guide"));
+    }
+
+    #[test]
+    fn test_export_several_files_only_as_epub() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = document(&temp_dir);
+
+        let files = [file.clone(), file];
+        let code = export(
+            &files,
+            ExportFormat::Html,
+            None,
+            &Config::default(),
+            &Overrides::default(),
+        );
+        assert_eq!(code, 2);
+        assert!(!temp_dir.path().join("guide.html").exists());
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    env,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    config::{Config, Overrides},
+    diagnostic::Diagnostic,
+    dialect::Dialect,
+    frontmatter::Frontmatter,
+    page::render_standalone,
+    state::{InnerState, Output},
+};
+
+/// Renders Markdown like the preview does, with the dialect, configuration
+/// and sanitizing of the document, for programs using glypho as a library.
+///
+/// ```
+/// use glypho::{Dialect, Renderer};
+/// use std::path::Path;
+///
+/// let html = Renderer::new()
+///     .with_dialect(Dialect::Gfm)
+///     .render_str(Path::new("notes.md"), "# Notes\n\n- [x] done\n")
+///     .unwrap();
+/// assert!(html.starts_with("<h1>Notes</h1>"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    config: Config,
+    overrides: Overrides,
+    output: Output,
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Renderer {
+            config: Config::default(),
+            overrides: Overrides::default(),
+            output: Output::Fragment,
+        }
+    }
+}
+
+impl Renderer {
+    /// A renderer with the default configuration, for HTML fragments.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The user configuration, `.glypho.toml` files of projects still apply.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Settings that win over every configuration file.
+    pub fn with_overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.config.markdown.dialect = Some(dialect);
+        self
+    }
+
+    /// Sanitizes every document and hands none to other programs.
+    pub fn with_safe(mut self, safe: bool) -> Self {
+        self.config.safe = safe;
+        self
+    }
+
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn render_file(&self, file: &Path) -> eyre::Result<String> {
+        let file = std::path::absolute(file)?;
+        self.state(&file)
+            .with_diagrams(|s| s.render_as(&file, self.output))
+    }
+
+    /// Renders `content` as if it were `file`, whose directory and extension
+    /// pick the configuration and dialect.
+    pub fn render_str(&self, file: &Path, content: &str) -> eyre::Result<String> {
+        let file = std::path::absolute(file)?;
+        self.state(&file)
+            .with_diagrams(|s| s.render_source(&file, content.to_string(), self.output))
+    }
+
+    /// The frontmatter title of `file`, or its file name.
+    pub fn title(&self, file: &Path) -> String {
+        self.state(file).label(file)
+    }
+
+    fn state(&self, file: &Path) -> InnerState {
+        InnerState::new(file.to_path_buf())
+            .with_config(self.config.clone())
+            .with_overrides(self.overrides.clone())
+    }
+}
+
+/// `glypho render`: prints the HTML of `file`, or of standard input for
+/// `-`, and returns the exit code, 2 when it could not be rendered.
+pub fn print_html(
+    file: &Path,
+    standalone: bool,
+    user_config: &Config,
+    overrides: &Overrides,
+) -> i32 {
+    match render_file(file, standalone, user_config, overrides) {
+        Ok(html) => {
+            // a pipe closed early, like `| head`, is not an error
+            let _ = io::stdout().write_all(html.as_bytes());
+            0
+        }
+        Err(err) => {
+            Diagnostic::new(file, &err).print();
+            2
+        }
+    }
+}
+
+fn render_file(
+    file: &Path,
+    standalone: bool,
+    user_config: &Config,
+    overrides: &Overrides,
+) -> eyre::Result<String> {
+    let renderer = Renderer::new()
+        .with_config(user_config.clone())
+        .with_overrides(overrides.clone());
+    let (path, html, title) = if file == Path::new("-") {
+        // standard input is a document of the current directory
+        let path = env::current_dir()?.join(file);
+        let mut content = String::new();
+        io::stdin().read_to_string(&mut content)?;
+        let title = Frontmatter::parse(&content)
+            .and_then(|frontmatter| frontmatter.title)
+            .unwrap_or_else(|| "stdin".to_string());
+        (path.clone(), renderer.render_str(&path, &content)?, title)
+    } else {
+        let path = std::path::absolute(file)?;
+        (
+            path.clone(),
+            renderer.render_file(&path)?,
+            renderer.title(&path),
+        )
+    };
+
+    if !standalone {
+        return Ok(html);
+    }
+    let config = user_config.for_file(&path, overrides)?;
+    Ok(render_standalone(&config, &title, &html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn document(temp_dir: &TempDir) -> std::path::PathBuf {
+        let file = temp_dir.path().join("notes.md");
+        fs::write(
+            &file,
+            "---\ntitle: Notes\n---\n\n# Notes\n\n- [ ] task\n\n![Logo](img/logo.png) [[other]]\n\nTrailing  \n",
+        )
+        .unwrap();
+        file
+    }
+
+    // ==================== Render Tests ====================
+
+    #[test]
+    fn test_render_fragment() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = document(&temp_dir);
+
+        let html = render_file(&file, false, &Config::default(), &Overrides::default()).unwrap();
+        assert!(html.starts_with("<h1>Notes</h1>"));
+        // links as written, wikilinks and tasks like the preview renders them
+        assert!(html.contains("src=\"img/logo.png\""));
+        assert!(html.contains(">other</a>"));
+        assert!(html.contains("<input disabled=\"\" type=\"checkbox\">"));
+        // none of the preview around the document
+        assert!(!html.contains("<div class=\"lint-gutter\">"));
+        assert!(!html.contains("@post('/task')"));
+        assert!(!html.contains("/files/"));
+    }
+
+    #[test]
+    fn test_render_standalone() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = document(&temp_dir);
+
+        let html = render_file(&file, true, &Config::default(), &Overrides::default()).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html><html><head><title>Notes</title>"));
+        assert!(html.contains("<h1>Notes</h1>"));
+        assert!(html.contains("<style id=\"glypho\">"));
+    }
+
+    #[test]
+    fn test_renderer_options() {
+        let renderer = Renderer::new();
+        let file = Path::new("/docs/notes.md");
+        let content = "Text | [[page]]\n\n<b onclick=\"x()\">bold</b>\n";
+
+        let html = renderer.render_str(file, content).unwrap();
+        assert!(html.contains("<b>bold</b>"));
+        assert!(html.contains("href=\"page\""));
+
+        let html = renderer
+            .clone()
+            .with_dialect(Dialect::Commonmark)
+            .render_str(file, "~~struck~~")
+            .unwrap();
+        assert_eq!(html, "<p>~~struck~~</p>");
+
+        let html = renderer
+            .with_output(Output::Preview)
+            .render_str(file, "- [ ] task\n")
+            .unwrap();
+        assert!(html.contains("@post('/task')"));
+    }
+
+    #[test]
+    fn test_render_missing_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("missing.md");
+
+        assert!(render_file(&file, false, &Config::default(), &Overrides::default()).is_err());
+        assert_eq!(
+            print_html(&file, false, &Config::default(), &Overrides::default()),
+            2
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use ammonia::Builder;
+use std::sync::LazyLock;
+
+// Prefix of the ids of documents, the one GitHub and footnotes use.
+const ID_PREFIX: &str = "user-content-";
+
+// ammonia's allowlist already drops scripts, iframes, event handlers and
+// javascript: URLs, the additions keep what the Markdown renderer produces.
+static SANITIZER: LazyLock<Builder<'static>> = LazyLock::new(|| {
+    let mut builder = Builder::default();
+    builder
+        // languages of code blocks for Prism, ids of footnotes, the blocks
+        // lint markers and slides are placed by
+        .add_generic_attributes(["class", "id", "data-block"])
+        // task list items
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["checked", "disabled"])
+        .set_tag_attribute_value("input", "type", "checkbox")
+        .add_tag_attributes("td", ["align"])
+        .add_tag_attributes("th", ["align"])
+        // the page has elements of its own with ids, like `navbar` or `toc`,
+        // which the preview patches by id. Footnotes are already prefixed
+        // like on GitHub, headings get their ids after sanitizing.
+        .attribute_filter(|_, attribute, value| match attribute {
+            "id" if !value.starts_with(ID_PREFIX) => Some(format!("{ID_PREFIX}{value}").into()),
+            _ => Some(value.into()),
+        });
+    builder
+});
+
+/// Rendered HTML with everything that could run code in the preview removed.
+pub fn sanitize_html(html: &str) -> String {
+    SANITIZER.clean(html).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    // ==================== Removal Tests ====================
+
+    #[rstest]
+    #[case("<script>alert(1)</script>", "<script")]
+    #[case("<img src=\"x.png\" onerror=\"alert(1)\">", "onerror")]
+    #[case("<iframe src=\"https://example.com\"></iframe>", "<iframe")]
+    #[case("<a href=\"javascript:alert(1)\">x</a>", "javascript:")]
+    #[case("<object data=\"x.swf\"></object>", "<object")]
+    #[case("<style>body { display: none }</style>", "<style")]
+    #[case("<form action=\"/stop\"><button>go</button></form>", "<form")]
+    #[case("<div style=\"position: fixed\">x</div>", "style=")]
+    fn test_dangerous_html_removed(#[case] html: &str, #[case] forbidden: &str) {
+        assert!(!sanitize_html(html).contains(forbidden));
+    }
+
+    #[test]
+    fn test_input_forced_to_checkbox() {
+        let html = sanitize_html("<input type=\"text\" name=\"password\">");
+        assert_eq!(html, "<input type=\"checkbox\">");
+    }
+
+    // ==================== Markdown Output Tests ====================
+
+    #[rstest]
+    #[case("<pre><code class=\"language-rust\">fn main() {}</code></pre>")]
+    #[case("<table><thead><tr><th align=\"right\">n</th></tr></thead></table>")]
+    #[case("<p><img src=\"images/logo.png\" alt=\"logo\"></p>")]
+    fn test_markdown_output_kept(#[case] html: &str) {
+        assert_eq!(sanitize_html(html), html);
+    }
+
+    #[rstest]
+    #[case(
+        "<div id=\"navbar\">x</div>",
+        "<div id=\"user-content-navbar\">x</div>"
+    )]
+    #[case(
+        "<h2 id=\"intro\">Intro</h2>",
+        "<h2 id=\"user-content-intro\">Intro</h2>"
+    )]
+    #[case(
+        "<sup><a href=\"#user-content-fn-1\" id=\"user-content-fnref-1\">1</a></sup>",
+        "<sup><a href=\"#user-content-fn-1\" id=\"user-content-fnref-1\" rel=\"noopener noreferrer\">1</a></sup>"
+    )]
+    fn test_ids_prefixed(#[case] html: &str, #[case] expected: &str) {
+        assert_eq!(sanitize_html(html), expected);
+    }
+
+    #[test]
+    fn test_task_list_kept() {
+        let html =
+            sanitize_html("<li><input type=\"checkbox\" disabled=\"\" checked=\"\"> done</li>");
+        assert!(html.contains("type=\"checkbox\""));
+        assert!(html.contains("checked=\"\""));
+        assert!(html.contains("disabled=\"\""));
+    }
+
+    #[rstest]
+    #[case("https://example.com")]
+    #[case("other.md#section")]
+    fn test_links_kept_with_rel(#[case] href: &str) {
+        let html = sanitize_html(&format!("<a href=\"{href}\">x</a>"));
+        assert_eq!(
+            html,
+            format!("<a href=\"{href}\" rel=\"noopener noreferrer\">x</a>")
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use axum::{
+    Router, middleware,
+    routing::{get, post},
+};
+use std::sync::Arc;
+
+use crate::{
+    assets::{serve_asset, serve_document_asset},
+    auth::{AccessToken, require_local_host, require_same_origin, require_token},
+    editor::{load_source, preview_source, save_source},
+    frontmatter::list_tagged,
+    print::print_page,
+    slides::{go_to_slide, toggle_slides},
+    state::{AppState, InnerState, change_active, event_handler, open_file, root},
+    stats::document_stats,
+    tasks::toggle_task,
+};
+
+/// The preview of a session, as an axum `Router` to serve or embed.
+///
+/// ```no_run
+/// # async fn run() -> eyre::Result<()> {
+/// use glypho::{InnerState, Server};
+///
+/// let server = Server::new(InnerState::new("notes.md".into()));
+/// let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+/// axum::serve(listener, server.router()).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Server {
+    state: Arc<AppState>,
+    token: Option<AccessToken>,
+}
+
+impl Server {
+    pub fn new(state: InnerState) -> Self {
+        Server {
+            state: Arc::new(AppState::new(state)),
+            token: None,
+        }
+    }
+
+    /// Rejects every request without `token`, in the query or its cookie.
+    pub fn with_token(mut self, token: AccessToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// The state the routes share, to add files or follow its events while
+    /// the preview is served.
+    pub fn state(&self) -> Arc<AppState> {
+        self.state.clone()
+    }
+
+    pub fn router(self) -> Router {
+        // the routes that change the documents on disk or the session
+        let documents = Router::new()
+            .route("/task", post(toggle_task))
+            .route("/source", get(load_source).post(save_source))
+            .route("/source/preview", post(preview_source))
+            .route("/slides/go", post(go_to_slide))
+            .route("/slides/toggle", post(toggle_slides))
+            .route("/update", post(change_active))
+            .route("/open", post(open_file))
+            .route_layer(middleware::from_fn(require_same_origin));
+        let router = Router::new()
+            .route("/", get(root))
+            .route("/files/{doc}/{*path}", get(serve_document_asset))
+            .fallback(serve_asset)
+            .route("/sse", get(event_handler))
+            .route("/tags/{tag}", get(list_tagged))
+            .route("/stats", get(document_stats))
+            .route("/print", get(print_page))
+            .merge(documents)
+            .with_state(self.state);
+
+        match self.token {
+            Some(token) => router.layer(middleware::from_fn_with_state(
+                Arc::new(token),
+                require_token,
+            )),
+            // without token, any page a rebound DNS name points to could
+            // read the documents
+            None => router.layer(middleware::from_fn(require_local_host)),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::path::Path;
+
+use crate::error::GlyphoError;
+
+/// Identifies one running glypho instance. Files opened in the same session
+/// share a server, a navbar and a browser tab.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    name: String,
+}
+
+impl Session {
+    /// A session chosen explicitly with `--session`.
+    pub fn named(name: &str) -> Result<Self, GlyphoError> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+        if valid {
+            Ok(Session {
+                name: name.to_string(),
+            })
+        } else {
+            Err(GlyphoError::InvalidSession(name.to_string()))
+        }
+    }
+
+    /// The automatic session of `file`: its git root, or its directory when
+    /// the file is not inside a repository.
+    pub fn for_file(file: &Path) -> Self {
+        Self::for_workspace(workspace_root(file))
+    }
+
+    fn for_workspace(workspace: &Path) -> Self {
+        let label: String = workspace
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .take(32)
+            .collect();
+
+        // the hash keeps same-named directories apart and the socket path short
+        let hash = fnv1a(workspace.as_os_str().as_encoded_bytes());
+        Session {
+            name: format!("{label}-{hash:016x}"),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// The git root of `file`, or its directory when the file is not inside a
+/// repository.
+pub fn workspace_root(file: &Path) -> &Path {
+    let dir = file.parent().unwrap_or(Path::new("/"));
+    dir.ancestors()
+        .find(|d| d.join(".git").exists())
+        .unwrap_or(dir)
+}
+
+// Stable across builds, unlike `DefaultHasher`, so that different glypho
+// versions agree on the session of a directory and on document ids.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    // ==================== Named Session Tests ====================
+
+    #[rstest]
+    #[case("work")]
+    #[case("my-project")]
+    #[case("notes_2024")]
+    #[case("v1.2")]
+    fn test_named_session_valid(#[case] name: &str) {
+        let session = Session::named(name).unwrap();
+        assert_eq!(session.name(), name);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("../escape")]
+    #[case("a/b")]
+    #[case(".hidden")]
+    #[case("with space")]
+    fn test_named_session_invalid(#[case] name: &str) {
+        let result = Session::named(name);
+        assert!(matches!(result, Err(GlyphoError::InvalidSession(_))));
+    }
+
+    // ==================== Automatic Session Tests ====================
+
+    #[test]
+    fn test_session_keyed_by_git_root() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join(".git")).unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("docs/guide")).unwrap();
+
+        let top = Session::for_file(&temp_dir.path().join("README.md"));
+        let nested = Session::for_file(&temp_dir.path().join("docs/guide/intro.md"));
+
+        assert_eq!(top, nested);
+    }
+
+    #[test]
+    fn test_session_keyed_by_directory_outside_git() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("a")).unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("b")).unwrap();
+
+        let a1 = Session::for_file(&temp_dir.path().join("a/one.md"));
+        let a2 = Session::for_file(&temp_dir.path().join("a/two.md"));
+        let b = Session::for_file(&temp_dir.path().join("b/one.md"));
+
+        assert_eq!(a1, a2);
+        assert_ne!(a1, b);
+    }
+
+    #[test]
+    fn test_session_same_dir_name_different_parent() {
+        let s1 = Session::for_workspace(Path::new("/home/me/work/docs"));
+        let s2 = Session::for_workspace(Path::new("/home/me/play/docs"));
+
+        assert_ne!(s1, s2);
+        assert!(s1.name().starts_with("docs-"));
+    }
+
+    #[test]
+    fn test_automatic_session_name_is_valid() {
+        let session = Session::for_workspace(Path::new("/tmp/My Notes (old)"));
+        assert!(Session::named(session.name()).is_ok());
+    }
+
+    #[test]
+    fn test_fnv1a_is_stable() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+}
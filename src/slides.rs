@@ -0,0 +1,209 @@
+use axum::{Json, extract::State, response::IntoResponse};
+use datastar::axum::ReadSignals;
+use markdown::mdast::Node;
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::{
+    blocks::blocks,
+    frontmatter::escape_html,
+    state::{AddFileResponse, AppState},
+};
+
+/// The slide controls of the preview page.
+pub const SLIDE_CONTROLS: &str = include_str!("templates/slides.html");
+
+/// The slide a preview asks every window to show, set by the keyboard.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SlideSignals {
+    pub slide_to: i64,
+}
+
+#[derive(Debug, Default)]
+struct Slide {
+    html: String,
+    notes: Vec<String>,
+}
+
+/// Splits a rendered document into `<section class="slide">` elements, one
+/// shown at a time. Documents with `---` breaks are split on them, others
+/// before every `##` heading. HTML comments become the speaker notes of
+/// their slide. `tree` is the document `html` was rendered from, its blocks
+/// tagged by `tag_blocks`. Returns the slides and their number.
+pub fn slides(html: &str, tree: &Node) -> (String, usize) {
+    let (html, blocks) = blocks(html, tree);
+    let html = html.as_str();
+    let on_breaks = blocks
+        .iter()
+        .any(|(_, node)| matches!(node, Node::ThematicBreak(_)));
+
+    // where each slide after the first starts in `html`, and on which line
+    let mut cuts = vec![];
+    for (start, node) in &blocks {
+        let line = node.position().map_or(0, |p| p.start.line);
+        match node {
+            // the break itself is not shown
+            Node::ThematicBreak(_) if on_breaks => {
+                let end = html[*start..].find('>').map_or(*start, |i| start + i + 1);
+                cuts.push((*start, end, line));
+            }
+            Node::Heading(heading) if !on_breaks && heading.depth == 2 => {
+                cuts.push((*start, *start, line));
+            }
+            _ => {}
+        }
+    }
+
+    let mut slides: Vec<Slide> = vec![];
+    let mut from = 0;
+    for (end, next, _) in &cuts {
+        slides.push(Slide {
+            html: html[from..*end].to_string(),
+            ..Slide::default()
+        });
+        from = *next;
+    }
+    slides.push(Slide {
+        html: html[from..].to_string(),
+        ..Slide::default()
+    });
+
+    for node in tree.children().into_iter().flatten() {
+        let Node::Html(comment) = node else {
+            continue;
+        };
+        let Some(note) = comment
+            .value
+            .trim()
+            .strip_prefix("<!--")
+            .and_then(|note| note.strip_suffix("-->"))
+        else {
+            continue;
+        };
+        let line = node.position().map_or(0, |p| p.start.line);
+        let slide = cuts.iter().filter(|(_, _, cut)| *cut <= line).count();
+        slides[slide].notes.push(note.trim().to_string());
+    }
+
+    // a document starting with a heading has nothing before its first cut
+    slides.retain(|slide| !slide.html.trim().is_empty() || !slide.notes.is_empty());
+    if slides.is_empty() {
+        slides.push(Slide::default());
+    }
+
+    let sections: Vec<String> = slides
+        .iter()
+        .enumerate()
+        .map(|(i, slide)| {
+            let notes: String = slide
+                .notes
+                .iter()
+                .map(|note| format!("<p>{}</p>", escape_html(note)))
+                .collect();
+            format!(
+                "<section class=\"slide\" data-class:current=\"$slide == {i}\" data-class:next=\"$slide + 1 == {i}\">\n{}\n<aside class=\"notes\">{notes}</aside>\n</section>",
+                slide.html.trim()
+            )
+        })
+        .collect();
+    (
+        format!("<div class=\"slides\">\n{}\n</div>", sections.join("\n")),
+        slides.len(),
+    )
+}
+
+/// Shows another slide in every window of the session.
+pub async fn go_to_slide(
+    State(state): State<Arc<AppState>>,
+    ReadSignals(signals): ReadSignals<SlideSignals>,
+) -> impl IntoResponse {
+    state.lock().await.go_to_slide(signals.slide_to);
+    Json(AddFileResponse { ok: true })
+}
+
+/// Switches every window of the session between the document and slides.
+pub async fn toggle_slides(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut s = state.lock().await;
+    let slides = !s.slides();
+    s.set_slides(slides);
+    Json(AddFileResponse { ok: true })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dialect::Dialect, safe::sanitize_html, testing::tagged};
+    use insta::assert_snapshot;
+    use rstest::rstest;
+
+    fn render(content: &str) -> (String, usize) {
+        let (html, tree) = tagged(content, Dialect::Gfm);
+        slides(&html, &tree)
+    }
+
+    // ==================== Split Tests ====================
+
+    #[rstest]
+    #[case("# Talk\n\nIntro\n\n---\n\n## One\n\n---\n\n## Two", 3)]
+    #[case("# Talk\n\nIntro\n\n## One\n\ntext\n\n## Two\n\n### Detail", 3)]
+    #[case("## One\n\n## Two", 2)]
+    #[case("Just one paragraph", 1)]
+    #[case("", 1)]
+    fn test_slide_count(#[case] content: &str, #[case] count: usize) {
+        assert_eq!(render(content).1, count);
+    }
+
+    #[test]
+    fn test_breaks_win_over_headings() {
+        let (html, count) = render("## One\n\n## Still one\n\n---\n\n## Two");
+        assert_eq!(count, 2);
+        assert!(!html.contains("<hr"));
+    }
+
+    #[test]
+    fn test_headings_start_slides() {
+        let (html, _) = render("# Talk\n\n## One\n\ntext");
+        assert!(html.contains(
+            "<section class=\"slide\" data-class:current=\"$slide == 1\" data-class:next=\"$slide + 1 == 1\">\n<h2>One</h2>"
+        ));
+    }
+
+    #[test]
+    fn test_breaks_after_dropped_html() {
+        let content = "# Talk\n\n<script>x()</script>\n\n---\n\n## One\n\ntext\n";
+        // without the tagfilter of GFM the script reaches the sanitizer
+        let (html, tree) = tagged(content, Dialect::Commonmark);
+        let html = sanitize_html(&html);
+
+        let (html, count) = slides(&html, &tree);
+        assert_eq!(count, 2);
+        assert!(!html.contains("<hr"));
+        assert!(html.contains("data-class:next=\"$slide + 1 == 1\">\n<h2>One</h2>"));
+    }
+
+    #[test]
+    fn test_nested_breaks_not_split() {
+        let (_, count) = render("> quoted\n>\n> ---\n>\n> still quoted\n\n- a\n\n  ---\n");
+        assert_eq!(count, 1);
+    }
+
+    // ==================== Notes Tests ====================
+
+    #[test]
+    fn test_notes_from_comments() {
+        let (html, _) = render(
+            "# One\n\n<!-- Welcome everyone -->\n\n---\n\n# Two\n\n<!--\nMention the <demo>\n-->\n",
+        );
+        assert!(html.contains("<aside class=\"notes\"><p>Welcome everyone</p></aside>"));
+        assert!(html.contains("<aside class=\"notes\"><p>Mention the &lt;demo&gt;</p></aside>"));
+    }
+
+    #[test]
+    fn test_slides_snapshot() {
+        let (html, _) = render(
+            "# Quarterly review\n\n<!-- Thank the team -->\n\n---\n\n## Numbers\n\n- up\n- down\n\n---\n\n## Questions?\n",
+        );
+        assert_snapshot!(html);
+    }
+}
@@ -1,4 +1,26 @@
-use crate::{error::GlyphoError, template::TEMPLATE, wikilinks::wikilinks_to_markdown};
+use crate::{
+    assets::{doc_id, referenced_assets, rewrite_relative_urls},
+    blocks::tag_blocks,
+    callouts::callouts,
+    config::{Config, Overrides},
+    diagnostic::Diagnostic,
+    diagrams::{
+        Diagram, DiagramCache, Rendered, cache_rendered, render_all, render_all_blocking,
+        render_diagrams, untrusted_diagrams,
+    },
+    dialect::math_for_mathjax,
+    error::GlyphoError,
+    frontmatter::{Frontmatter, escape_html, is_markdown, js_string},
+    lint::{check, gutter},
+    page::{render_page, user_css},
+    print::expand_links,
+    safe::sanitize_html,
+    session::workspace_root,
+    slides::slides,
+    stats::Stats,
+    tasks::{task_checkboxes, version},
+    toc::{heading_ids, toc_html},
+};
 use async_watcher::{
     AsyncDebouncer, DebouncedEvent,
     notify::{self, RecommendedWatcher, RecursiveMode},
@@ -6,7 +28,7 @@ use async_watcher::{
 use asynk_strim::{Yielder, stream_fn};
 use axum::{
     Json,
-    extract::{self, State},
+    extract::State,
     response::{
         Html, IntoResponse,
         sse::{Event, Sse},
@@ -19,20 +41,21 @@ use datastar::{
     prelude::{ExecuteScript, PatchElements, PatchSignals},
 };
 use eyre::bail;
-use futures::FutureExt;
-use markdown::{CompileOptions, Constructs, Options, ParseOptions};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     convert::Infallible,
     fs,
     path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
-use tokio::sync::{
-    Mutex, MutexGuard,
-    broadcast::{self, Sender},
+use tokio::{
+    sync::{
+        Mutex,
+        broadcast::{self, Receiver, Sender},
+    },
+    task::AbortHandle,
 };
 
 use tracing::*;
@@ -48,13 +71,14 @@ pub struct AddFileRequest {
     pub file: PathBuf,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct AddFileResponse {
     pub ok: bool,
 }
 
 pub async fn debounce_watch<P: AsRef<Path>>(
     path: P,
+    timeout: Duration,
 ) -> Result<
     (
         tokio::sync::mpsc::Receiver<Result<Vec<DebouncedEvent>, Vec<notify::Error>>>,
@@ -64,8 +88,7 @@ pub async fn debounce_watch<P: AsRef<Path>>(
 > {
     let (tx, rx) = tokio::sync::mpsc::channel(100);
 
-    let mut debouncer =
-        AsyncDebouncer::new(Duration::from_secs(5), Some(Duration::from_secs(4)), tx).await?;
+    let mut debouncer = AsyncDebouncer::new(timeout, Some(timeout * 4 / 5), tx).await?;
 
     // Add the paths to the watcher
     debouncer
@@ -87,9 +110,11 @@ pub async fn watch_file(file: PathBuf, state: Arc<AppState>) {
     {
         local_state.lock().await.watched_files.push(file.clone());
         debug!("file not watched");
-        tokio::spawn(async move {
+        let debounce = local_state.lock().await.config_for(&file).debounce();
+        let watched = file.clone();
+        let watcher = tokio::spawn(async move {
             let dir = file.parent().expect("Reading file error");
-            let (mut file_events, _debouncer) = debounce_watch(dir)
+            let (mut file_events, _debouncer) = debounce_watch(dir, debounce)
                 .await
                 .expect("Cannot get debouncer channel");
             while let Some(file_watcher_events) = file_events.recv().await {
@@ -106,32 +131,21 @@ pub async fn watch_file(file: PathBuf, state: Arc<AppState>) {
                             .map(|(f1, f2)| f1 == f2)
                             .unwrap_or(false)
                         {
-                            // let mut s = local_state.lock().await;
-                            let buffer = {
-                                local_state
-                                    .lock()
-                                    .await
-                                    .files
-                                    .get(&file)
-                                    .map(|b| b.to_owned())
-                            };
-                            let rendered = { local_state.lock().await.render(&file) };
-
-                            {
-                                let mut s = local_state.lock().await;
-                                if let Some((html, buf)) = rendered.as_ref().ok().zip(buffer) {
-                                    s.reload_file(&file, buf.to_owned(), html.clone());
-                                };
+                            // the file was removed through the control socket
+                            if !local_state.lock().await.files.contains_key(&file) {
+                                return;
                             }
 
-                            // s.reload_file(file, buffer.unwrap(), rendered);
-
-                            let html: String = match rendered {
-                                Ok(v) => v,
-                                Err(err) => {
-                                    format!("Something weird happened:{}", err)
-                                }
-                            };
+                            // let mut s = local_state.lock().await;
+                            // the diagnostic is printed once per change, not
+                            // once per preview showing the file
+                            let html = render_with_diagrams(&local_state, |s| {
+                                s.try_render(&file).unwrap_or_else(|diagnostic| {
+                                    diagnostic.print();
+                                    s.diagnosed(&file, &diagnostic)
+                                })
+                            })
+                            .await;
 
                             let _ = {
                                 local_state.lock().await.event_sender.send(
@@ -146,21 +160,85 @@ pub async fn watch_file(file: PathBuf, state: Arc<AppState>) {
                 }
             }
         });
+        state
+            .lock()
+            .await
+            .watchers
+            .insert(watched, watcher.abort_handle());
     }
 }
 
-pub async fn add_file(
-    State(state): State<Arc<AppState>>,
-    extract::Json(file_request): extract::Json<AddFileRequest>,
-) -> impl IntoResponse {
+/// Watches the `extra_css` or `extra_js` file at `path` so that previews
+/// pick up changes without a reload by hand.
+pub async fn watch_extra(path: PathBuf, state: Arc<AppState>) {
+    let path = std::path::absolute(&path).unwrap_or(path);
+    let debounce = {
+        let mut s = state.lock().await;
+        if s.watched_extras.contains(&path) {
+            return;
+        }
+        s.watched_extras.push(path.clone());
+        s.config.debounce()
+    };
+
+    tokio::spawn(async move {
+        let Some(dir) = path.parent() else {
+            return;
+        };
+        let watch = debounce_watch(dir, debounce)
+            .await
+            .map_err(|err| err.to_string());
+        let (mut events, _debouncer) = match watch {
+            Ok(watch) => watch,
+            Err(err) => {
+                warn!("Cannot watch {}: {err}", path.display());
+                state.lock().await.watched_extras.retain(|p| p != &path);
+                return;
+            }
+        };
+        while let Some(Ok(evs)) = events.recv().await {
+            if evs.iter().any(|ev| ev.path == path) {
+                debug!("{} changed", path.display());
+                let _ = state
+                    .lock()
+                    .await
+                    .event_sender
+                    .send(SignalEvents::ExtraChanged { path: path.clone() });
+            }
+        }
+    });
+}
+
+/// Runs `render` on the state, and once more after rendering the diagrams
+/// it is missing. The renderers run without holding the state.
+pub async fn render_with_diagrams<T>(
+    state: &AppState,
+    mut render: impl FnMut(&mut InnerState) -> T,
+) -> T {
+    let (result, diagrams) = {
+        let mut s = state.lock().await;
+        let result = render(&mut s);
+        (result, s.take_diagrams())
+    };
+    if diagrams.is_empty() {
+        return result;
+    }
+
+    let rendered = render_all(diagrams).await;
+    let mut s = state.lock().await;
+    s.add_diagrams(rendered);
+    render(&mut s)
+}
+
+/// Starts tracking `file`, watches it for changes and refreshes the navbar.
+pub async fn add_file(state: Arc<AppState>, file: PathBuf) {
     let buffer = BytesMut::with_capacity(4096);
-    let file = file_request.file.clone();
     state
         .lock()
-        .then(|mut s: MutexGuard<InnerState>| async move {
-            s.files.insert(file_request.file.clone(), buffer);
-        })
-        .await;
+        .await
+        .files
+        .entry(file.clone())
+        .or_insert(buffer);
 
     watch_file(file, state.clone()).await;
 
@@ -169,29 +247,96 @@ pub async fn add_file(
         .await
         .event_sender
         .send(SignalEvents::AddedNewFile);
+}
+
+/// Stops tracking `file`. When it was the active file, the first remaining
+/// file becomes active. The last tracked file cannot be removed.
+pub async fn remove_file(state: Arc<AppState>, file: PathBuf) -> bool {
+    let mut s = state.lock().await;
+    if !s.files.contains_key(&file) || s.files.len() == 1 {
+        return false;
+    }
 
-    Json(AddFileResponse { ok: true })
+    s.files.remove(&file);
+    s.assets.remove(&file);
+    s.watched_files.retain(|f| f != &file);
+    if let Some(watcher) = s.watchers.remove(&file) {
+        watcher.abort();
+    }
+    let _ = s.event_sender.send(SignalEvents::AddedNewFile);
+
+    if s.active_file == file
+        && let Some(next) = s.files.keys().next().cloned()
+    {
+        s.active_file = next;
+        s.slide = 0;
+        let _ = s.event_sender.send(SignalEvents::ActiveFileChanged);
+    }
+
+    true
+}
+
+/// Makes an already tracked `file` the active one.
+pub async fn switch_file(state: Arc<AppState>, file: PathBuf) -> bool {
+    let mut s = state.lock().await;
+    if !s.files.contains_key(&file) {
+        return false;
+    }
+
+    s.active_file = file;
+    s.slide = 0;
+    let _ = s.event_sender.send(SignalEvents::ActiveFileChanged);
+    true
 }
 
 pub async fn change_active(
     State(state): State<Arc<AppState>>,
     ReadSignals(signals): ReadSignals<Signals>,
 ) -> impl IntoResponse {
-    state
-        .lock()
-        .then(|mut s: MutexGuard<InnerState>| async move {
-            match signals.file {
-                Some(ref f) => {
-                    s.active_file = f.clone();
-                }
-                None => {
-                    debug!("file not found");
-                }
-            };
-            let _ = s.event_sender.send(SignalEvents::ActiveFileChanged);
-        })
-        .await;
-    Json(AddFileResponse { ok: true })
+    let ok = match signals.file {
+        Some(f) => switch_file(state, f).await,
+        None => {
+            debug!("file not found");
+            false
+        }
+    };
+    Json(AddFileResponse { ok })
+}
+
+/// Opens a Markdown document of the workspace, as listed for a tag.
+pub async fn open_file(
+    State(state): State<Arc<AppState>>,
+    ReadSignals(signals): ReadSignals<Signals>,
+) -> impl IntoResponse {
+    let file = match signals.file.map(|f| f.canonicalize()) {
+        Some(Ok(file)) => file,
+        _ => return Json(AddFileResponse { ok: false }),
+    };
+
+    let root = {
+        let s = state.lock().await;
+        s.files
+            .keys()
+            .filter_map(|tracked| workspace_root(tracked).canonicalize().ok())
+            .find(|root| file.starts_with(root))
+    };
+    // hidden files inside the workspace stay private, like static assets
+    let visible = root
+        .as_ref()
+        .and_then(|root| file.strip_prefix(root).ok())
+        .is_some_and(|relative| {
+            relative
+                .components()
+                .all(|c| !c.as_os_str().to_string_lossy().starts_with('.'))
+        });
+    if !visible || !is_markdown(&file) || !file.is_file() {
+        debug!("refusing to open {}", file.display());
+        return Json(AddFileResponse { ok: false });
+    }
+
+    add_file(state.clone(), file.clone()).await;
+    let ok = switch_file(state, file).await;
+    Json(AddFileResponse { ok })
 }
 
 pub async fn event_handler(
@@ -207,102 +352,42 @@ pub async fn event_handler(
             let local_state = state.clone();
 
             if signals.first {
-                // let mut s = local_state.lock().await;
                 let file = { local_state.lock().await.active_file.clone() };
 
-                let buffer = {
-                    local_state
-                        .lock()
-                        .await
-                        .files
-                        .get(&file)
-                        .map(|b| b.to_owned())
+                let html =
+                    render_with_diagrams(&local_state, |s| s.render_or_diagnose(&file)).await;
+                let patch = PatchSignals::new(r#"{"first": false}"#);
+                yielder
+                    .yield_item(Ok(patch.write_as_axum_sse_event()))
+                    .await;
+
+                let events = {
+                    let s = local_state.lock().await;
+                    let mut events = document_events(&s, &file, &html);
+                    events.push(slide_signal(s.slide));
+                    events
                 };
-                let rendered = { local_state.lock().await.render(&file) };
-
-                {
-                    let mut s = local_state.lock().await;
-                    if let Some((html, buf)) = rendered.as_ref().ok().zip(buffer) {
-                        s.reload_file(&file, buf.to_owned(), html.clone());
-                    };
+                for event in events {
+                    yielder.yield_item(Ok(event)).await;
                 }
-
-                // s.reload_file(file, buffer.unwrap(), rendered);
-
-                let html: String = match rendered {
-                    Ok(v) => v,
-                    Err(err) => {
-                        format!("Something weird happened:{}", err)
-                    }
-                };
-                let patch = PatchSignals::new(r#"{"first": false}"#);
-
-                let sse_event = patch.write_as_axum_sse_event();
-                yielder.yield_item(Ok(sse_event)).await;
-
-                let patch = PatchElements::new(html)
-                    .selector("article#markdown")
-                    .mode(ElementPatchMode::Inner);
-
-                let sse_event = patch.write_as_axum_sse_event();
-                yielder.yield_item(Ok(sse_event)).await;
-
-                let script = ExecuteScript::new(
-                    "Prism.highlightAllUnder(document.querySelector('article#markdown'));MathJax.typeset();",
-                );
-                let sse_event = script.write_as_axum_sse_event();
-                yielder.yield_item(Ok(sse_event)).await;
             }
             let len = { state.lock().await.watched_files.len() };
             if len > 1 {
-                let mut buttons = vec![];
-                for path in state.lock().await.watched_files.iter() {
-                    let filename = path
-                        .file_name()
-                        .unwrap_or_default()
-                        .to_os_string()
-                        .into_string()
-                        .unwrap_or_default();
-                    let string_path = path
-                        .clone()
-                        .into_os_string()
-                        .into_string()
-                        .unwrap_or_default();
-
-                    let button = format!(
-                        "<button id ='{string_path}' class='rounded-md px-5 py-2.5 leading-5 font-semibold' data-on:click=\"$file = '{string_path}';@get('/update')\" >{filename}</button><br />"
-                    );
-                    buttons.push(button);
-                }
+                let buttons = { state.lock().await.navbar() };
 
-                let patch = PatchElements::new(buttons.join("\n"))
+                let patch = PatchElements::new(buttons)
                     .selector("nav#navbar")
                     .mode(ElementPatchMode::Replace);
                 let sse_event = patch.write_as_axum_sse_event();
                 yielder.yield_item(Ok(sse_event)).await;
             }
 
-            let mut events = { state.lock().await.event_sender.subscribe() };
+            let mut events = { state.lock().await.subscribe() };
 
             while let Ok(signal_events) = events.recv().await {
                 match signal_events {
                     SignalEvents::AddedNewFile => {
-                        let mut buttons = vec![];
-                        for path in state.lock().await.watched_files.iter() {
-                            let filename = path
-                                .file_name()
-                                .unwrap_or_default()
-                                .to_os_string()
-                                .into_string()
-                                .unwrap();
-                            let string_path = path.clone().into_os_string().into_string().unwrap();
-
-                            let button = format!(
-                                "<button id ='{string_path}' class='rounded-md px-5 py-2.5 leading-5 font-semibold' data-on:click=\"$file = '{string_path}';@get('/update')\">{filename}</button><br />"
-                            );
-                            buttons.push(button);
-                        }
-                        let html = buttons.join("\n");
+                        let html = { state.lock().await.navbar() };
                         let patch = PatchElements::new(html)
                             .selector("nav#navbar")
                             .mode(ElementPatchMode::Inner);
@@ -313,74 +398,79 @@ pub async fn event_handler(
                     SignalEvents::UpdatedFile { updated_file, html } => {
                         // from inotify
                         // send html signals
-                        let active = { local_state.lock().await.active_file.clone() };
-                        if active == updated_file {
-                            let patch = PatchElements::new(html)
-                                .selector("article#markdown")
-                                .mode(ElementPatchMode::Inner);
-                            let sse_event = patch.write_as_axum_sse_event();
-                            yielder.yield_item(Ok(sse_event)).await;
-
-                            let script = ExecuteScript::new(
-                                "Prism.highlightAllUnder(document.querySelector('article#markdown'));MathJax.typeset();",
-                            );
-                            let sse_event = script.write_as_axum_sse_event();
-                            yielder.yield_item(Ok(sse_event)).await;
+                        let events = {
+                            let s = local_state.lock().await;
+                            if s.active_file == updated_file {
+                                document_events(&s, &updated_file, &html)
+                            } else {
+                                vec![]
+                            }
+                        };
+                        for event in events {
+                            yielder.yield_item(Ok(event)).await;
                         }
 
+                        // the title in the navbar may have changed
+                        let html = { local_state.lock().await.navbar() };
+                        let patch = PatchElements::new(html)
+                            .selector("nav#navbar")
+                            .mode(ElementPatchMode::Inner);
+                        yielder
+                            .yield_item(Ok(patch.write_as_axum_sse_event()))
+                            .await;
+
                         let patch = PatchSignals::new(r#"{"first": false}"#);
 
                         let sse_event = patch.write_as_axum_sse_event();
                         yielder.yield_item(Ok(sse_event)).await;
                     }
+                    SignalEvents::ExtraChanged { path } => {
+                        let config = {
+                            let s = local_state.lock().await;
+                            s.config_for(&s.active_file)
+                        };
+                        let is = |extra: &Option<PathBuf>| {
+                            extra.as_ref().is_some_and(|e| {
+                                std::path::absolute(e).ok().as_ref() == Some(&path)
+                            })
+                        };
+                        if is(&config.extra_css) {
+                            let patch = PatchElements::new(user_css(&path))
+                                .selector("style#user-css")
+                                .mode(ElementPatchMode::Replace);
+                            yielder
+                                .yield_item(Ok(patch.write_as_axum_sse_event()))
+                                .await;
+                        } else if is(&config.extra_js) {
+                            // scripts cannot be undone, only run again on a fresh page
+                            let script = ExecuteScript::new("location.reload();");
+                            yielder
+                                .yield_item(Ok(script.write_as_axum_sse_event()))
+                                .await;
+                        }
+                    }
+                    SignalEvents::SlideChanged { slide } => {
+                        yielder.yield_item(Ok(slide_signal(slide))).await;
+                    }
                     SignalEvents::ActiveFileChanged => {
                         // signal active file
                         //
                         let file = { local_state.lock().await.active_file.clone() };
 
-                        let buffer = {
-                            local_state
-                                .lock()
-                                .await
-                                .files
-                                .get(&file)
-                                .map(|b| b.to_owned())
-                        };
-                        let rendered = { local_state.lock().await.render(&file) };
-
-                        {
-                            let mut s = local_state.lock().await;
-                            if let Some((html, buf)) = rendered.as_ref().ok().zip(buffer) {
-                                s.reload_file(&file, buf.to_owned(), html.clone());
-                            };
-                        }
-
-                        // s.reload_file(file, buffer.unwrap(), rendered);
+                        let html =
+                            render_with_diagrams(&local_state, |s| s.render_or_diagnose(&file))
+                                .await;
 
-                        let html: String = match rendered {
-                            Ok(v) => v,
-                            Err(err) => {
-                                format!("Something weird happened:{}", err)
-                            }
+                        let events = {
+                            let s = local_state.lock().await;
+                            let mut events = document_events(&s, &file, &html);
+                            events.push(slide_signal(s.slide));
+                            events
                         };
+                        for event in events {
+                            yielder.yield_item(Ok(event)).await;
+                        }
 
-                        let patch = PatchElements::new(html)
-                            .selector("article#markdown")
-                            .mode(ElementPatchMode::Inner);
-                        let sse_event = patch.write_as_axum_sse_event();
-                        yielder.yield_item(Ok(sse_event)).await;
-
-                        let script = ExecuteScript::new(
-                            "Prism.highlightAllUnder(document.querySelector('article#markdown'));MathJax.typeset();",
-                        );
-                        let sse_event = script.write_as_axum_sse_event();
-                        yielder.yield_item(Ok(sse_event)).await;
-
-                        let script = ExecuteScript::new(
-                            "Prism.highlightAllUnder(document.querySelector('article#markdown'));MathJax.typeset();",
-                        );
-                        let sse_event = script.write_as_axum_sse_event();
-                        yielder.yield_item(Ok(sse_event)).await;
                         let patch = PatchSignals::new(r#"{"first": false}"#);
                         let sse_event = patch.write_as_axum_sse_event();
                         yielder.yield_item(Ok(sse_event)).await;
@@ -397,15 +487,72 @@ pub async fn event_handler(
     )
 }
 
+// The article showing `html`, rendered from `file`, and the parts of the
+// page that follow the document.
+fn document_events(s: &InnerState, file: &Path, html: &str) -> Vec<Event> {
+    let mut events = vec![
+        PatchElements::new(html)
+            .selector("article#markdown")
+            .mode(ElementPatchMode::Inner)
+            .write_as_axum_sse_event(),
+        ExecuteScript::new(
+            "Prism.highlightAllUnder(document.querySelector('article#markdown'));MathJax.typeset();",
+        )
+        .write_as_axum_sse_event(),
+        title_script(&s.label(file)),
+    ];
+    if let Some(stats) = s.stats(file) {
+        events.push(stats_patch(stats));
+    }
+    if s.config_for(file).template.is_some() {
+        events.push(toc_patch(html));
+    }
+    events
+}
+
+fn title_script(title: &str) -> Event {
+    // `<` is escaped so the title cannot close the script element
+    let title = serde_json::to_string(title)
+        .unwrap_or_default()
+        .replace('<', "\\u003c");
+    ExecuteScript::new(format!("document.title = {title};")).write_as_axum_sse_event()
+}
+
+pub fn stats_patch(stats: &Stats) -> Event {
+    PatchElements::new(stats.to_html())
+        .selector("footer#stats")
+        .mode(ElementPatchMode::Outer)
+        .write_as_axum_sse_event()
+}
+
+// Every window of the session shows the same slide.
+fn slide_signal(slide: usize) -> Event {
+    PatchSignals::new(serde_json::json!({ "slide": slide }).to_string()).write_as_axum_sse_event()
+}
+
+// Only custom templates have a table of contents to fill in.
+fn toc_patch(html: &str) -> Event {
+    PatchElements::new(toc_html(html))
+        .selector("nav#toc")
+        .mode(ElementPatchMode::Outer)
+        .write_as_axum_sse_event()
+}
+
 pub async fn root(State(state): State<Arc<AppState>>) -> Html<String> {
     let local_state = state.clone();
 
-    let file = { local_state.lock().await.active_file.clone() };
+    let file = { local_state.lock().await.active_file().to_path_buf() };
 
-    watch_file(file, state.clone()).await;
+    watch_file(file.clone(), state.clone()).await;
 
-    let html = TEMPLATE.to_string();
-    Html(html)
+    let (config, title) = {
+        let s = local_state.lock().await;
+        (s.config_for(&file), s.label(&file))
+    };
+    for extra in [&config.extra_css, &config.extra_js].into_iter().flatten() {
+        watch_extra(extra.clone(), state.clone()).await;
+    }
+    Html(render_page(&config, &title))
 }
 
 #[derive(Clone, Debug)]
@@ -414,6 +561,8 @@ pub enum SignalEvents {
     AddedNewFile,
     UpdatedFile { updated_file: PathBuf, html: String },
     ActiveFileChanged,
+    ExtraChanged { path: PathBuf },
+    SlideChanged { slide: usize },
 }
 
 pub struct InnerState {
@@ -422,6 +571,25 @@ pub struct InnerState {
     event_sender: Sender<SignalEvents>,
     // event_reciever: Receiver<SignalEvents>,
     watched_files: Vec<PathBuf>,
+    // tasks watching the tracked files, stopped when a file is removed
+    watchers: BTreeMap<PathBuf, AbortHandle>,
+    // extra_css and extra_js files of the previewed documents
+    watched_extras: Vec<PathBuf>,
+    diagrams: DiagramCache,
+    // diagrams of the last renders missing from the cache
+    pending_diagrams: Vec<Diagram>,
+    // statistics of the last good render of each document
+    stats: BTreeMap<PathBuf, Stats>,
+    // documents are shown one slide at a time
+    slides: bool,
+    slide: usize,
+    // number of slides of the last render of each document
+    slide_counts: BTreeMap<PathBuf, usize>,
+    // local files linked from each rendered document
+    assets: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+    config: Config,
+    // win over project files as well as the user configuration
+    overrides: Overrides,
 }
 
 impl InnerState {
@@ -436,9 +604,202 @@ impl InnerState {
             active_file: first_file,
             event_sender,
             watched_files: vec![],
+            watchers: BTreeMap::new(),
+            watched_extras: vec![],
+            diagrams: DiagramCache::new(),
+            pending_diagrams: Vec::new(),
+            stats: BTreeMap::new(),
+            slides: false,
+            slide: 0,
+            slide_counts: BTreeMap::new(),
+            assets: BTreeMap::new(),
+            config: Config::default(),
+            overrides: Overrides::default(),
         }
     }
 
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Shows the documents one slide at a time.
+    pub fn with_slides(mut self, slides: bool) -> Self {
+        self.slides = slides;
+        self
+    }
+
+    /// The configuration of `file` including its project file. An invalid
+    /// project file is reported by `render`, other users fall back to the
+    /// user configuration.
+    pub fn config_for(&self, file: &Path) -> Config {
+        self.project_config(file).unwrap_or_else(|err| {
+            warn!("{err}");
+            self.config.clone()
+        })
+    }
+
+    fn project_config(&self, file: &Path) -> Result<Config, GlyphoError> {
+        let config = self
+            .config
+            .for_dir(file.parent().unwrap_or(Path::new("/")))?;
+        Ok(config.with_overrides(&self.overrides))
+    }
+
+    /// The frontmatter title of `file`, or its file name.
+    pub fn label(&self, file: &Path) -> String {
+        let frontmatter = self
+            .config_for(file)
+            .markdown
+            .options(file)
+            .parse
+            .constructs
+            .frontmatter;
+
+        frontmatter
+            .then(|| fs::read_to_string(file).ok())
+            .flatten()
+            .and_then(|content| Frontmatter::parse(&content)?.title)
+            .unwrap_or_else(|| {
+                file.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+    }
+
+    /// The navbar buttons switching between the watched files.
+    pub fn navbar(&self) -> String {
+        let buttons: Vec<String> = self
+            .watched_files
+            .iter()
+            .map(|path| {
+                let string_path = path.to_string_lossy();
+                let id = escape_html(&string_path);
+                let arg = escape_html(&js_string(&string_path));
+                let label = escape_html(&self.label(path));
+
+                format!(
+                    "<button id ='{id}' class='rounded-md px-5 py-2.5 leading-5 font-semibold' data-on:click=\"$file = '{arg}';@post('/update')\">{label}</button><br />"
+                )
+            })
+            .collect();
+        buttons.join("\n")
+    }
+
+    pub fn active_file(&self) -> &Path {
+        &self.active_file
+    }
+
+    pub fn is_tracked(&self, file: &Path) -> bool {
+        self.files.contains_key(file)
+    }
+
+    /// The files of the session, by path.
+    pub fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.files.keys()
+    }
+
+    /// Follows the changes to the session, like every preview window does.
+    pub fn subscribe(&self) -> Receiver<SignalEvents> {
+        self.event_sender.subscribe()
+    }
+
+    pub fn stats(&self, file: &Path) -> Option<&Stats> {
+        self.stats.get(file)
+    }
+
+    pub fn slides(&self) -> bool {
+        self.slides
+    }
+
+    /// Switches between the document and its slides, starting at the first
+    /// slide.
+    pub fn set_slides(&mut self, slides: bool) {
+        self.slides = slides;
+        self.slide = 0;
+        let file = self.active_file.clone();
+        self.refresh(&file);
+        let _ = self
+            .event_sender
+            .send(SignalEvents::SlideChanged { slide: 0 });
+    }
+
+    /// Shows slide `slide` of the active document, or its first or last
+    /// slide when there is no such slide.
+    pub fn go_to_slide(&mut self, slide: i64) {
+        let count = self
+            .slide_counts
+            .get(&self.active_file)
+            .copied()
+            .unwrap_or(1);
+        self.slide = slide.clamp(0, count.saturating_sub(1) as i64) as usize;
+        let _ = self
+            .event_sender
+            .send(SignalEvents::SlideChanged { slide: self.slide });
+    }
+
+    /// Directories static files may be served from, with the files their
+    /// document references. The active document comes first.
+    pub fn asset_dirs(&self) -> Vec<(PathBuf, BTreeSet<PathBuf>)> {
+        let active = std::iter::once(&self.active_file);
+        let others = self.files.keys().filter(|f| **f != self.active_file);
+
+        active
+            .chain(others)
+            .filter_map(|file| {
+                let dir = file.parent()?.to_path_buf();
+                let referenced = self.assets.get(file).cloned().unwrap_or_default();
+                Some((dir, referenced))
+            })
+            .collect()
+    }
+
+    /// Directory of the document behind `/files/<doc-id>/` and the files it
+    /// references.
+    pub fn asset_dir(&self, doc: &str) -> Option<(PathBuf, BTreeSet<PathBuf>)> {
+        let file = self.files.keys().find(|f| doc_id(f) == doc)?;
+        let referenced = self.assets.get(file).cloned().unwrap_or_default();
+        Some((file.parent()?.to_path_buf(), referenced))
+    }
+
+    /// The diagrams the last renders are missing, to render while the state
+    /// is not held.
+    pub fn take_diagrams(&mut self) -> Vec<Diagram> {
+        std::mem::take(&mut self.pending_diagrams)
+    }
+
+    pub fn add_diagrams(&mut self, rendered: Rendered) {
+        cache_rendered(&mut self.diagrams, rendered);
+    }
+
+    /// Runs `render`, and once more after rendering the diagrams it is
+    /// missing. Blocks while they are rendered, see `render_with_diagrams`
+    /// for the server.
+    pub fn with_diagrams<T>(&mut self, mut render: impl FnMut(&mut Self) -> T) -> T {
+        let result = render(self);
+        let diagrams = self.take_diagrams();
+        if diagrams.is_empty() {
+            return result;
+        }
+        self.add_diagrams(render_all_blocking(diagrams));
+        render(self)
+    }
+
+    /// Renders `file` and sends it to the previews showing it.
+    pub fn refresh(&mut self, file: &PathBuf) {
+        let html = self.render_or_diagnose(file);
+        let _ = self.event_sender.send(SignalEvents::UpdatedFile {
+            updated_file: file.clone(),
+            html,
+        });
+    }
+
     fn reload_file(&mut self, file: &Path, mut buffer: BytesMut, html: String) -> &mut Self {
         buffer.clear();
         buffer = html.as_bytes().into();
@@ -447,7 +808,57 @@ impl InnerState {
         self
     }
 
-    fn render(&mut self, file: &PathBuf) -> eyre::Result<String> {
+    /// Renders `file` and keeps the result as its last good render. When
+    /// rendering fails, the diagnostic is shown above the last good render
+    /// instead.
+    pub fn render_or_diagnose(&mut self, file: &PathBuf) -> String {
+        self.try_render(file)
+            .unwrap_or_else(|diagnostic| self.diagnosed(file, &diagnostic))
+    }
+
+    /// Renders `file` and keeps the result as its last good render, or tells
+    /// why it could not be rendered.
+    pub fn try_render(&mut self, file: &PathBuf) -> Result<String, Box<Diagnostic>> {
+        match self.render(file) {
+            Ok(html) => {
+                let buffer = self.files.get(file).cloned().unwrap_or_default();
+                self.reload_file(file, buffer, html.clone());
+                Ok(html)
+            }
+            Err(err) => Err(Box::new(Diagnostic::new(file, &err))),
+        }
+    }
+
+    /// Renders unsaved `content` of `file` from the editor, without keeping
+    /// it as the last good render.
+    pub fn preview(&mut self, file: &Path, content: &str) -> String {
+        match self.render_source(file, content.to_string(), Output::Preview) {
+            Ok(html) => html,
+            Err(err) => self.diagnosed(file, &Diagnostic::new(file, &err)),
+        }
+    }
+
+    /// `diagnostic` above the last good render of `file`.
+    fn diagnosed(&self, file: &Path, diagnostic: &Diagnostic) -> String {
+        diagnostic.to_html() + &self.last_good(file)
+    }
+
+    fn last_good(&self, file: &Path) -> String {
+        self.files
+            .get(file)
+            .map(|buffer| String::from_utf8_lossy(buffer).into_owned())
+            .unwrap_or_default()
+    }
+
+    pub fn render(&mut self, file: &PathBuf) -> eyre::Result<String> {
+        self.render_as(file, Output::Preview)
+    }
+
+    /// Renders `file` for `output`.
+    // `bail!` ends the arms of the read error below
+    #[allow(semicolon_in_expressions_from_non_local_macros)]
+    pub fn render_as(&mut self, file: &PathBuf, output: Output) -> eyre::Result<String> {
+        let key = file.clone();
         let (file, _buffer) = self.files.get_key_value(file).unzip();
         let content = match fs::read_to_string(file.expect("file not being tracked")) {
             Ok(c) => c,
@@ -512,50 +923,142 @@ impl InnerState {
                 _ => bail!("An unknown error occurred: {:?}", err),
             },
         };
+        self.render_source(&key, content, output)
+    }
 
-        let options = Options {
-            parse: ParseOptions {
-                constructs: Constructs {
-                    code_indented: true,
-                    gfm_table: true,
-                    gfm_task_list_item: true,
-                    attention: true,
-                    frontmatter: true,
-                    gfm_footnote_definition: true,
-                    autolink: true,
-                    gfm_autolink_literal: true,
-                    ..Constructs::gfm()
-                },
-                gfm_strikethrough_single_tilde: true,
-                ..ParseOptions::default()
-            },
-            compile: CompileOptions {
-                allow_dangerous_html: true,
-
-                ..CompileOptions::gfm()
-            },
+    /// Renders `content` as the document `key`, with its configuration and
+    /// relative links.
+    pub fn render_source(
+        &mut self,
+        key: &Path,
+        content: String,
+        output: Output,
+    ) -> eyre::Result<String> {
+        let preview = output == Output::Preview;
+        let key = key.to_path_buf();
+        let source_version = version(&content);
+        let config = self.project_config(&key)?;
+        let options = config.markdown.options(&key);
+        let frontmatter = Frontmatter::parse(&content);
+        let (parsed, source_map) = config.wikilinks.rewrite(&content);
+
+        // errors are shown in the document, not in its rewritten wikilinks
+        let error = |message| GlyphoError::markdown(source_map.message(message), &content);
+        let body = markdown::to_html_with_options(&parsed, &options).map_err(error)?;
+        let mut tree = markdown::to_mdast(&parsed, &options.parse).map_err(error)?;
+        // blocks, tasks and findings are placed in the document
+        source_map.tree(&mut tree);
+        self.stats.insert(key.clone(), Stats::new(&tree));
+
+        // slides and lint markers find their blocks by tags that survive
+        // sanitizing
+        let body = if preview && (self.slides || config.lint.enabled) {
+            tag_blocks(&body, &tree, &options.compile)
+        } else {
+            body
         };
-
-        let with_wikilinks = wikilinks_to_markdown(&content);
-
-        let body =
-            markdown::to_html_with_options(&with_wikilinks, &options).map_err(|message| {
-                GlyphoError::MarkdownError {
-                    place: message.place,
-                    reason: message.reason,
-                    rule_id: *message.rule_id,
-                    m_source: *message.source,
-                }
-            })?;
+        let body = if config.is_trusted(&key) {
+            body
+        } else {
+            sanitize_html(&body)
+        };
+        // slides and markers are added after sanitizing, which would drop
+        // their classes
+        let body = if !preview {
+            body
+        } else if self.slides {
+            let (body, count) = slides(&body, &tree);
+            self.slide_counts.insert(key.clone(), count);
+            body
+        } else if config.lint.enabled {
+            gutter(&body, &tree, &check(&tree, &content, &config.lint))
+        } else {
+            body
+        };
+        // safe mode only reviews documents, it does not change them
+        let body = if config.safe || !preview {
+            body
+        } else {
+            task_checkboxes(&body, &tree, &source_version)
+        };
+        // headings are linked from the table of contents, which only the
+        // print page and custom templates have
+        let body = if output == Output::Print || (preview && config.template.is_some()) {
+            heading_ids(&body)
+        } else {
+            body
+        };
+        // callouts are built after sanitizing, which would drop data-callout
+        let body = if config.markdown.callouts(&key) {
+            callouts(&body)
+        } else {
+            body
+        };
+        // only trusted documents are handed to other programs, the others
+        // tell why their diagrams stay code
+        let body = if !config.diagrams.enabled {
+            body
+        } else if config.is_trusted(&key) {
+            render_diagrams(
+                &body,
+                &config.diagrams,
+                &self.diagrams,
+                &mut self.pending_diagrams,
+            )
+        } else {
+            untrusted_diagrams(&body)
+        };
+        // the card is built from escaped text after sanitizing, so that its
+        // tag buttons keep their datastar attributes. Books take their
+        // metadata from the frontmatter instead.
+        let card = match output {
+            Output::Preview => !self.slides,
+            Output::Print => true,
+            Output::Epub | Output::Fragment => false,
+        };
+        let body = match frontmatter {
+            Some(frontmatter) if options.parse.constructs.frontmatter && card => {
+                frontmatter.card() + &body
+            }
+            _ => body,
+        };
+        let body = if output == Output::Print {
+            expand_links(&body)
+        } else {
+            body
+        };
+        let body = math_for_mathjax(&body);
+        let assets = referenced_assets(&body);
+        let body = if output == Output::Fragment {
+            body
+        } else {
+            rewrite_relative_urls(&body, &doc_id(&key))
+        };
+        self.assets.insert(key, assets);
         Ok(body)
     }
 }
 
-type AppState = Mutex<InnerState>;
+/// What a document is rendered for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    /// The live preview, with lint markers, clickable tasks and slides
+    Preview,
+    /// A page to print or export, the document alone
+    Print,
+    /// A chapter of an EPUB book
+    Epub,
+    /// HTML for other programs, the document alone with the links it was
+    /// written with
+    Fragment,
+}
+
+pub type AppState = Mutex<InnerState>;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::DiagramsConfig;
     use rstest::rstest;
     use tempfile::TempDir;
 
@@ -704,6 +1207,30 @@ fn main() {
         assert!(html.contains("checkbox") || html.contains("type=\"checkbox\""));
     }
 
+    #[test]
+    fn test_render_task_checkboxes_clickable() {
+        let (_temp_dir, file_path) = create_temp_markdown_file("- [ ] todo\n");
+        let mut state = InnerState::new(file_path.clone());
+
+        let html = state.render(&file_path).unwrap();
+        let version = version("- [ ] todo\n");
+        assert!(html.contains(&format!("$taskVersion = '{version}'; @post('/task')")));
+    }
+
+    #[test]
+    fn test_render_task_checkboxes_disabled_in_safe_mode() {
+        let (_temp_dir, file_path) = create_temp_markdown_file("- [ ] todo\n");
+        let config = Config {
+            safe: true,
+            ..Config::default()
+        };
+
+        let mut state = InnerState::new(file_path.clone()).with_config(config);
+        let html = state.render(&file_path).unwrap();
+        assert!(html.contains("disabled"));
+        assert!(!html.contains("/task"));
+    }
+
     #[test]
     fn test_inner_state_render_file_not_found() {
         let file_path = PathBuf::from("/nonexistent/path/file.md");
@@ -754,6 +1281,346 @@ $$
         assert!(result.is_ok());
     }
 
+    // ==================== Configuration Tests ====================
+
+    #[test]
+    fn test_render_wikilinks_disabled() {
+        let (_temp_dir, file_path) = create_temp_markdown_file("See [[Other Page]]");
+        let mut config = Config::default();
+        config.wikilinks.enabled = false;
+
+        let mut state = InnerState::new(file_path.clone()).with_config(config);
+        let html = state.render(&file_path).unwrap();
+
+        assert!(html.contains("[[Other Page]]"));
+        assert!(!html.contains("<a"));
+    }
+
+    #[test]
+    fn test_render_wikilinks_extension() {
+        let (_temp_dir, file_path) = create_temp_markdown_file("See [[other]]");
+        let mut config = Config::default();
+        config.wikilinks.extension = Some("md".to_string());
+
+        let mut state = InnerState::new(file_path.clone()).with_config(config);
+        let html = state.render(&file_path).unwrap();
+
+        assert!(html.contains("other.md"));
+    }
+
+    #[test]
+    fn test_render_markdown_constructs_from_config() {
+        let (_temp_dir, file_path) = create_temp_markdown_file("~~gone~~");
+        let mut config = Config::default();
+        config.markdown.strikethrough = Some(false);
+
+        let mut state = InnerState::new(file_path.clone()).with_config(config);
+        let html = state.render(&file_path).unwrap();
+
+        assert!(!html.contains("<del>"));
+    }
+
+    #[test]
+    fn test_render_uses_project_config_per_document() {
+        let temp_dir = TempDir::new().unwrap();
+        let (plain, wiki) = (temp_dir.path().join("plain"), temp_dir.path().join("wiki"));
+        std::fs::create_dir_all(&plain).unwrap();
+        std::fs::create_dir_all(&wiki).unwrap();
+        std::fs::write(plain.join(".glypho.toml"), "[wikilinks]\nenabled = false\n").unwrap();
+        std::fs::write(
+            wiki.join(".glypho.toml"),
+            "[wikilinks]\nextension = \"md\"\n",
+        )
+        .unwrap();
+        std::fs::write(plain.join("a.md"), "[[page]]").unwrap();
+        std::fs::write(wiki.join("b.md"), "[[page]]").unwrap();
+
+        let mut state = InnerState::new(plain.join("a.md"));
+        state.files.insert(wiki.join("b.md"), BytesMut::new());
+
+        let html = state.render(&plain.join("a.md")).unwrap();
+        assert!(html.contains("[[page]]"));
+        let html = state.render(&wiki.join("b.md")).unwrap();
+        assert!(html.contains("page.md"));
+    }
+
+    #[test]
+    fn test_render_invalid_project_config() {
+        let (temp_dir, file_path) = create_temp_markdown_file("# Title");
+        std::fs::write(temp_dir.path().join(".glypho.toml"), "tables = 1").unwrap();
+
+        let mut state = InnerState::new(file_path.clone());
+        let err = state.render(&file_path).unwrap_err();
+        assert!(err.to_string().contains(".glypho.toml"));
+    }
+
+    #[test]
+    fn test_flags_win_over_project_config() {
+        let (temp_dir, file_path) = create_temp_markdown_file("# Notes");
+        std::fs::write(
+            temp_dir.path().join(".glypho.toml"),
+            "theme = \"github\"\ntemplate = \"site\"",
+        )
+        .unwrap();
+        let overrides = Overrides {
+            theme: Some("sepia".to_string()),
+            ..Overrides::default()
+        };
+        let state = InnerState::new(file_path.clone())
+            .with_config(Config {
+                trusted_roots: vec![temp_dir.path().to_path_buf()],
+                ..Config::default()
+            })
+            .with_overrides(overrides);
+
+        let config = state.config_for(&file_path);
+        assert_eq!(config.theme.as_deref(), Some("sepia"));
+        assert_eq!(config.template, Some(temp_dir.path().join("site")));
+    }
+
+    #[test]
+    fn test_render_callouts_in_untrusted_document() {
+        let (_temp_dir, file_path) = create_temp_markdown_file("> [!TIP]\n> Use callouts.");
+        let mut state = InnerState::new(file_path.clone());
+
+        let html = state.render(&file_path).unwrap();
+        assert!(html.contains("<div class=\"callout callout-tip\" data-callout=\"tip\">"));
+        assert!(!html.contains("[!TIP]"));
+    }
+
+    #[rstest]
+    #[case(true, false, true)]
+    #[case(true, true, false)]
+    #[case(false, false, false)]
+    fn test_render_diagrams_of_trusted_documents(
+        #[case] trusted: bool,
+        #[case] safe: bool,
+        #[case] rendered: bool,
+    ) {
+        let (temp_dir, file_path) = create_temp_markdown_file("```dot\ndigraph { a -> b }\n```");
+        let config = Config {
+            safe,
+            trusted_roots: if trusted {
+                vec![temp_dir.path().to_path_buf()]
+            } else {
+                vec![]
+            },
+            diagrams: DiagramsConfig {
+                dot: "/nonexistent/dot".to_string(),
+                ..DiagramsConfig::default()
+            },
+            ..Config::default()
+        };
+        let mut state = InnerState::new(file_path.clone()).with_config(config);
+
+        let html = state.with_diagrams(|s| s.render(&file_path)).unwrap();
+        assert!(html.contains("<code class=\"language-dot\">"));
+        assert_eq!(html.contains("/nonexistent/dot was not found"), rendered);
+        assert_eq!(html.contains("<div class=\"diagram-note\">"), !rendered);
+    }
+
+    #[tokio::test]
+    async fn test_diagrams_rendered_without_holding_state() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (temp_dir, file_path) = create_temp_markdown_file("```dot\ndigraph { a -> b }\n```");
+        // dot is called with -Tsvg -o <output> <input>
+        let dot = temp_dir.path().join("dot");
+        fs::write(&dot, "#!/bin/sh\nsleep 1\nprintf '<svg/>' > \"$3\"\n").unwrap();
+        fs::set_permissions(&dot, fs::Permissions::from_mode(0o755)).unwrap();
+        let config = Config {
+            trusted_roots: vec![temp_dir.path().to_path_buf()],
+            diagrams: DiagramsConfig {
+                dot: dot.to_string_lossy().into_owned(),
+                ..DiagramsConfig::default()
+            },
+            ..Config::default()
+        };
+        let state = Arc::new(Mutex::new(
+            InnerState::new(file_path.clone()).with_config(config),
+        ));
+
+        let render = tokio::spawn({
+            let state = state.clone();
+            async move { render_with_diagrams(&state, |s| s.render(&file_path)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(state.try_lock().is_ok());
+
+        let html = render.await.unwrap().unwrap();
+        assert!(html.contains("<figure class=\"diagram diagram-dot\">"));
+    }
+
+    #[test]
+    fn test_render_or_diagnose_keeps_last_good_render() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("page.mdx");
+        std::fs::write(&file_path, "# Working").unwrap();
+        let mut state = InnerState::new(file_path.clone());
+
+        let good = state.render_or_diagnose(&file_path);
+        std::fs::write(&file_path, "# Working\n\n<Chart size=1 />").unwrap();
+        let broken = state.render_or_diagnose(&file_path);
+
+        assert!(broken.starts_with("<div class=\"diagnostic\" role=\"alert\">"));
+        assert!(broken.contains("page.mdx:3:13"));
+        assert!(broken.ends_with(&good));
+    }
+
+    #[test]
+    fn test_try_render_snippet_of_parsed_content() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("page.mdx");
+        std::fs::write(&file_path, "# Working\n\n<Chart size=1 />").unwrap();
+        let mut state = InnerState::new(file_path.clone());
+
+        let diagnostic = state.try_render(&file_path).unwrap_err();
+        std::fs::write(&file_path, "# Fixed").unwrap();
+
+        assert_eq!(diagnostic.line, Some(3));
+        assert_eq!(
+            diagnostic.snippet.last(),
+            Some(&(3, "<Chart size=1 />".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_try_render_diagnostic_after_wikilink() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("page.mdx");
+        let line = "See [[Some Page|label]] then <Chart size=1 />";
+        std::fs::write(&file_path, format!("# Title\n\n{line}")).unwrap();
+        let mut state = InnerState::new(file_path.clone());
+
+        let diagnostic = state.try_render(&file_path).unwrap_err();
+        assert!(diagnostic.location().ends_with("page.mdx:3:42"));
+        assert_eq!(diagnostic.snippet.last(), Some(&(3, line.to_string())));
+    }
+
+    #[test]
+    fn test_render_or_diagnose_missing_file() {
+        let (temp_dir, file_path) = create_temp_markdown_file("# Test");
+        let mut state = InnerState::new(file_path.clone());
+        drop(temp_dir);
+
+        let html = state.render_or_diagnose(&file_path);
+        assert!(html.contains("The file or directory does not exist"));
+        assert!(!html.contains("Something weird happened"));
+    }
+
+    #[test]
+    fn test_render_heading_ids() {
+        let (temp_dir, file_path) = create_temp_markdown_file("# Notes\n\n## Notes");
+        let mut state = InnerState::new(file_path.clone());
+
+        let html = state.render_as(&file_path, Output::Print).unwrap();
+        assert!(html.contains("<h1 id=\"notes\">Notes</h1>"));
+        assert!(html.contains("<h2 id=\"notes-1\">Notes</h2>"));
+
+        // the preview only has a table of contents with a custom template
+        let html = state.render(&file_path).unwrap();
+        assert!(html.contains("<h1>Notes</h1>"));
+        let mut state = InnerState::new(file_path.clone()).with_config(Config {
+            template: Some(temp_dir.path().to_path_buf()),
+            ..Config::default()
+        });
+        let html = state.render(&file_path).unwrap();
+        assert!(html.contains("<h1 id=\"notes\">Notes</h1>"));
+    }
+
+    #[test]
+    fn test_render_lint_markers() {
+        let (_temp_dir, file_path) = create_temp_markdown_file("# Title\n\n### Skipped\n");
+        let mut state = InnerState::new(file_path.clone());
+
+        let html = state.render(&file_path).unwrap();
+        assert!(html.contains(
+            "<div class=\"lint-gutter\"><span class=\"lint-marker\" title=\"3:1: heading-increment: Heading level jumps from h1 to h3\">1</span></div><h3"
+        ));
+    }
+
+    #[test]
+    fn test_render_slides() {
+        let (_temp_dir, file_path) = create_temp_markdown_file(
+            "---\ntitle: Talk\n---\n\n# Talk\n\n## One\n\n- [ ] task\n\n## Two\n",
+        );
+        let mut state = InnerState::new(file_path.clone()).with_slides(true);
+
+        let html = state.render(&file_path).unwrap();
+        assert!(html.starts_with("<div class=\"slides\">"));
+        assert!(html.contains("<h2>One</h2>"));
+        assert!(html.contains("class=\"task\""));
+        assert!(!html.contains("frontmatter"));
+        assert!(!html.contains("lint-gutter"));
+        assert_eq!(state.slide_counts.get(&file_path), Some(&3));
+    }
+
+    #[rstest]
+    #[case(1, 1)]
+    #[case(-1, 0)]
+    #[case(7, 2)]
+    fn test_go_to_slide_clamps(#[case] to: i64, #[case] expected: usize) {
+        let (_temp_dir, file_path) = create_temp_markdown_file("# A\n\n## B\n\n## C\n");
+        let mut state = InnerState::new(file_path.clone());
+        state.set_slides(true);
+        let mut events = state.event_sender.subscribe();
+
+        state.go_to_slide(to);
+        assert_eq!(state.slide, expected);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(SignalEvents::SlideChanged { slide }) if slide == expected
+        ));
+    }
+
+    #[test]
+    fn test_preview_unsaved_source() {
+        let (_temp_dir, file_path) = create_temp_markdown_file("# Saved");
+        let mut state = InnerState::new(file_path.clone());
+        state.render_or_diagnose(&file_path);
+
+        let html = state.preview(&file_path, "# Unsaved");
+        assert!(html.contains(">Unsaved</h1>"));
+        assert!(state.last_good(&file_path).contains(">Saved</h1>"));
+    }
+
+    #[test]
+    fn test_preview_error_shows_unsaved_source() {
+        let (_temp_dir, file_path) = create_temp_markdown_file("# Saved");
+        let mdx = file_path.with_extension("mdx");
+        std::fs::write(&mdx, "# Saved").unwrap();
+        let mut state = InnerState::new(mdx.clone());
+        state.render_or_diagnose(&mdx);
+
+        let html = state.preview(&mdx, "# Saved\n\n<Chart size=1 />");
+        assert!(html.starts_with("<div class=\"diagnostic\""));
+        assert!(html.contains("&lt;Chart size=1 /&gt;"));
+        assert!(html.contains(">Saved</h1>"));
+    }
+
+    #[test]
+    fn test_render_keeps_stats() {
+        let (_temp_dir, file_path) = create_temp_markdown_file("# Title\n\nThree more words");
+        let mut state = InnerState::new(file_path.clone());
+        assert!(state.stats(&file_path).is_none());
+
+        state.render(&file_path).unwrap();
+        let stats = state.stats(&file_path).unwrap();
+        assert_eq!(stats.words, 4);
+        assert_eq!(stats.headings, 1);
+    }
+
+    #[test]
+    fn test_render_lint_disabled() {
+        let (_temp_dir, file_path) = create_temp_markdown_file("# Title\n\n### Skipped\n");
+        let mut config = Config::default();
+        config.lint.enabled = false;
+
+        let mut state = InnerState::new(file_path.clone()).with_config(config);
+        let html = state.render(&file_path).unwrap();
+        assert!(!html.contains("lint-gutter"));
+    }
+
     // ==================== Signals Struct Tests ====================
 
     #[test]
@@ -923,6 +1790,42 @@ $$
         }
     }
 
+    #[tokio::test]
+    async fn test_watch_extra_once() {
+        let (temp_dir, file_path) = create_temp_markdown_file("# Test");
+        let css = temp_dir.path().join("house.css");
+        std::fs::write(&css, "body {}").unwrap();
+        let state = create_test_state(file_path);
+
+        watch_extra(css.clone(), state.clone()).await;
+        watch_extra(css.clone(), state.clone()).await;
+
+        let guard = state.lock().await;
+        assert_eq!(guard.watched_extras, vec![css]);
+        // extras are not documents of the navbar
+        assert!(guard.watched_files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_removed_file_not_watched() {
+        let (temp_dir, file_path) = create_temp_markdown_file("# Test");
+        let other = temp_dir.path().join("other.md");
+        std::fs::write(&other, "# Other").unwrap();
+        let state = create_test_state(file_path);
+
+        add_file(state.clone(), other.clone()).await;
+        let watcher = state.lock().await.watchers[&other].clone();
+        assert!(remove_file(state.clone(), other.clone()).await);
+        tokio::task::yield_now().await;
+        assert!(watcher.is_finished());
+
+        // added again, the file has a single watcher
+        add_file(state.clone(), other.clone()).await;
+        let guard = state.lock().await;
+        assert_eq!(guard.watched_files, vec![other.clone()]);
+        assert!(!guard.watchers[&other].is_finished());
+    }
+
     // ==================== Markdown Rendering Options Tests ====================
 
     #[test]
@@ -982,6 +1885,133 @@ Custom HTML content
         assert!(html.contains("<div") || html.contains("custom"));
     }
 
+    #[test]
+    fn test_render_sanitizes_untrusted_file() {
+        let content = "<script>alert(1)</script>\n\n<img src=\"a.png\" onerror=\"alert(2)\">\n";
+        let (_temp_dir, file_path) = create_temp_markdown_file(content);
+
+        let mut state = InnerState::new(file_path.clone());
+        let html = state.render(&file_path).unwrap();
+
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("onerror"));
+        assert!(html.contains("<img"));
+    }
+
+    #[test]
+    fn test_render_keeps_html_in_trusted_root() {
+        let content = "<button onclick=\"run()\">Run</button>\n";
+        let (temp_dir, file_path) = create_temp_markdown_file(content);
+        let config = Config {
+            trusted_roots: vec![temp_dir.path().to_path_buf()],
+            ..Config::default()
+        };
+
+        let mut state = InnerState::new(file_path.clone()).with_config(config.clone());
+        assert!(state.render(&file_path).unwrap().contains("onclick"));
+
+        let config = Config {
+            safe: true,
+            ..config
+        };
+        let mut state = InnerState::new(file_path.clone()).with_config(config);
+        assert!(!state.render(&file_path).unwrap().contains("onclick"));
+    }
+
+    // ==================== Frontmatter Tests ====================
+
+    #[test]
+    fn test_render_frontmatter_card() {
+        let content = "---\ntitle: Notes\nauthor: Ada\ntags: [rust]\n---\n\n# Body\n";
+        let (_temp_dir, file_path) = create_temp_markdown_file(content);
+
+        let mut state = InnerState::new(file_path.clone());
+        let html = state.render(&file_path).unwrap();
+
+        assert!(html.starts_with("<details class=\"frontmatter\" open>"));
+        assert!(html.contains("<dd>Ada</dd>"));
+        assert!(html.contains("@get('/tags/rust')"));
+        assert!(html.contains("<h1>Body</h1>"));
+    }
+
+    #[test]
+    fn test_render_without_frontmatter_construct() {
+        let content = "---\nauthor: Ada\n---\n\n# Body\n";
+        let (_temp_dir, file_path) = create_temp_markdown_file(content);
+        let mut config = Config::default();
+        config.markdown.frontmatter = Some(false);
+
+        let mut state = InnerState::new(file_path.clone()).with_config(config);
+        let html = state.render(&file_path).unwrap();
+
+        assert!(!html.contains("frontmatter"));
+    }
+
+    #[test]
+    fn test_label_from_title() {
+        let temp_dir = TempDir::new().unwrap();
+        let titled = temp_dir.path().join("titled.md");
+        let plain = temp_dir.path().join("plain.md");
+        std::fs::write(&titled, "---\ntitle: My <Notes>\n---\n").unwrap();
+        std::fs::write(&plain, "# Plain").unwrap();
+
+        let mut state = InnerState::new(titled.clone());
+        state.watched_files = vec![titled.clone(), plain.clone()];
+
+        assert_eq!(state.label(&titled), "My <Notes>");
+        assert_eq!(state.label(&plain), "plain.md");
+        let navbar = state.navbar();
+        assert!(navbar.contains(">My &lt;Notes&gt;</button>"));
+        assert!(navbar.contains(">plain.md</button>"));
+    }
+
+    #[tokio::test]
+    async fn test_open_file_in_workspace_only() {
+        use axum::{Router, body::Body, http::Request, routing::post};
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let workspace = temp_dir.path().join("repo");
+        std::fs::create_dir_all(workspace.join(".git")).unwrap();
+        std::fs::create_dir_all(workspace.join("docs")).unwrap();
+        std::fs::write(workspace.join("README.md"), "# Readme").unwrap();
+        std::fs::write(workspace.join("docs/tagged.md"), "# Tagged").unwrap();
+        std::fs::write(temp_dir.path().join("outside.md"), "# Outside").unwrap();
+
+        let state = create_test_state(workspace.join("README.md"));
+        let app = Router::new()
+            .route("/open", post(open_file))
+            .with_state(state.clone());
+        let open = |file: PathBuf| {
+            let signals = serde_json::json!({ "file": file, "first": false }).to_string();
+            Request::post("/open")
+                .header("content-type", "application/json")
+                .body(Body::from(signals))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(open(temp_dir.path().join("outside.md")))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], br#"{"ok":false}"#);
+
+        let response = app
+            .oneshot(open(workspace.join("docs/tagged.md")))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], br#"{"ok":true}"#);
+        let tagged = workspace.join("docs/tagged.md").canonicalize().unwrap();
+        assert_eq!(state.lock().await.active_file(), tagged);
+    }
+
     // ==================== File Operations Tests ====================
 
     #[tokio::test]
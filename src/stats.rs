@@ -0,0 +1,271 @@
+use axum::{Json, extract::State, response::IntoResponse};
+use markdown::mdast::Node;
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    config::{Config, Overrides},
+    diagnostic::Diagnostic,
+    error::GlyphoError,
+    state::AppState,
+};
+
+// Average silent reading speed of prose.
+const WORDS_PER_MINUTE: usize = 200;
+
+/// Counts of the prose and the elements of a document, code blocks and
+/// frontmatter do not count as words.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub words: usize,
+    /// Characters of the words, without whitespace
+    pub characters: usize,
+    pub reading_minutes: usize,
+    pub headings: usize,
+    pub links: usize,
+    pub images: usize,
+    pub code_blocks: usize,
+    pub outline: Vec<Heading>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Heading {
+    pub depth: u8,
+    pub text: String,
+    pub line: usize,
+}
+
+/// The statistics of a file, as served by `/stats` and printed by
+/// `glypho stats`.
+#[derive(Debug, Serialize)]
+pub struct DocumentStats {
+    pub file: PathBuf,
+    #[serde(flatten)]
+    pub stats: Stats,
+}
+
+impl Stats {
+    pub fn new(tree: &Node) -> Self {
+        let mut stats = Stats::default();
+        let mut prose = String::new();
+        stats.count(tree, &mut prose);
+        for word in prose.split_whitespace() {
+            stats.words += 1;
+            stats.characters += word.chars().count();
+        }
+        stats.reading_minutes = stats.words.div_ceil(WORDS_PER_MINUTE);
+        stats
+    }
+
+    // Text is collected per block, so that `code`, and *emphasis* followed
+    // by punctuation stay one word.
+    fn count(&mut self, node: &Node, prose: &mut String) {
+        match node {
+            Node::Text(text) => prose.push_str(&text.value),
+            Node::InlineCode(code) => prose.push_str(&code.value),
+            Node::Break(_) => prose.push(' '),
+            Node::Heading(heading) => {
+                self.headings += 1;
+                self.outline.push(Heading {
+                    depth: heading.depth,
+                    text: node.to_string().trim().to_string(),
+                    line: node.position().map_or(0, |p| p.start.line),
+                });
+            }
+            Node::Link(_) | Node::LinkReference(_) => self.links += 1,
+            Node::Image(_) | Node::ImageReference(_) => self.images += 1,
+            Node::Code(_) => self.code_blocks += 1,
+            _ => {}
+        }
+        for child in node.children().into_iter().flatten() {
+            self.count(child, prose);
+        }
+        if matches!(
+            node,
+            Node::Paragraph(_) | Node::Heading(_) | Node::TableCell(_)
+        ) {
+            prose.push(' ');
+        }
+    }
+
+    /// The status bar of the preview.
+    pub fn to_html(&self) -> String {
+        let items = [
+            plural(self.words, "word"),
+            format!("{} min read", self.reading_minutes),
+            plural(self.headings, "heading"),
+            plural(self.links, "link"),
+            plural(self.images, "image"),
+            plural(self.code_blocks, "code block"),
+        ];
+        format!(
+            "<footer id=\"stats\" title=\"{} characters\">{}</footer>",
+            self.characters,
+            items.map(|item| format!("<span>{item}</span>")).join(" · ")
+        )
+    }
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {noun}"),
+        _ => format!("{count} {noun}s"),
+    }
+}
+
+/// The statistics of the active document as JSON.
+pub async fn document_stats(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut s = state.lock().await;
+    let file = s.active_file().to_path_buf();
+    if s.stats(&file).is_none() {
+        s.render_or_diagnose(&file);
+    }
+    let stats = s.stats(&file).cloned().unwrap_or_default();
+    Json(DocumentStats { file, stats })
+}
+
+/// `glypho stats`: prints the statistics of `file` as JSON and returns the
+/// exit code, 2 when it could not be read.
+pub fn print_stats(file: &Path, user_config: &Config, overrides: &Overrides) -> i32 {
+    match file_stats(file, user_config, overrides) {
+        Ok(stats) => {
+            let stats = DocumentStats {
+                file: file.to_path_buf(),
+                stats,
+            };
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&stats).unwrap_or_default()
+            );
+            0
+        }
+        Err(err) => {
+            Diagnostic::new(file, &err).print();
+            2
+        }
+    }
+}
+
+fn file_stats(file: &Path, user_config: &Config, overrides: &Overrides) -> eyre::Result<Stats> {
+    let content = fs::read_to_string(file)?;
+    let path = std::path::absolute(file)?;
+    let config = user_config.for_file(&path, overrides)?;
+
+    let (parsed, source_map) = config.wikilinks.rewrite(&content);
+    let options = config.markdown.options(&path);
+    let tree = markdown::to_mdast(&parsed, &options.parse)
+        .map_err(|message| GlyphoError::markdown(source_map.message(message), &content))?;
+    Ok(Stats::new(&tree))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dialect::Dialect, testing::options};
+    use insta::assert_snapshot;
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    fn stats(content: &str) -> Stats {
+        Stats::new(&markdown::to_mdast(content, &options(Dialect::Gfm).parse).unwrap())
+    }
+
+    // ==================== Count Tests ====================
+
+    #[test]
+    fn test_counts() {
+        let stats = stats(
+            "---\ntitle: Not counted\n---\n\n# Guide\n\nRead [the docs](https://example.com) and run `cargo build`.\n\n\
+             ![Logo](logo.png) [ref]\n\n```sh\nnot counted either\n```\n\n[ref]: https://example.com\n",
+        );
+
+        assert_eq!(stats.words, 9);
+        assert_eq!(stats.characters, 36);
+        assert_eq!(stats.headings, 1);
+        assert_eq!(stats.links, 2);
+        assert_eq!(stats.images, 1);
+        assert_eq!(stats.code_blocks, 1);
+    }
+
+    #[rstest]
+    #[case("*Emphasis*, `code`. And **more**", 4)]
+    #[case("# Title\nText", 2)]
+    #[case("| a | b |\n| - | - |\n| c | d |", 4)]
+    #[case("line  \nbreak", 2)]
+    fn test_words_across_nodes(#[case] content: &str, #[case] words: usize) {
+        assert_eq!(stats(content).words, words);
+    }
+
+    #[rstest]
+    #[case(0, 0)]
+    #[case(1, 1)]
+    #[case(200, 1)]
+    #[case(201, 2)]
+    fn test_reading_time(#[case] words: usize, #[case] minutes: usize) {
+        assert_eq!(stats(&"word ".repeat(words)).reading_minutes, minutes);
+    }
+
+    #[test]
+    fn test_outline() {
+        let stats = stats("# Guide\n\n## Install *it*\n\ntext\n\n### From `source`\n");
+        assert_eq!(
+            stats.outline,
+            vec![
+                Heading {
+                    depth: 1,
+                    text: "Guide".to_string(),
+                    line: 1
+                },
+                Heading {
+                    depth: 2,
+                    text: "Install it".to_string(),
+                    line: 3
+                },
+                Heading {
+                    depth: 3,
+                    text: "From source".to_string(),
+                    line: 7
+                },
+            ]
+        );
+    }
+
+    // ==================== Output Tests ====================
+
+    #[test]
+    fn test_status_bar() {
+        let html = stats("# One\n\n[a](b)").to_html();
+        assert_eq!(
+            html,
+            "<footer id=\"stats\" title=\"4 characters\"><span>2 words</span> · <span>1 min read</span> · \
+             <span>1 heading</span> · <span>1 link</span> · <span>0 images</span> · <span>0 code blocks</span></footer>"
+        );
+    }
+
+    #[test]
+    fn test_json_snapshot() {
+        let stats = DocumentStats {
+            file: PathBuf::from("/docs/guide.md"),
+            stats: stats("# Guide\n\nSome words here.\n\n## Next\n"),
+        };
+        assert_snapshot!(serde_json::to_string_pretty(&stats).unwrap());
+    }
+
+    #[rstest]
+    #[case("# Notes\n", 0)]
+    #[case("<Chart size=1 />\n", 2)]
+    fn test_exit_codes(#[case] content: &str, #[case] expected: i32) {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("doc.mdx");
+        fs::write(&file, content).unwrap();
+
+        assert_eq!(
+            print_stats(&file, &Config::default(), &Overrides::default()),
+            expected
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use axum::{Json, extract::State, response::IntoResponse};
+use datastar::axum::ReadSignals;
+use lazy_regex::*;
+use markdown::mdast::Node;
+use serde::Deserialize;
+use std::{
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::Path,
+    sync::Arc,
+};
+use tracing::*;
+
+use crate::{
+    editor::write_atomic,
+    error::GlyphoError,
+    state::{AddFileResponse, AppState},
+};
+
+/// The task a checkbox in the preview toggles, set by its click handler.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskSignals {
+    pub task_line: usize,
+    pub task_column: usize,
+    /// Version of the source the checkbox was rendered from
+    pub task_version: String,
+}
+
+/// Identifies the content of a document, so that a checkbox rendered from
+/// an older version cannot toggle the wrong task.
+pub fn version(content: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Makes the task list checkboxes of rendered HTML clickable. `tree` is
+/// the document `html` was rendered from, its task items are the
+/// checkboxes of `html` in order.
+pub fn task_checkboxes(html: &str, tree: &Node, version: &str) -> String {
+    let mut tasks = vec![];
+    collect_tasks(tree, &mut tasks);
+
+    // the sanitizer puts the attributes in its own order
+    let checkbox = regex!(r#"<input\b([^>]*?\btype="checkbox"[^>]*?)\s*/?>"#);
+    // raw HTML checkboxes would shift the tasks onto the wrong items
+    if tasks.is_empty() || checkbox.find_iter(html).count() != tasks.len() {
+        return html.to_string();
+    }
+
+    let mut tasks = tasks.into_iter();
+    checkbox
+        .replace_all(html, |caps: &Captures| {
+            let Some((line, column)) = tasks.next() else {
+                return caps[0].to_string();
+            };
+            let checked = if caps[1].contains("checked") {
+                " checked=\"\""
+            } else {
+                ""
+            };
+            format!(
+                "<input type=\"checkbox\"{checked} class=\"task\" data-on:click=\"$taskLine = {line}; $taskColumn = {column}; $taskVersion = '{version}'; @post('/task')\" />"
+            )
+        })
+        .into_owned()
+}
+
+fn collect_tasks(node: &Node, tasks: &mut Vec<(usize, usize)>) {
+    if let Node::ListItem(item) = node
+        && item.checked.is_some()
+        && let Some(position) = &item.position
+    {
+        tasks.push((position.start.line, position.start.column));
+    }
+    for child in node.children().into_iter().flatten() {
+        collect_tasks(child, tasks);
+    }
+}
+
+/// `content` with the task whose list item starts at `line` and `column`
+/// checked or unchecked.
+pub fn toggle(content: &str, line: usize, column: usize) -> Result<String, GlyphoError> {
+    let marker = regex!(r"^(?:[-*+]|\d{1,9}[.)])[ \t]+\[([ xX])\]");
+
+    let mut lines = content.split_inclusive('\n');
+    let line_start: usize = lines
+        .by_ref()
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let start = line_start + column.saturating_sub(1);
+    let state = lines
+        .next()
+        .filter(|_| line > 0)
+        .and_then(|text| text.get(column.saturating_sub(1)..))
+        .and_then(|rest| marker.captures(rest))
+        .and_then(|caps| caps.get(1))
+        .ok_or(GlyphoError::NoTask(line))?;
+
+    let at = start + state.start();
+    let toggled = if state.as_str() == " " { "x" } else { " " };
+    Ok(format!("{}{toggled}{}", &content[..at], &content[at + 1..]))
+}
+
+/// Toggles the task in `file` unless the file changed since `version`.
+pub fn toggle_in_file(file: &Path, signals: &TaskSignals) -> Result<(), GlyphoError> {
+    let content = fs::read_to_string(file).map_err(|err| GlyphoError::from(err.kind()))?;
+    if version(&content) != signals.task_version {
+        return Err(GlyphoError::StaleDocument);
+    }
+    let content = toggle(&content, signals.task_line, signals.task_column)?;
+    write_atomic(file, &content).map_err(|err| GlyphoError::from(err.kind()))
+}
+
+/// Checks or unchecks a task of the active document on disk. The watcher
+/// renders the change, a refused toggle renders the document right away
+/// to reset the checkbox.
+pub async fn toggle_task(
+    State(state): State<Arc<AppState>>,
+    ReadSignals(signals): ReadSignals<TaskSignals>,
+) -> impl IntoResponse {
+    let mut s = state.lock().await;
+    let file = s.active_file().to_path_buf();
+    if s.config_for(&file).safe {
+        s.refresh(&file);
+        return Json(AddFileResponse { ok: false });
+    }
+
+    match toggle_in_file(&file, &signals) {
+        Ok(()) => Json(AddFileResponse { ok: true }),
+        Err(err) => {
+            warn!(
+                "Cannot toggle the task on line {}: {err}",
+                signals.task_line
+            );
+            s.refresh(&file);
+            Json(AddFileResponse { ok: false })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dialect::Dialect, testing};
+    use markdown::ParseOptions;
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    fn render(content: &str) -> String {
+        let (html, tree) = testing::render(content, Dialect::Gfm);
+        task_checkboxes(&html, &tree, "v1")
+    }
+
+    // ==================== Checkbox Tests ====================
+
+    #[test]
+    fn test_checkboxes_clickable() {
+        let html = render("- [x] done\n- [ ] todo\n");
+
+        assert!(html.contains(
+            "<li><input type=\"checkbox\" checked=\"\" class=\"task\" data-on:click=\"$taskLine = 1; $taskColumn = 1; $taskVersion = 'v1'; @post('/task')\" /> done</li>"
+        ));
+        assert!(html.contains("$taskLine = 2; $taskColumn = 1;"));
+        assert!(!html.contains("disabled"));
+    }
+
+    #[test]
+    fn test_nested_and_quoted_tasks() {
+        let html = render("- [ ] parent\n  - [ ] child\n\n> 1. [x] quoted\n");
+
+        assert!(html.contains("$taskLine = 2; $taskColumn = 3;"));
+        assert!(html.contains("$taskLine = 4; $taskColumn = 3;"));
+    }
+
+    #[test]
+    fn test_sanitized_checkboxes_clickable() {
+        let html =
+            "<ul>\n<li><input disabled=\"\" type=\"checkbox\" checked=\"\"> done</li>\n</ul>";
+        let tree = markdown::to_mdast("- [x] done", &ParseOptions::gfm()).unwrap();
+
+        let html = task_checkboxes(html, &tree, "v1");
+        assert!(html.contains("<li><input type=\"checkbox\" checked=\"\" class=\"task\""));
+    }
+
+    #[test]
+    fn test_raw_checkboxes_left_alone() {
+        let html = render("- [ ] task\n\n<input type=\"checkbox\">\n");
+        assert!(!html.contains("data-on:click"));
+    }
+
+    #[test]
+    fn test_version_changes_with_content() {
+        assert_eq!(version("- [ ] a"), version("- [ ] a"));
+        assert_ne!(version("- [ ] a"), version("- [x] a"));
+    }
+
+    // ==================== Toggle Tests ====================
+
+    #[rstest]
+    #[case("- [ ] todo\n", 1, 1, "- [x] todo\n")]
+    #[case("- [x] done\n", 1, 1, "- [ ] done\n")]
+    #[case("- [X] done\n", 1, 1, "- [ ] done\n")]
+    #[case("# Tasks\n\n* [ ] a\n* [ ] b\n", 4, 1, "# Tasks\n\n* [ ] a\n* [x] b\n")]
+    #[case("- [ ] parent\n  - [ ] child\n", 2, 3, "- [ ] parent\n  - [x] child\n")]
+    #[case("> 1. [ ] quoted\n", 1, 3, "> 1. [x] quoted\n")]
+    #[case("10)  [x] wide\n", 1, 1, "10)  [ ] wide\n")]
+    #[case("- [ ] café ✓\r\n- [ ] b\r\n", 2, 1, "- [ ] café ✓\r\n- [x] b\r\n")]
+    fn test_toggle(
+        #[case] content: &str,
+        #[case] line: usize,
+        #[case] column: usize,
+        #[case] expected: &str,
+    ) {
+        assert_eq!(toggle(content, line, column).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("- [ ] todo\n", 2, 1)]
+    #[case("- [ ] todo\n", 0, 1)]
+    #[case("- plain item\n", 1, 1)]
+    #[case("- [ ] todo\n", 1, 40)]
+    #[case("x\n- [ ] todo\n", 1, 3)]
+    fn test_no_task(#[case] content: &str, #[case] line: usize, #[case] column: usize) {
+        assert!(matches!(
+            toggle(content, line, column),
+            Err(GlyphoError::NoTask(_))
+        ));
+    }
+
+    #[test]
+    fn test_positions_match_source() {
+        // the rendered positions toggle the right items
+        let content = "Intro\n\n- [ ] one\n  - [x] two\n\n> - [ ] three\n";
+        let html = render(content);
+        let lines = regex!(r"\$taskLine = (\d+); \$taskColumn = (\d+);");
+
+        let mut toggled = content.to_string();
+        for caps in lines.captures_iter(&html) {
+            toggled = toggle(&toggled, caps[1].parse().unwrap(), caps[2].parse().unwrap()).unwrap();
+        }
+        assert_eq!(
+            toggled,
+            "Intro\n\n- [x] one\n  - [ ] two\n\n> - [x] three\n"
+        );
+    }
+
+    // ==================== File Tests ====================
+
+    fn signals(content: &str, line: usize) -> TaskSignals {
+        TaskSignals {
+            task_line: line,
+            task_column: 1,
+            task_version: version(content),
+        }
+    }
+
+    #[test]
+    fn test_toggle_in_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("todo.md");
+        fs::write(&file, "- [ ] a\n- [ ] b\n").unwrap();
+
+        toggle_in_file(&file, &signals("- [ ] a\n- [ ] b\n", 2)).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "- [ ] a\n- [x] b\n");
+    }
+
+    #[test]
+    fn test_changed_file_not_toggled() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("todo.md");
+        fs::write(&file, "- [ ] new first\n- [ ] a\n").unwrap();
+
+        let result = toggle_in_file(&file, &signals("- [ ] a\n", 1));
+        assert!(matches!(result, Err(GlyphoError::StaleDocument)));
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "- [ ] new first\n- [ ] a\n"
+        );
+    }
+}
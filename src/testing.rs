@@ -0,0 +1,31 @@
+//! Helpers shared by the unit tests of the modules.
+
+use markdown::{Options, mdast::Node};
+use std::path::Path;
+
+use crate::{blocks::tag_blocks, config::MarkdownConfig, dialect::Dialect};
+
+/// The options a document in `dialect` is rendered with when the
+/// configuration changes no construct.
+pub fn options(dialect: Dialect) -> Options {
+    MarkdownConfig {
+        dialect: Some(dialect),
+        ..MarkdownConfig::default()
+    }
+    .options(Path::new("doc.md"))
+}
+
+/// The HTML of `content` in `dialect` and its syntax tree.
+pub fn render(content: &str, dialect: Dialect) -> (String, Node) {
+    let options = options(dialect);
+    let html = markdown::to_html_with_options(content, &options).unwrap();
+    let tree = markdown::to_mdast(content, &options.parse).unwrap();
+    (html, tree)
+}
+
+/// `render` with the top-level blocks tagged, as the preview does before
+/// sanitizing.
+pub fn tagged(content: &str, dialect: Dialect) -> (String, Node) {
+    let (html, tree) = render(content, dialect);
+    (tag_blocks(&html, &tree, &options(dialect).compile), tree)
+}
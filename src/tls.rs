@@ -0,0 +1,207 @@
+use axum::serve::Listener;
+use std::{io, net::SocketAddr, path::Path, sync::Arc, time::Duration};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ServerConfig,
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, pem::PemObject},
+    },
+    server::TlsStream,
+};
+use tracing::*;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn server_config(
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> eyre::Result<Arc<ServerConfig>> {
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Loads a PEM certificate chain and private key supplied by the user.
+pub fn load_pem(cert: &Path, key: &Path) -> eyre::Result<Arc<ServerConfig>> {
+    let certs = CertificateDer::pem_file_iter(cert)?.collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        eyre::bail!("No certificate found in {}", cert.display());
+    }
+    let key = PrivateKeyDer::from_pem_file(key)?;
+    server_config(certs, key)
+}
+
+/// Generates a throwaway certificate for `hosts`, browsers will ask to trust
+/// it on every run.
+pub fn self_signed(hosts: Vec<String>) -> eyre::Result<Arc<ServerConfig>> {
+    let certified = rcgen::generate_simple_self_signed(hosts)?;
+    let key = PrivatePkcs8KeyDer::from(certified.signing_key.serialize_der());
+    server_config(vec![certified.cert.der().clone()], key.into())
+}
+
+/// A TCP listener that hands out connections once their TLS handshake is done.
+/// Handshakes run concurrently so a slow client does not block the others.
+pub struct TlsListener {
+    local_addr: SocketAddr,
+    connections: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let acceptor = TlsAcceptor::from(config);
+        let (sender, connections) = mpsc::channel(32);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(err) => {
+                        debug!("Accept error: {err}");
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+
+                let acceptor = acceptor.clone();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(tls)) => {
+                            let _ = sender.send((tls, addr)).await;
+                        }
+                        Ok(Err(err)) => debug!("TLS handshake with {addr} failed: {err}"),
+                        Err(_) => debug!("TLS handshake with {addr} timed out"),
+                    }
+                });
+            }
+        });
+
+        Ok(TlsListener {
+            local_addr,
+            connections,
+        })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            // the accept task never returns, it only goes away on panic
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, routing::get};
+    use tempfile::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{
+        TlsConnector,
+        rustls::{ClientConfig, RootCertStore, pki_types::ServerName},
+    };
+
+    fn write_pem(dir: &TempDir) -> (std::path::PathBuf, std::path::PathBuf) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert = dir.path().join("cert.pem");
+        let key = dir.path().join("key.pem");
+        std::fs::write(&cert, certified.cert.pem()).unwrap();
+        std::fs::write(&key, certified.signing_key.serialize_pem()).unwrap();
+        (cert, key)
+    }
+
+    // ==================== Configuration Tests ====================
+
+    #[test]
+    fn test_load_pem() {
+        let dir = TempDir::new().unwrap();
+        let (cert, key) = write_pem(&dir);
+
+        let config = load_pem(&cert, &key).unwrap();
+        assert_eq!(config.alpn_protocols, vec![b"http/1.1".to_vec()]);
+    }
+
+    #[test]
+    fn test_load_pem_missing_file() {
+        let dir = TempDir::new().unwrap();
+        let (cert, _) = write_pem(&dir);
+
+        assert!(load_pem(&cert, &dir.path().join("missing.pem")).is_err());
+    }
+
+    #[test]
+    fn test_load_pem_key_as_certificate() {
+        let dir = TempDir::new().unwrap();
+        let (_, key) = write_pem(&dir);
+
+        assert!(load_pem(&key, &key).is_err());
+    }
+
+    #[test]
+    fn test_self_signed() {
+        let hosts = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+        assert!(self_signed(hosts).is_ok());
+    }
+
+    // ==================== Listener Tests ====================
+
+    #[tokio::test]
+    async fn test_serves_over_tls() {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivatePkcs8KeyDer::from(certified.signing_key.serialize_der());
+        let config = server_config(vec![certified.cert.der().clone()], key.into()).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener = TlsListener::new(listener, config).unwrap();
+        let addr = Listener::local_addr(&listener).unwrap();
+        let router = Router::new().route("/", get(|| async { "over tls" }));
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let client = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(client));
+
+        // a plain HTTP client fails the handshake without stopping the listener
+        let mut plain = TcpStream::connect(addr).await.unwrap();
+        plain
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let domain = ServerName::try_from("localhost").unwrap();
+        let mut tls = connector.connect(domain, tcp).await.unwrap();
+        tls.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        tls.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("over tls"));
+    }
+}
@@ -0,0 +1,117 @@
+use lazy_regex::*;
+use std::collections::HashMap;
+
+/// Gives every heading of the rendered document an `id` to link to, made
+/// from its text like GitHub does, `-1`, `-2`... are appended to repeats.
+pub fn heading_ids(html: &str) -> String {
+    let heading = regex!(r"<h([1-6])>([\s\S]*?)</h[1-6]>");
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    heading
+        .replace_all(html, |caps: &Captures| {
+            let slug = slug(&text(&caps[2]));
+            let count = seen.entry(slug.clone()).or_default();
+            let id = match *count {
+                0 => slug,
+                n => format!("{slug}-{n}"),
+            };
+            *count += 1;
+            format!("<h{0} id=\"{id}\">{1}</h{0}>", &caps[1], &caps[2])
+        })
+        .into_owned()
+}
+
+/// A list of links to the headings `heading_ids` named, for the `toc` slot
+/// of page templates.
+pub fn toc_html(html: &str) -> String {
+    let heading = regex!(r#"<h([1-6]) id="([^"]*)">([\s\S]*?)</h[1-6]>"#);
+    let items: String = heading
+        .captures_iter(html)
+        .map(|caps| {
+            format!(
+                "<li class=\"toc-h{}\"><a href=\"#{}\">{}</a></li>",
+                &caps[1],
+                &caps[2],
+                text(&caps[3])
+            )
+        })
+        .collect();
+
+    format!("<nav id=\"toc\"><ul>{items}</ul></nav>")
+}
+
+// The heading without its markup, entities stay escaped.
+fn text(html: &str) -> String {
+    regex_replace_all!(r"<[^>]*>", html, "").trim().to_string()
+}
+
+fn slug(text: &str) -> String {
+    let text = regex_replace_all!(r"&[a-zA-Z0-9#]+;", text, "");
+    text.chars()
+        .filter_map(|c| match c {
+            ' ' | '-' => Some('-'),
+            '_' => Some('_'),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    // ==================== Heading Id Tests ====================
+
+    #[rstest]
+    #[case("<h1>Introduction</h1>", "<h1 id=\"introduction\">Introduction</h1>")]
+    #[case(
+        "<h2>Getting started!</h2>",
+        "<h2 id=\"getting-started\">Getting started!</h2>"
+    )]
+    #[case(
+        "<h3>The <code>render</code> step</h3>",
+        "<h3 id=\"the-render-step\">The <code>render</code> step</h3>"
+    )]
+    #[case("<h2>Q&amp;A</h2>", "<h2 id=\"qa\">Q&amp;A</h2>")]
+    #[case("<h2>Über uns</h2>", "<h2 id=\"über-uns\">Über uns</h2>")]
+    fn test_heading_ids(#[case] html: &str, #[case] expected: &str) {
+        assert_eq!(heading_ids(html), expected);
+    }
+
+    #[test]
+    fn test_repeated_headings() {
+        let html = heading_ids("<h2>Usage</h2><h2>Usage</h2><h2>Usage</h2>");
+        assert_eq!(
+            html,
+            "<h2 id=\"usage\">Usage</h2><h2 id=\"usage-1\">Usage</h2><h2 id=\"usage-2\">Usage</h2>"
+        );
+    }
+
+    #[test]
+    fn test_headings_with_attributes_untouched() {
+        let html = "<h2 id=\"custom\">Custom</h2>";
+        assert_eq!(heading_ids(html), html);
+    }
+
+    // ==================== Table of Contents Tests ====================
+
+    #[test]
+    fn test_toc_html() {
+        let html = heading_ids("<h1>Guide</h1><p>text</p><h2>The <em>first</em> step</h2>");
+        assert_eq!(
+            toc_html(&html),
+            "<nav id=\"toc\"><ul>\
+             <li class=\"toc-h1\"><a href=\"#guide\">Guide</a></li>\
+             <li class=\"toc-h2\"><a href=\"#the-first-step\">The first step</a></li>\
+             </ul></nav>"
+        );
+    }
+
+    #[test]
+    fn test_toc_without_headings() {
+        assert_eq!(toc_html("<p>text</p>"), "<nav id=\"toc\"><ul></ul></nav>");
+    }
+}
@@ -1,6 +1,83 @@
 use lazy_regex::*;
+use markdown::{
+    mdast::Node,
+    message::{Message, Place},
+    unist::Point,
+};
+use std::ops::Range;
 
 pub fn wikilinks_to_markdown(input: &str) -> String {
+    convert(input, None).0
+}
+
+/// Like [`wikilinks_to_markdown`], appending `extension` to targets that have
+/// none so that `[[notes]]` can link to `notes.md`.
+pub fn wikilinks_with_extension(input: &str, extension: &str) -> String {
+    convert(input, Some(extension)).0
+}
+
+/// The wikilinks of `input` turned into Markdown links, and where they were
+/// in `input`.
+pub fn rewrite_wikilinks(input: &str, extension: Option<&str>) -> (String, SourceMap) {
+    convert(input, extension)
+}
+
+/// Where each wikilink of a document is in its rewritten text and in the
+/// document, to report places of the rewritten text in the document.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap(Vec<(Range<usize>, Range<usize>)>);
+
+impl SourceMap {
+    /// The offset in the document of `offset` in the rewritten text. Offsets
+    /// inside a link are at the start of its wikilink.
+    fn offset(&self, offset: usize) -> usize {
+        match self.0.iter().rev().find(|(link, _)| link.start <= offset) {
+            Some((link, wikilink)) if offset < link.end => wikilink.start,
+            Some((link, wikilink)) => wikilink.end + offset - link.end,
+            None => offset,
+        }
+    }
+
+    /// `point` of the rewritten text in the document. Wikilinks do not span
+    /// lines, only the column moves.
+    fn point(&self, point: &Point) -> Point {
+        let offset = self.offset(point.offset);
+        let column = (point.column + offset).saturating_sub(point.offset).max(1);
+        Point::new(point.line, column, offset)
+    }
+
+    /// `message` of the parser with its place in the document.
+    pub fn message(&self, mut message: Message) -> Message {
+        message.place = message.place.map(|place| {
+            Box::new(match *place {
+                Place::Point(point) => Place::Point(self.point(&point)),
+                Place::Position(mut position) => {
+                    position.start = self.point(&position.start);
+                    position.end = self.point(&position.end);
+                    Place::Position(position)
+                }
+            })
+        });
+        message
+    }
+
+    /// Moves the positions of `tree`, parsed from the rewritten text, to the
+    /// document.
+    pub fn tree(&self, tree: &mut Node) {
+        if self.0.is_empty() {
+            return;
+        }
+        if let Some(position) = tree.position_mut() {
+            position.start = self.point(&position.start);
+            position.end = self.point(&position.end);
+        }
+        for child in tree.children_mut().into_iter().flatten() {
+            self.tree(child);
+        }
+    }
+}
+
+fn convert(input: &str, extension: Option<&str>) -> (String, SourceMap) {
     // The regex captures:
     //   1. `target` – everything up to the first `|` or `]`
     //   2. optional `label` – everything after the `|` up to `]`
@@ -17,6 +94,7 @@ pub fn wikilinks_to_markdown(input: &str) -> String {
 
     // `last_end` keeps track of the byte index we have already processed.
     let mut last_end = 0;
+    let mut source_map = SourceMap::default();
 
     // Iterate over all non‑overlapping matches.
     for caps in re.captures_iter(input) {
@@ -31,11 +109,16 @@ pub fn wikilinks_to_markdown(input: &str) -> String {
         let label = caps.name("label").map_or(target, |m| m.as_str());
 
         // Emit the Markdown link.
+        let start = result.len();
         result.push('[');
         result.push_str(label);
         result.push_str("](");
-        result.push_str(target);
+        match extension {
+            Some(ext) => result.push_str(&with_extension(target, ext)),
+            None => result.push_str(target),
+        }
         result.push(')');
+        source_map.0.push((start..result.len(), span.clone()));
 
         // Update our cursor to the end of the match.
         last_end = span.end;
@@ -44,7 +127,30 @@ pub fn wikilinks_to_markdown(input: &str) -> String {
     // Append the tail of the input that never matched the regex.
     result.push_str(&input[last_end..]);
 
-    result
+    (result, source_map)
+}
+
+fn with_extension(target: &str, extension: &str) -> String {
+    // keep a `#section` anchor after the extension
+    let (page, anchor) = match target.split_once('#') {
+        Some((page, anchor)) => (page, Some(anchor)),
+        None => (target, None),
+    };
+    let has_extension = page.is_empty()
+        || std::path::Path::new(page)
+            .extension()
+            .is_some_and(|e| !e.is_empty());
+
+    let mut link = page.to_string();
+    if !has_extension {
+        link.push('.');
+        link.push_str(extension.trim_start_matches('.'));
+    }
+    if let Some(anchor) = anchor {
+        link.push('#');
+        link.push_str(anchor);
+    }
+    link
 }
 
 #[cfg(test)]
@@ -161,6 +267,55 @@ mod tests {
         assert_eq!(wikilinks_to_markdown(input), expected);
     }
 
+    // ==================== Extension Tests ====================
+
+    #[rstest]
+    #[case("[[notes]]", "[notes](notes.md)")]
+    #[case("[[notes|My Notes]]", "[My Notes](notes.md)")]
+    #[case("[[notes#todo]]", "[notes#todo](notes.md#todo)")]
+    #[case("[[diagram.png]]", "[diagram.png](diagram.png)")]
+    #[case("[[#todo]]", "[#todo](#todo)")]
+    #[case("[[v1.2/notes]]", "[v1.2/notes](v1.2/notes.md)")]
+    fn test_wikilinks_with_extension(#[case] input: &str, #[case] expected: &str) {
+        assert_eq!(wikilinks_with_extension(input, "md"), expected);
+        assert_eq!(wikilinks_with_extension(input, ".md"), expected);
+    }
+
+    // ==================== Source Map Tests ====================
+
+    #[rstest]
+    // before the first wikilink
+    #[case(2, 2)]
+    // inside the link, at the start of its wikilink
+    #[case(8, 4)]
+    // after a link, moved by the length of each rewrite
+    #[case(22, 23)]
+    #[case(28, 29)]
+    #[case(35, 35)]
+    fn test_source_map_offset(#[case] rewritten: usize, #[case] expected: usize) {
+        let (text, source_map) = rewrite_wikilinks("See [[Some Page|label]] then [[x]] z", None);
+        assert_eq!(text, "See [label](Some Page) then [x](x) z");
+        assert_eq!(source_map.offset(rewritten), expected);
+    }
+
+    #[test]
+    fn test_source_map_message() {
+        let input = "# Title\n\nSee [[Some Page|label]] then <Chart size=1 />\n";
+        let (text, source_map) = rewrite_wikilinks(input, None);
+        let options = markdown::Options {
+            parse: markdown::ParseOptions::mdx(),
+            ..markdown::Options::default()
+        };
+        let message = markdown::to_html_with_options(&text, &options).unwrap_err();
+
+        let message = source_map.message(message);
+        let Some(Place::Point(point)) = message.place.as_deref() else {
+            panic!("no point in {message:?}");
+        };
+        assert_eq!((point.line, point.column), (3, 42));
+        assert_eq!(&input[point.offset..point.offset + 4], "1 />");
+    }
+
     // ==================== Snapshot Tests ====================
 
     #[test]
@@ -237,7 +392,7 @@ Back to [[Normal]] text with [[Multiple|Links]] in [[One|Line]].
         #[test]
         fn test_plain_text_unchanged(text in "[a-zA-Z0-9 ,.!?]{0,100}") {
             // Text without [[ should remain unchanged
-            let text_no_brackets = text.replace('[', "").replace(']', "");
+            let text_no_brackets = text.replace(['[', ']'], "");
             let result = wikilinks_to_markdown(&text_no_brackets);
             prop_assert_eq!(result, text_no_brackets);
         }
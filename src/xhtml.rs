@@ -0,0 +1,334 @@
+use html5ever::{
+    Attribute, ParseOpts, QualName,
+    interface::{ElementFlags, NodeOrText, QuirksMode, TreeSink},
+    local_name, ns, parse_fragment,
+    tendril::{StrTendril, TendrilSink},
+};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
+
+// Elements without content, closed with `/>` in XHTML.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+// Attribute prefixes XML knows, the datastar attributes of the preview have
+// others.
+const PREFIXES: &[&str] = &["xml", "xmlns", "xlink", "epub"];
+
+/// Rendered HTML as the XHTML EPUB chapters are made of. The HTML is parsed
+/// the way a browser would and written back as XML: every element closed,
+/// every attribute quoted, characters instead of entities and no comments.
+/// The datastar attributes of the preview are left out.
+pub fn xhtml(html: &str) -> String {
+    let context = QualName::new(None, ns!(html), local_name!("body"));
+    let dom =
+        parse_fragment(Dom::default(), ParseOpts::default(), context, vec![], false).one(html);
+
+    let mut xhtml = String::new();
+    // the fragment is parsed into an <html> element of its own
+    for root in dom.document.children.borrow().iter() {
+        for node in root.children.borrow().iter() {
+            write(&mut xhtml, node, &ns!(html));
+        }
+    }
+    xhtml
+}
+
+fn write(xhtml: &mut String, node: &Handle, parent_ns: &html5ever::Namespace) {
+    let (name, attrs) = match &node.data {
+        Data::Text(text) => {
+            xhtml.push_str(&escape(&text.borrow(), false));
+            return;
+        }
+        Data::Document | Data::Comment => return,
+        Data::Element { name, attrs, .. } => (name, attrs.borrow()),
+    };
+
+    xhtml.push('<');
+    xhtml.push_str(&name.local);
+    let mut has_xmlns = false;
+    for attr in attrs.iter() {
+        let attr_name = match &attr.name.prefix {
+            Some(prefix) => format!("{prefix}:{}", attr.name.local),
+            None => attr.name.local.to_string(),
+        };
+        if let Some((prefix, _)) = attr_name.split_once(':')
+            && !PREFIXES.contains(&prefix)
+        {
+            continue;
+        }
+        has_xmlns |= attr_name == "xmlns";
+        xhtml.push_str(&format!(" {attr_name}=\"{}\"", escape(&attr.value, true)));
+    }
+    // SVG and MathML keep their namespace in XML
+    if name.ns != *parent_ns && !has_xmlns {
+        xhtml.push_str(&format!(" xmlns=\"{}\"", name.ns));
+    }
+
+    let children = node.children.borrow();
+    let void = if name.ns == ns!(html) {
+        VOID_ELEMENTS.contains(&&*name.local)
+    } else {
+        children.is_empty()
+    };
+    if void {
+        xhtml.push_str(" />");
+        return;
+    }
+    xhtml.push('>');
+    for child in children.iter() {
+        write(xhtml, child, &name.ns);
+    }
+    xhtml.push_str(&format!("</{}>", name.local));
+}
+
+fn escape(text: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+type Handle = Rc<Node>;
+
+// A document as html5ever builds it, only what XHTML needs of it is kept.
+struct Node {
+    parent: RefCell<Option<Weak<Node>>>,
+    children: RefCell<Vec<Handle>>,
+    data: Data,
+}
+
+enum Data {
+    Document,
+    Element {
+        name: QualName,
+        attrs: RefCell<Vec<Attribute>>,
+        template: Option<Handle>,
+    },
+    Text(RefCell<StrTendril>),
+    Comment,
+}
+
+impl Node {
+    fn new(data: Data) -> Handle {
+        Rc::new(Node {
+            parent: RefCell::new(None),
+            children: RefCell::new(vec![]),
+            data,
+        })
+    }
+}
+
+struct Dom {
+    document: Handle,
+}
+
+impl Default for Dom {
+    fn default() -> Self {
+        Dom {
+            document: Node::new(Data::Document),
+        }
+    }
+}
+
+impl Dom {
+    fn detach(&self, node: &Handle) {
+        let parent = node.parent.take().and_then(|parent| parent.upgrade());
+        if let Some(parent) = parent {
+            parent
+                .children
+                .borrow_mut()
+                .retain(|child| !Rc::ptr_eq(child, node));
+        }
+    }
+
+    // Inserts `child` into `parent` before `sibling`, or last. Text next to
+    // text joins it, as in the DOM of a browser.
+    fn insert(&self, parent: &Handle, sibling: Option<&Handle>, child: NodeOrText<Handle>) {
+        let node = match child {
+            NodeOrText::AppendText(text) => Node::new(Data::Text(RefCell::new(text))),
+            NodeOrText::AppendNode(node) => {
+                self.detach(&node);
+                node
+            }
+        };
+        let mut children = parent.children.borrow_mut();
+        let index = sibling
+            .and_then(|sibling| children.iter().position(|c| Rc::ptr_eq(c, sibling)))
+            .unwrap_or(children.len());
+        if let (Data::Text(text), Some(previous)) = (&node.data, index.checked_sub(1))
+            && let Data::Text(previous) = &children[previous].data
+        {
+            previous.borrow_mut().push_tendril(&text.borrow());
+            return;
+        }
+        *node.parent.borrow_mut() = Some(Rc::downgrade(parent));
+        children.insert(index, node);
+    }
+}
+
+impl TreeSink for Dom {
+    type Handle = Handle;
+    type Output = Self;
+    type ElemName<'a> = &'a QualName;
+
+    fn finish(self) -> Self {
+        self
+    }
+
+    fn parse_error(&self, _msg: Cow<'static, str>) {}
+
+    fn get_document(&self) -> Handle {
+        self.document.clone()
+    }
+
+    fn elem_name<'a>(&'a self, target: &'a Handle) -> &'a QualName {
+        match &target.data {
+            Data::Element { name, .. } => name,
+            _ => panic!("not an element"),
+        }
+    }
+
+    fn create_element(&self, name: QualName, attrs: Vec<Attribute>, flags: ElementFlags) -> Handle {
+        Node::new(Data::Element {
+            name,
+            attrs: RefCell::new(attrs),
+            template: flags.template.then(|| Node::new(Data::Document)),
+        })
+    }
+
+    fn create_comment(&self, _text: StrTendril) -> Handle {
+        Node::new(Data::Comment)
+    }
+
+    fn create_pi(&self, _target: StrTendril, _data: StrTendril) -> Handle {
+        Node::new(Data::Comment)
+    }
+
+    fn append(&self, parent: &Handle, child: NodeOrText<Handle>) {
+        self.insert(parent, None, child);
+    }
+
+    fn append_based_on_parent_node(
+        &self,
+        element: &Handle,
+        prev_element: &Handle,
+        child: NodeOrText<Handle>,
+    ) {
+        if element.parent.borrow().is_some() {
+            self.append_before_sibling(element, child);
+        } else {
+            self.append(prev_element, child);
+        }
+    }
+
+    fn append_doctype_to_document(
+        &self,
+        _name: StrTendril,
+        _public: StrTendril,
+        _system: StrTendril,
+    ) {
+    }
+
+    fn get_template_contents(&self, target: &Handle) -> Handle {
+        match &target.data {
+            Data::Element {
+                template: Some(contents),
+                ..
+            } => contents.clone(),
+            _ => panic!("not a template element"),
+        }
+    }
+
+    fn same_node(&self, x: &Handle, y: &Handle) -> bool {
+        Rc::ptr_eq(x, y)
+    }
+
+    fn set_quirks_mode(&self, _mode: QuirksMode) {}
+
+    fn append_before_sibling(&self, sibling: &Handle, new_node: NodeOrText<Handle>) {
+        let parent = sibling.parent.borrow().as_ref().and_then(Weak::upgrade);
+        if let Some(parent) = parent {
+            self.insert(&parent, Some(sibling), new_node);
+        }
+    }
+
+    fn add_attrs_if_missing(&self, target: &Handle, attrs: Vec<Attribute>) {
+        let Data::Element {
+            attrs: existing, ..
+        } = &target.data
+        else {
+            return;
+        };
+        let mut existing = existing.borrow_mut();
+        for attr in attrs {
+            if !existing.iter().any(|e| e.name == attr.name) {
+                existing.push(attr);
+            }
+        }
+    }
+
+    fn remove_from_parent(&self, target: &Handle) {
+        self.detach(target);
+    }
+
+    fn reparent_children(&self, node: &Handle, new_parent: &Handle) {
+        let children = node.children.take();
+        for child in children {
+            *child.parent.borrow_mut() = None;
+            self.append(new_parent, NodeOrText::AppendNode(child));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    // ==================== XHTML Tests ====================
+
+    #[rstest]
+    #[case("<p>a<br>b</p>", "<p>a<br />b</p>")]
+    #[case("<img src=\"a.png\" alt=\"\">", "<img src=\"a.png\" alt=\"\" />")]
+    #[case("<hr />", "<hr />")]
+    #[case(
+        "<input type=checkbox checked disabled>",
+        "<input type=\"checkbox\" checked=\"\" disabled=\"\" />"
+    )]
+    #[case(
+        "<table><tbody><tr><td align='left'>x</td></tr></tbody></table>",
+        "<table><tbody><tr><td align=\"left\">x</td></tr></tbody></table>"
+    )]
+    #[case(
+        "<button data-on:click=\"@post('/task')\">x</button>",
+        "<button>x</button>"
+    )]
+    #[case(
+        "<svg viewBox=\"0 0 1 1\" xmlns:xlink=\"http://www.w3.org/1999/xlink\"><use xlink:href=\"#a\"/></svg>",
+        "<svg viewBox=\"0 0 1 1\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" xmlns=\"http://www.w3.org/2000/svg\"><use xlink:href=\"#a\" /></svg>"
+    )]
+    #[case("<p>a<!-- gone -->b</p>", "<p>ab</p>")]
+    #[case("Tom &amp; Jerry & co", "Tom &amp; Jerry &amp; co")]
+    #[case("&nbsp;&mdash;&#169;&#x2014;&lt;", "\u{a0}—©—&lt;")]
+    #[case("&unknown;", "&amp;unknown;")]
+    #[case("<p title='say \"hi\"'>x</p>", "<p title=\"say &quot;hi&quot;\">x</p>")]
+    #[case("<p title=\"a > b\">x</p>", "<p title=\"a &gt; b\">x</p>")]
+    #[case("<ul><li>one<li>two</ul>", "<ul><li>one</li><li>two</li></ul>")]
+    #[case("<b><i>x</b>y</i>", "<b><i>x</i></b><i>y</i>")]
+    fn test_xhtml(#[case] html: &str, #[case] expected: &str) {
+        assert_eq!(xhtml(html), expected);
+    }
+}
@@ -0,0 +1,194 @@
+use std::io;
+
+// Every entry is dated 1980-01-01, the earliest date ZIP can hold, so that
+// the same book is always the same archive.
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Writes a ZIP archive of uncompressed files, in the order they are added.
+/// EPUB readers require the first file stored that way, the others may be.
+#[derive(Debug, Default)]
+pub struct ZipWriter {
+    data: Vec<u8>,
+    central: Vec<u8>,
+    entries: u16,
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: &str, content: &[u8]) -> io::Result<()> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "archive too large");
+        let offset = u32::try_from(self.data.len()).map_err(|_| too_large())?;
+        let size = u32::try_from(content.len()).map_err(|_| too_large())?;
+        let name_len = u16::try_from(name.len()).map_err(|_| too_large())?;
+        self.entries = self.entries.checked_add(1).ok_or_else(too_large)?;
+        let crc = crc32(content);
+        // names are UTF-8
+        let flags: u16 = if name.is_ascii() { 0 } else { 1 << 11 };
+
+        let header = |out: &mut Vec<u8>| {
+            put16(out, 20); // version needed to extract
+            put16(out, flags);
+            put16(out, 0); // stored
+            put16(out, 0); // time
+            put16(out, DOS_DATE);
+            put32(out, crc);
+            put32(out, size);
+            put32(out, size);
+            put16(out, name_len);
+            put16(out, 0); // extra field
+        };
+
+        put32(&mut self.data, 0x0403_4b50);
+        header(&mut self.data);
+        self.data.extend_from_slice(name.as_bytes());
+        self.data.extend_from_slice(content);
+
+        put32(&mut self.central, 0x0201_4b50);
+        put16(&mut self.central, 20); // made by
+        header(&mut self.central);
+        put16(&mut self.central, 0); // comment
+        put16(&mut self.central, 0); // disk
+        put16(&mut self.central, 0); // internal attributes
+        put32(&mut self.central, 0); // external attributes
+        put32(&mut self.central, offset);
+        self.central.extend_from_slice(name.as_bytes());
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<Vec<u8>> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "archive too large");
+        let offset = u32::try_from(self.data.len()).map_err(|_| too_large())?;
+        let size = u32::try_from(self.central.len()).map_err(|_| too_large())?;
+
+        self.data.append(&mut self.central);
+        put32(&mut self.data, 0x0605_4b50);
+        put16(&mut self.data, 0); // disk
+        put16(&mut self.data, 0); // disk with the directory
+        put16(&mut self.data, self.entries);
+        put16(&mut self.data, self.entries);
+        put32(&mut self.data, size);
+        put32(&mut self.data, offset);
+        put16(&mut self.data, 0); // comment
+        Ok(self.data)
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, byte| {
+        CRC_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn put16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// The files of an archive written by `ZipWriter`, read through its central
+/// directory and checked against their local headers and checksums.
+#[cfg(test)]
+pub fn read_entries(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let u16_at = |at: usize| u16::from_le_bytes([archive[at], archive[at + 1]]) as usize;
+    let u32_at = |at: usize| u32::from_le_bytes(archive[at..at + 4].try_into().unwrap()) as usize;
+
+    let end = archive.len() - 22;
+    assert_eq!(u32_at(end), 0x0605_4b50, "end of central directory");
+    let count = u16_at(end + 10);
+    let mut at = u32_at(end + 16);
+    assert_eq!(at + u32_at(end + 12), end, "central directory size");
+
+    let mut entries = vec![];
+    for _ in 0..count {
+        assert_eq!(u32_at(at), 0x0201_4b50, "central directory entry");
+        let (crc, size, name_len) = (u32_at(at + 16), u32_at(at + 24), u16_at(at + 28));
+        let local = u32_at(at + 42);
+        let name = String::from_utf8(archive[at + 46..at + 46 + name_len].to_vec()).unwrap();
+
+        assert_eq!(u32_at(local), 0x0403_4b50, "local header of {name}");
+        assert_eq!(u16_at(local + 8), 0, "{name} is stored");
+        assert_eq!(u16_at(local + 28), 0, "{name} has no extra field");
+        let start = local + 30 + u16_at(local + 26);
+        let content = archive[start..start + size].to_vec();
+        assert_eq!(crc32(&content) as usize, crc, "checksum of {name}");
+
+        entries.push((name, content));
+        at += 46 + name_len;
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    // ==================== Checksum Tests ====================
+
+    #[rstest]
+    #[case(b"", 0)]
+    #[case(b"a", 0xe8b7_be43)]
+    #[case(b"123456789", 0xcbf4_3926)]
+    #[case(b"application/epub+zip", 0x2cab_616f)]
+    fn test_crc32(#[case] data: &[u8], #[case] expected: u32) {
+        assert_eq!(crc32(data), expected);
+    }
+
+    // ==================== Archive Tests ====================
+
+    #[test]
+    fn test_archive_round_trip() {
+        let mut zip = ZipWriter::new();
+        zip.add("mimetype", b"application/epub+zip").unwrap();
+        zip.add("OEBPS/Kapitel ü.xhtml", b"<p>Hallo</p>").unwrap();
+        zip.add("empty", b"").unwrap();
+        let archive = zip.finish().unwrap();
+
+        // a reader finds the mimetype at a fixed offset
+        assert_eq!(&archive[30..38], b"mimetype");
+        assert_eq!(&archive[38..58], b"application/epub+zip");
+        assert_eq!(
+            read_entries(&archive),
+            vec![
+                ("mimetype".to_string(), b"application/epub+zip".to_vec()),
+                (
+                    "OEBPS/Kapitel ü.xhtml".to_string(),
+                    b"<p>Hallo</p>".to_vec()
+                ),
+                ("empty".to_string(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_archive() {
+        let archive = ZipWriter::new().finish().unwrap();
+        assert_eq!(archive.len(), 22);
+        assert!(read_entries(&archive).is_empty());
+    }
+}
@@ -2,20 +2,38 @@
 //!
 //! This module provides shared functionality for integration tests.
 
-use axum::{
-    Router,
-    routing::{get, post},
-};
-use std::path::PathBuf;
-use std::sync::Arc;
+use axum_test::TestServer;
+use glypho::{AccessToken, AppState, InnerState, Server};
+use std::{path::PathBuf, sync::Arc};
 use tempfile::TempDir;
-use tokio::sync::Mutex;
 
-/// Test server configuration
-pub struct TestServer {
+/// A served preview of one document
+#[allow(dead_code)]
+pub struct TestApp {
     pub temp_dir: TempDir,
     pub file_path: PathBuf,
-    pub app: Router,
+    pub state: Arc<AppState>,
+    pub server: TestServer,
+}
+
+/// Serve the preview of a temporary markdown file with the given content,
+/// behind `token` when there is one
+pub fn serve(content: &str, token: Option<&str>) -> TestApp {
+    let (temp_dir, file_path) = create_temp_file(content);
+    let server = Server::new(InnerState::new(file_path.clone()));
+    let state = server.state();
+    let server = match token {
+        Some(token) => server.with_token(AccessToken::new(token.to_string())),
+        None => server,
+    };
+    let server = TestServer::new(server.router()).expect("Failed to start test server");
+
+    TestApp {
+        temp_dir,
+        file_path,
+        state,
+        server,
+    }
 }
 
 /// Create a temporary markdown file with the given content
@@ -1,72 +1,33 @@
 //! Integration tests for the Glypho server
 //!
-//! These tests verify the full server functionality including HTTP handlers,
-//! file serving, and SSE streaming.
-
-use axum::{
-    Router,
-    body::Body,
-    http::{Request, StatusCode},
-    routing::{get, post},
-};
-use bytes::BytesMut;
-use std::{collections::BTreeMap, path::PathBuf, sync::Arc, time::Duration};
+//! These tests drive the real state, handlers and router of the library,
+//! the way the binary and embedding programs use them.
+
+use glypho::{InnerState, Output, Renderer, SignalEvents, add_file, remove_file, switch_file};
+use std::{sync::Arc, time::Duration};
 use tempfile::TempDir;
-use tokio::sync::{Mutex, broadcast};
-use tower::ServiceExt;
+use tokio::sync::{Mutex, broadcast::Receiver};
 
 use super::common;
 use common::fixtures;
 
-// ==================== Test App Setup ====================
-
-/// Simplified InnerState for testing (mirrors the real implementation)
-struct TestInnerState {
-    files: BTreeMap<PathBuf, BytesMut>,
-    active_file: PathBuf,
-    event_sender: broadcast::Sender<TestSignalEvent>,
-    watched_files: Vec<PathBuf>,
-}
-
-#[derive(Clone, Debug)]
-enum TestSignalEvent {
-    AddedNewFile,
-    UpdatedFile { updated_file: PathBuf, html: String },
-    ActiveFileChanged,
-}
-
-impl TestInnerState {
-    fn new(first_file: PathBuf) -> Self {
-        let mut files = BTreeMap::new();
-        let buffer = BytesMut::with_capacity(4096);
-        let (event_sender, _) = broadcast::channel(32);
-        files.insert(first_file.clone(), buffer);
-        TestInnerState {
-            files,
-            active_file: first_file,
-            event_sender,
-            watched_files: vec![],
-        }
-    }
-}
-
-type TestAppState = Mutex<TestInnerState>;
-
-fn create_test_state(file_path: PathBuf) -> Arc<TestAppState> {
-    Arc::new(Mutex::new(TestInnerState::new(file_path)))
+async fn next_event(receiver: &mut Receiver<SignalEvents>) -> SignalEvents {
+    tokio::time::timeout(Duration::from_secs(1), receiver.recv())
+        .await
+        .expect("Timeout waiting for event")
+        .expect("Failed to receive event")
 }
 
-// ==================== Server Integration Tests ====================
+// ==================== State Tests ====================
 
 #[tokio::test]
 async fn test_server_starts_with_valid_file() {
-    let (temp_dir, file_path) = common::create_temp_file(fixtures::SIMPLE_MARKDOWN);
-    let state = create_test_state(file_path.clone());
+    let app = common::serve(fixtures::SIMPLE_MARKDOWN, None);
 
-    // Verify state was initialized correctly
-    let guard = state.lock().await;
-    assert_eq!(guard.active_file, file_path);
-    assert!(guard.files.contains_key(&file_path));
+    let guard = app.state.lock().await;
+    assert_eq!(guard.active_file(), app.file_path);
+    assert!(guard.is_tracked(&app.file_path));
+    assert_eq!(guard.files().count(), 1);
 }
 
 #[tokio::test]
@@ -75,27 +36,25 @@ async fn test_multiple_files_can_be_added() {
     let file1 = common::create_named_temp_file(&temp_dir, "file1.md", "# File 1");
     let file2 = common::create_named_temp_file(&temp_dir, "file2.md", "# File 2");
     let file3 = common::create_named_temp_file(&temp_dir, "file3.md", "# File 3");
-
-    let state = create_test_state(file1.clone());
-
-    // Add more files
-    {
-        let mut guard = state.lock().await;
-        guard
-            .files
-            .insert(file2.clone(), BytesMut::with_capacity(4096));
-        guard
-            .files
-            .insert(file3.clone(), BytesMut::with_capacity(4096));
-        guard.watched_files.push(file1.clone());
-        guard.watched_files.push(file2.clone());
-        guard.watched_files.push(file3.clone());
-    }
-
-    // Verify all files are tracked
+    let state = Arc::new(Mutex::new(InnerState::new(file1.clone())));
+    let mut events = state.lock().await.subscribe();
+
+    add_file(state.clone(), file2.clone()).await;
+    add_file(state.clone(), file3.clone()).await;
+    // adding a file twice keeps one
+    add_file(state.clone(), file2.clone()).await;
+
+    assert!(matches!(
+        next_event(&mut events).await,
+        SignalEvents::AddedNewFile
+    ));
     let guard = state.lock().await;
-    assert_eq!(guard.files.len(), 3);
-    assert_eq!(guard.watched_files.len(), 3);
+    assert_eq!(
+        guard.files().collect::<Vec<_>>(),
+        vec![&file1, &file2, &file3]
+    );
+    // the first file stays active
+    assert_eq!(guard.active_file(), file1);
 }
 
 #[tokio::test]
@@ -103,245 +62,279 @@ async fn test_active_file_can_be_changed() {
     let temp_dir = TempDir::new().unwrap();
     let file1 = common::create_named_temp_file(&temp_dir, "file1.md", "# File 1");
     let file2 = common::create_named_temp_file(&temp_dir, "file2.md", "# File 2");
+    let state = Arc::new(Mutex::new(InnerState::new(file1.clone())));
+    add_file(state.clone(), file2.clone()).await;
+    let mut events = state.lock().await.subscribe();
 
-    let state = create_test_state(file1.clone());
+    assert!(switch_file(state.clone(), file2.clone()).await);
 
-    // Add second file and change active
-    {
-        let mut guard = state.lock().await;
-        guard
-            .files
-            .insert(file2.clone(), BytesMut::with_capacity(4096));
-        guard.active_file = file2.clone();
-    }
-
-    // Verify active file changed
-    let guard = state.lock().await;
-    assert_eq!(guard.active_file, file2);
+    assert!(matches!(
+        next_event(&mut events).await,
+        SignalEvents::ActiveFileChanged
+    ));
+    assert_eq!(state.lock().await.active_file(), file2);
 }
 
 #[tokio::test]
-async fn test_event_broadcasting() {
-    let (_, file_path) = common::create_temp_file(fixtures::SIMPLE_MARKDOWN);
-    let state = create_test_state(file_path.clone());
-
-    // Subscribe to events
-    let mut receiver = {
-        let guard = state.lock().await;
-        guard.event_sender.subscribe()
-    };
-
-    // Send event
-    {
-        let guard = state.lock().await;
-        let _ = guard.event_sender.send(TestSignalEvent::AddedNewFile);
-    }
-
-    // Verify event received
-    let event = tokio::time::timeout(Duration::from_secs(1), receiver.recv())
-        .await
-        .expect("Timeout waiting for event")
-        .expect("Failed to receive event");
+async fn test_untracked_file_cannot_be_active() {
+    let temp_dir = TempDir::new().unwrap();
+    let file1 = common::create_named_temp_file(&temp_dir, "file1.md", "# File 1");
+    let other = common::create_named_temp_file(&temp_dir, "other.md", "# Other");
+    let state = Arc::new(Mutex::new(InnerState::new(file1.clone())));
 
-    assert!(matches!(event, TestSignalEvent::AddedNewFile));
+    assert!(!switch_file(state.clone(), other).await);
+    assert_eq!(state.lock().await.active_file(), file1);
 }
 
 #[tokio::test]
-async fn test_event_broadcasting_updated_file() {
-    let (_, file_path) = common::create_temp_file(fixtures::SIMPLE_MARKDOWN);
-    let state = create_test_state(file_path.clone());
-
-    let mut receiver = {
-        let guard = state.lock().await;
-        guard.event_sender.subscribe()
-    };
-
-    // Send UpdatedFile event
-    {
-        let guard = state.lock().await;
-        let _ = guard.event_sender.send(TestSignalEvent::UpdatedFile {
-            updated_file: file_path.clone(),
-            html: "<p>Updated HTML</p>".to_string(),
-        });
-    }
-
-    let event = tokio::time::timeout(Duration::from_secs(1), receiver.recv())
-        .await
-        .expect("Timeout")
-        .expect("Failed to receive");
-
-    if let TestSignalEvent::UpdatedFile { updated_file, html } = event {
-        assert_eq!(updated_file, file_path);
-        assert_eq!(html, "<p>Updated HTML</p>");
-    } else {
-        panic!("Expected UpdatedFile event");
-    }
+async fn test_removing_active_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let file1 = common::create_named_temp_file(&temp_dir, "file1.md", "# File 1");
+    let file2 = common::create_named_temp_file(&temp_dir, "file2.md", "# File 2");
+    let state = Arc::new(Mutex::new(InnerState::new(file1.clone())));
+    add_file(state.clone(), file2.clone()).await;
+
+    assert!(remove_file(state.clone(), file1.clone()).await);
+    assert_eq!(state.lock().await.active_file(), file2);
+    // the last file of a session stays
+    assert!(!remove_file(state.clone(), file2.clone()).await);
+    assert!(state.lock().await.is_tracked(&file2));
 }
 
 #[tokio::test]
 async fn test_concurrent_state_access() {
-    let (_, file_path) = common::create_temp_file(fixtures::SIMPLE_MARKDOWN);
-    let state = create_test_state(file_path.clone());
-
-    let state1 = state.clone();
-    let state2 = state.clone();
-    let state3 = state.clone();
-
-    let file_path1 = file_path.clone();
-    let file_path2 = file_path.clone();
-    let file_path3 = file_path.clone();
-
-    let handle1 = tokio::spawn(async move {
-        for _ in 0..100 {
-            let guard = state1.lock().await;
-            assert_eq!(guard.active_file, file_path1);
-        }
-    });
-
-    let handle2 = tokio::spawn(async move {
-        for _ in 0..100 {
-            let guard = state2.lock().await;
-            assert!(guard.files.contains_key(&file_path2));
-        }
-    });
-
-    let handle3 = tokio::spawn(async move {
-        for _ in 0..100 {
-            let guard = state3.lock().await;
-            let _ = guard.event_sender.send(TestSignalEvent::AddedNewFile);
-        }
-    });
-
-    handle1.await.unwrap();
-    handle2.await.unwrap();
-    handle3.await.unwrap();
+    let app = common::serve(fixtures::SIMPLE_MARKDOWN, None);
+
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            let state = app.state.clone();
+            let file = app.file_path.clone();
+            tokio::spawn(async move {
+                for _ in 0..100 {
+                    let mut guard = state.lock().await;
+                    assert_eq!(guard.active_file(), file);
+                    guard.render(&file).unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.await.unwrap();
+    }
 }
 
-// ==================== File Content Tests ====================
+// ==================== Rendering Tests ====================
 
 #[tokio::test]
 async fn test_file_content_with_various_markdown() {
     let test_cases = vec![
-        ("simple", fixtures::SIMPLE_MARKDOWN),
-        ("code", fixtures::MARKDOWN_WITH_CODE),
-        ("wikilinks", fixtures::MARKDOWN_WITH_WIKILINKS),
-        ("math", fixtures::MARKDOWN_WITH_MATH),
-        ("table", fixtures::MARKDOWN_WITH_TABLE),
-        ("task_list", fixtures::MARKDOWN_WITH_TASK_LIST),
-        ("frontmatter", fixtures::MARKDOWN_WITH_FRONTMATTER),
-        ("complex", fixtures::MARKDOWN_COMPLEX),
+        ("simple", fixtures::SIMPLE_MARKDOWN, "Hello World</h1>"),
+        (
+            "code",
+            fixtures::MARKDOWN_WITH_CODE,
+            "<code class=\"language-rust\">",
+        ),
+        (
+            "wikilinks",
+            fixtures::MARKDOWN_WITH_WIKILINKS,
+            ">Custom Label</a>",
+        ),
+        ("math", fixtures::MARKDOWN_WITH_MATH, "Math Document</h1>"),
+        ("table", fixtures::MARKDOWN_WITH_TABLE, "<table>"),
+        (
+            "task_list",
+            fixtures::MARKDOWN_WITH_TASK_LIST,
+            "type=\"checkbox\"",
+        ),
+        (
+            "frontmatter",
+            fixtures::MARKDOWN_WITH_FRONTMATTER,
+            "Test Author",
+        ),
+        (
+            "complex",
+            fixtures::MARKDOWN_COMPLEX,
+            "<del>strikethrough</del>",
+        ),
     ];
 
-    for (name, content) in test_cases {
-        let (_, file_path) = common::create_temp_file(content);
-        let state = create_test_state(file_path.clone());
+    for (name, content, expected) in test_cases {
+        let app = common::serve(content, None);
+        let html = app.state.lock().await.render(&app.file_path).unwrap();
+        assert!(html.contains(expected), "Failed for test case: {name}");
 
-        let guard = state.lock().await;
+        // the print page renders the same document
+        let response = app.server.get("/print").await;
+        response.assert_status_ok();
         assert!(
-            guard.files.contains_key(&file_path),
-            "Failed for test case: {}",
-            name
+            response.text().contains(expected),
+            "Failed for test case: {name}"
         );
     }
 }
 
 #[tokio::test]
 async fn test_empty_file_handling() {
-    let (_, file_path) = common::create_temp_file(fixtures::EMPTY_MARKDOWN);
-    let state = create_test_state(file_path.clone());
+    for content in [fixtures::EMPTY_MARKDOWN, fixtures::WHITESPACE_ONLY_MARKDOWN] {
+        let app = common::serve(content, None);
+        let html = app.state.lock().await.render(&app.file_path).unwrap();
+        assert!(html.trim().is_empty());
+    }
+}
 
-    let guard = state.lock().await;
-    assert!(guard.files.contains_key(&file_path));
+#[test]
+fn test_renderer_matches_preview_flavor() {
+    let (_temp_dir, file_path) = common::create_temp_file(fixtures::MARKDOWN_COMPLEX);
+
+    let html = Renderer::new().render_file(&file_path).unwrap();
+    assert!(html.contains("<a href=\"InternalPage\""));
+    assert!(html.contains("<table>"));
+    // the document alone, as written
+    assert!(!html.contains("lint-gutter"));
+    assert!(!html.contains("/files/"));
+
+    let html = Renderer::new()
+        .with_output(Output::Preview)
+        .render_file(&file_path)
+        .unwrap();
+    assert!(html.contains("@post('/task')"));
 }
 
-#[tokio::test]
-async fn test_whitespace_only_file_handling() {
-    let (_, file_path) = common::create_temp_file(fixtures::WHITESPACE_ONLY_MARKDOWN);
-    let state = create_test_state(file_path.clone());
+// ==================== HTTP Tests ====================
 
-    let guard = state.lock().await;
-    assert!(guard.files.contains_key(&file_path));
+#[tokio::test]
+async fn test_preview_page() {
+    let app = common::serve(fixtures::MARKDOWN_WITH_FRONTMATTER, None);
+
+    let response = app.server.get("/").await;
+    response.assert_status_ok();
+    let page = response.text();
+    assert!(page.contains("<title>Test Document</title>"));
+    assert!(page.contains("@get('/sse')"));
 }
 
-// ==================== Buffer Management Tests ====================
-
 #[tokio::test]
-async fn test_buffer_capacity() {
-    let (_, file_path) = common::create_temp_file(fixtures::SIMPLE_MARKDOWN);
-    let state = create_test_state(file_path.clone());
+async fn test_stats_of_active_document() {
+    let app = common::serve(fixtures::SIMPLE_MARKDOWN, None);
+
+    let response = app.server.get("/stats").await;
+    response.assert_status_ok();
+    let stats: serde_json::Value = response.json();
+    assert_eq!(stats["file"], app.file_path.to_string_lossy().as_ref());
+    assert_eq!(stats["headings"], 3);
+    assert_eq!(stats["outline"][0]["text"], "Hello World");
+}
 
-    let guard = state.lock().await;
-    let buffer = guard.files.get(&file_path).unwrap();
-    assert_eq!(buffer.capacity(), 4096);
+#[tokio::test]
+async fn test_switching_files_over_http() {
+    let app = common::serve(fixtures::SIMPLE_MARKDOWN, None);
+    let other = common::create_named_temp_file(&app.temp_dir, "other.md", "# Other");
+    add_file(app.state.clone(), other.clone()).await;
+
+    let signals = serde_json::json!({ "file": other, "first": false });
+    let response = app.server.post("/update").json(&signals).await;
+    response.assert_status_ok();
+    assert_eq!(response.json::<serde_json::Value>()["ok"], true);
+    assert_eq!(app.state.lock().await.active_file(), other);
 }
 
 #[tokio::test]
-async fn test_buffer_update() {
-    let (_, file_path) = common::create_temp_file(fixtures::SIMPLE_MARKDOWN);
-    let state = create_test_state(file_path.clone());
-
-    // Update buffer
-    {
-        let mut guard = state.lock().await;
-        let buffer = guard.files.get_mut(&file_path).unwrap();
-        buffer.clear();
-        buffer.extend_from_slice(b"<p>New content</p>");
-    }
+async fn test_slides_toggle() {
+    let app = common::serve(fixtures::SIMPLE_MARKDOWN, None);
 
-    // Verify update
-    let guard = state.lock().await;
-    let buffer = guard.files.get(&file_path).unwrap();
-    assert_eq!(buffer.as_ref(), b"<p>New content</p>");
+    app.server.post("/slides/toggle").await.assert_status_ok();
+    assert!(app.state.lock().await.slides());
 }
 
-// ==================== Watched Files Tests ====================
-
 #[tokio::test]
-async fn test_watched_files_initially_empty() {
-    let (_, file_path) = common::create_temp_file(fixtures::SIMPLE_MARKDOWN);
-    let state = create_test_state(file_path);
-
-    let guard = state.lock().await;
-    assert!(guard.watched_files.is_empty());
+async fn test_document_assets() {
+    let app = common::serve("![Logo](logo.png)\n", None);
+    std::fs::write(app.temp_dir.path().join("logo.png"), b"\x89PNG").unwrap();
+    std::fs::write(app.temp_dir.path().join(".env"), "SECRET=1").unwrap();
+    // the preview links the image through the assets of its document
+    let html = app.state.lock().await.render(&app.file_path).unwrap();
+    let logo = html
+        .split('"')
+        .find(|part| part.ends_with("/logo.png"))
+        .unwrap()
+        .to_string();
+    let doc = logo.trim_end_matches("/logo.png");
+
+    let response = app.server.get(&logo).await;
+    response.assert_status_ok();
+    assert_eq!(response.as_bytes().as_ref(), b"\x89PNG");
+
+    app.server
+        .get(&format!("{doc}/.env"))
+        .await
+        .assert_status_not_found();
+    app.server
+        .get("/files/0000000000000000/logo.png")
+        .await
+        .assert_status_not_found();
 }
 
 #[tokio::test]
-async fn test_watched_files_can_be_added() {
-    let temp_dir = TempDir::new().unwrap();
-    let file1 = common::create_named_temp_file(&temp_dir, "file1.md", "# File 1");
-    let file2 = common::create_named_temp_file(&temp_dir, "file2.md", "# File 2");
+async fn test_access_token() {
+    let app = common::serve(fixtures::SIMPLE_MARKDOWN, Some("secret"));
 
-    let state = create_test_state(file1.clone());
+    app.server.get("/").await.assert_status_unauthorized();
+    app.server
+        .get("/")
+        .add_query_param("token", "wrong")
+        .await
+        .assert_status_unauthorized();
+
+    let response = app.server.get("/").add_query_param("token", "secret").await;
+    response.assert_status_ok();
+    assert!(
+        response
+            .header("set-cookie")
+            .to_str()
+            .unwrap()
+            .contains("HttpOnly")
+    );
+}
 
-    {
-        let mut guard = state.lock().await;
-        guard.watched_files.push(file1.clone());
-        guard.watched_files.push(file2.clone());
-    }
+#[tokio::test]
+async fn test_rebound_name_refused() {
+    let app = common::serve(fixtures::SIMPLE_MARKDOWN, None);
+    let other = common::create_named_temp_file(&app.temp_dir, "secret.md", "# Secret");
+
+    let signals = serde_json::json!({ "file": other, "first": false });
+    app.server
+        .post("/open")
+        .add_header("host", "evil.example")
+        .json(&signals)
+        .await
+        .assert_status_forbidden();
+    app.server
+        .get("/print")
+        .add_header("host", "evil.example")
+        .await
+        .assert_status_forbidden();
+    assert_eq!(app.state.lock().await.active_file(), app.file_path);
 
-    let guard = state.lock().await;
-    assert_eq!(guard.watched_files.len(), 2);
-    assert!(guard.watched_files.contains(&file1));
-    assert!(guard.watched_files.contains(&file2));
+    // the preview is still reached by its address
+    app.server
+        .get("/print")
+        .add_header("host", "127.0.0.1:4000")
+        .await
+        .assert_status_ok();
 }
 
 #[tokio::test]
-async fn test_watched_files_no_duplicates_check() {
-    let (_, file_path) = common::create_temp_file(fixtures::SIMPLE_MARKDOWN);
-    let state = create_test_state(file_path.clone());
-
-    {
-        let mut guard = state.lock().await;
-        // Simulate checking if file is already watched (as in watch_file)
-        if !guard.watched_files.contains(&file_path) {
-            guard.watched_files.push(file_path.clone());
-        }
-        // Try to add again - should not add duplicate
-        if !guard.watched_files.contains(&file_path) {
-            guard.watched_files.push(file_path.clone());
-        }
-    }
-
-    let guard = state.lock().await;
-    assert_eq!(guard.watched_files.len(), 1);
+async fn test_session_not_changed_by_get() {
+    let app = common::serve(fixtures::SIMPLE_MARKDOWN, None);
+    let other = common::create_named_temp_file(&app.temp_dir, "other.md", "# Other");
+    add_file(app.state.clone(), other.clone()).await;
+
+    let signals = serde_json::json!({ "file": other, "first": false }).to_string();
+    app.server
+        .get("/update")
+        .add_query_param("datastar", signals)
+        .await
+        .assert_status(axum::http::StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(app.state.lock().await.active_file(), app.file_path);
 }